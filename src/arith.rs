@@ -0,0 +1,505 @@
+use crate::variables::{ is_valid_name, Variables };
+use std::fmt::{ self, Display, Formatter };
+
+// a variable's value is itself evaluated as an expression, which could otherwise refer back to itself forever.
+const MAX_DEPTH: usize = 64;
+
+// operators longer than one character, longest first so that e.g. "<<=" wins over "<<".
+const OPERATORS: [&str; 21] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=",
+];
+
+// the assignment operators, along with the binary operator each one applies.
+const ASSIGNMENTS: [(&str, &str); 11] = [
+    ("=", ""), ("*=", "*"), ("/=", "/"), ("%=", "%"), ("+=", "+"), ("-=", "-"),
+    ("<<=", "<<"), (">>=", ">>"), ("&=", "&"), ("^=", "^"), ("|=", "|"),
+];
+
+// a malformed expression or one that can't be evaluated, like a division by zero.
+#[derive(Debug, PartialEq)]
+pub struct ArithError {
+    pub message: String,
+}
+
+impl ArithError {
+    pub fn new(message: &str) -> ArithError {
+        ArithError { message: message.to_string() }
+    }
+}
+
+impl Display for ArithError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithToken {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl Display for ArithToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithToken::Number(n) => write!(f, "{}", n),
+            ArithToken::Name(name) => write!(f, "{}", name),
+            ArithToken::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/**
+* A parsed expression. Evaluation happens on the tree rather than while parsing so that the side of
+* "&&", "||" or "?:" that isn't taken never runs its assignments.
+*/
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Number(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    // the operator is empty for a plain "=".
+    Assign(String, &'static str, Box<Expr>),
+    // ++x, --x, x++ and x--
+    Step { name: String, delta: i64, post: bool },
+}
+
+/**
+* Evaluates an arithmetic expression the way $((...)) and ((...)) do: 64 bit integers with the C operators
+* and precedence. Names refer to shell variables, which are updated by the assignment operators.
+*/
+pub fn evaluate(text: &str, vars: &mut Variables) -> Result<i64, ArithError> {
+    evaluate_at(text, vars, 0).map_err(|err| ArithError { message: format!("{}: {}", text.trim(), err.message) })
+}
+
+fn evaluate_at(text: &str, vars: &mut Variables, depth: usize) -> Result<i64, ArithError> {
+    let tokens = tokenize(text)?;
+    // an empty expression is 0, so $(( )) and $(($unset)) both work.
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = ArithParser { tokens, pos: 0 };
+    let expr = parser.parse_comma()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(ArithError { message: format!("syntax error in expression (error token is \"{}\")", token) });
+    }
+
+    Evaluator { vars, depth }.eval(&expr)
+}
+
+fn tokenize(text: &str) -> Result<Vec<ArithToken>, ArithError> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    loop {
+        rest = rest.trim_start();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        let end = if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '#' | '@' | '_'))).unwrap_or(rest.len());
+            tokens.push(ArithToken::Number(parse_number(&rest[..end])?));
+            end
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(ArithToken::Name(rest[..end].to_string()));
+            end
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(ArithToken::Op(op));
+            op.len()
+        } else if let Some(op) = ["+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^", "|", "?", ":", ",", "(", ")"]
+            .into_iter()
+            .find(|op| rest.starts_with(op))
+        {
+            tokens.push(ArithToken::Op(op));
+            1
+        } else {
+            return Err(ArithError { message: format!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest) });
+        };
+
+        rest = &rest[end..];
+    }
+
+    Ok(tokens)
+}
+
+/**
+* Numbers are decimal, octal with a leading 0, hex with a leading 0x, or "base#digits" for any base
+* from 2 to 64. Bases up to 36 ignore the case of letters.
+*/
+fn parse_number(text: &str) -> Result<i64, ArithError> {
+    let invalid = || ArithError { message: format!("invalid number (error token is \"{}\")", text) };
+
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base @ 2..=64) => (base, digits),
+            _ => return Err(ArithError { message: format!("invalid arithmetic base (error token is \"{}\")", text) }),
+        }
+    } else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, digits)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(ArithError { message: format!("value too great for base (error token is \"{}\")", text) });
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+
+    Ok(value)
+}
+
+// how tightly each binary operator binds, higher binds tighter.
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "|" => Some(3),
+        "^" => Some(4),
+        "&" => Some(5),
+        "==" | "!=" => Some(6),
+        "<" | ">" | "<=" | ">=" => Some(7),
+        "<<" | ">>" => Some(8),
+        "+" | "-" => Some(9),
+        "*" | "/" | "%" => Some(10),
+        "**" => Some(11),
+        _ => None,
+    }
+}
+
+struct ArithParser {
+    tokens: Vec<ArithToken>,
+    pos: usize,
+}
+
+impl ArithParser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(ArithToken::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str, message: &str) -> Result<(), ArithError> {
+        if self.peek_op() != Some(op) {
+            return Err(ArithError::new(message));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, ArithError> {
+        let mut expr = self.parse_assign()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.parse_assign()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assign(&mut self) -> Result<Expr, ArithError> {
+        if let (Some(ArithToken::Name(name)), Some(ArithToken::Op(op))) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            if let Some((_, binary)) = ASSIGNMENTS.iter().find(|(assign, _)| assign == op) {
+                let name = name.clone();
+                self.pos += 2;
+                let value = self.parse_assign()?;
+                return Ok(Expr::Assign(name, binary, Box::new(value)));
+            }
+        }
+
+        let expr = self.parse_conditional()?;
+        // anything else on the left of an assignment, e.g. "1 = 2" or "(x) += 1".
+        if let Some(op) = self.peek_op() {
+            if ASSIGNMENTS.iter().any(|(assign, _)| *assign == op) {
+                return Err(ArithError::new("attempted assignment to non-variable"));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr, ArithError> {
+        let cond = self.parse_binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+
+        self.pos += 1;
+        let then = self.parse_comma()?;
+        self.expect(":", "`:' expected for conditional expression")?;
+        let otherwise = self.parse_assign()?;
+        Ok(Expr::Conditional(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    // precedence climbing over the binary operators. "**" is the only one that groups to the right.
+    fn parse_binary(&mut self, min: u8) -> Result<Expr, ArithError> {
+        let mut left = self.parse_unary()?;
+
+        loop {
+            let op = match self.peek_op() {
+                // "a--b" can only mean "a - -b" here, since a value has just been read.
+                Some(op @ ("++" | "--")) => {
+                    let sign = &op[..1];
+                    self.tokens[self.pos] = ArithToken::Op(sign);
+                    self.tokens.insert(self.pos + 1, ArithToken::Op(sign));
+                    sign
+                },
+                Some(op) => op,
+                None => break,
+            };
+            let prec = match precedence(op) {
+                Some(prec) if prec >= min => prec,
+                _ => break,
+            };
+
+            self.pos += 1;
+            let right = if op == "**" { self.parse_binary(prec)? } else { self.parse_binary(prec + 1)? };
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ArithError> {
+        match self.peek_op() {
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            },
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                if let Some(ArithToken::Name(name)) = self.tokens.get(self.pos) {
+                    let name = name.clone();
+                    self.pos += 1;
+                    return Ok(Expr::Step { name, delta: if op == "++" { 1 } else { -1 }, post: false });
+                }
+                // without a variable it's just two signs, e.g. "--5".
+                let sign = &op[..1];
+                Ok(Expr::Unary(sign, Box::new(Expr::Unary(sign, Box::new(self.parse_unary()?)))))
+            },
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, ArithError> {
+        let expr = self.parse_primary()?;
+        if let (Expr::Var(name), Some(op @ ("++" | "--"))) = (&expr, self.peek_op()) {
+            let step = Expr::Step { name: name.clone(), delta: if op == "++" { 1 } else { -1 }, post: true };
+            self.pos += 1;
+            return Ok(step);
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ArithError> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(ArithToken::Number(n)) => {
+                self.pos += 1;
+                Ok(Expr::Number(n))
+            },
+            Some(ArithToken::Name(name)) => {
+                self.pos += 1;
+                Ok(Expr::Var(name))
+            },
+            Some(ArithToken::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                self.expect(")", "missing `)'")?;
+                Ok(expr)
+            },
+            Some(token) => Err(ArithError { message: format!("syntax error: operand expected (error token is \"{}\")", token) }),
+            None => Err(ArithError::new("syntax error: operand expected")),
+        }
+    }
+}
+
+struct Evaluator<'v> {
+    vars: &'v mut Variables,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ArithError> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            Expr::Var(name) => self.get(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            },
+            Expr::Binary("&&", left, right) => Ok((self.eval(left)? != 0 && self.eval(right)? != 0) as i64),
+            Expr::Binary("||", left, right) => Ok((self.eval(left)? != 0 || self.eval(right)? != 0) as i64),
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                apply(op, left, right)
+            },
+            Expr::Conditional(cond, then, otherwise) => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            },
+            Expr::Assign(name, op, value) => {
+                let mut value = self.eval(value)?;
+                if !op.is_empty() {
+                    value = apply(op, self.get(name)?, value)?;
+                }
+                self.set(name, value)
+            },
+            Expr::Step { name, delta, post } => {
+                let old = self.get(name)?;
+                let new = self.set(name, old.wrapping_add(*delta))?;
+                Ok(if *post { old } else { new })
+            },
+        }
+    }
+
+    // unset and empty variables are 0, anything else is evaluated as an expression of its own.
+    fn get(&mut self, name: &str) -> Result<i64, ArithError> {
+        let value = self.vars.get(name).unwrap_or_default().trim().to_string();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse::<i64>() {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(ArithError::new("expression recursion level exceeded"));
+        }
+        evaluate_at(&value, self.vars, self.depth + 1)
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> Result<i64, ArithError> {
+        if !is_valid_name(name) {
+            return Err(ArithError::new("attempted assignment to non-variable"));
+        }
+        self.vars.set(name, &value.to_string());
+        Ok(value)
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, ArithError> {
+    Ok(match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err(ArithError::new("division by 0")),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err(ArithError::new("exponent less than 0")),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "&" => left & right,
+        "^" => left ^ right,
+        "|" => left | right,
+        "," => right,
+        _ => return Err(ArithError { message: format!("{}: unknown operator", op) }),
+    })
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    fn eval(text: &str) -> Result<i64, ArithError> {
+        evaluate(text, &mut Variables::new())
+    }
+
+    #[test]
+    fn test_arith_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("7 / 2 + 7 % 2 - 1 << 2"), Ok(12));
+        assert_eq!(eval("1 < 2 == 2 > 1"), Ok(1));
+        assert_eq!(eval("6 & 3 | 8 ^ 1"), Ok(11));
+        assert_eq!(eval("!0 + ~0 + 5--2"), Ok(7));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn test_arith_numbers() {
+        assert_eq!(eval("0x1f + 010 + 2#101 + 64#_"), Ok(31 + 8 + 5 + 63));
+        assert_eq!(eval("08"), Err(ArithError::new("08: value too great for base (error token is \"08\")")));
+        assert_eq!(eval("1x"), Err(ArithError::new("1x: value too great for base (error token is \"1x\")")));
+        assert_eq!(eval("10#"), Err(ArithError::new("10#: invalid number (error token is \"10#\")")));
+        assert_eq!(eval("65#1"), Err(ArithError::new("65#1: invalid arithmetic base (error token is \"65#1\")")));
+    }
+
+    #[test]
+    fn test_arith_variables() {
+        let mut vars = Variables::new();
+        vars.set("x", "5");
+        vars.set("expr", "x * 2");
+        assert_eq!(evaluate("x + expr + unset", &mut vars), Ok(15));
+        assert_eq!(evaluate("y = x++ + ++x, y", &mut vars), Ok(12));
+        assert_eq!(vars.get("x"), Some("7"));
+        assert_eq!(evaluate("x += 3, x <<= 1, x--", &mut vars), Ok(20));
+        assert_eq!(vars.get("x"), Some("19"));
+
+        vars.set("loop", "loop + 1");
+        assert_eq!(evaluate("loop", &mut vars), Err(ArithError::new("loop: expression recursion level exceeded")));
+    }
+
+    #[test]
+    fn test_arith_short_circuit() {
+        let mut vars = Variables::new();
+        assert_eq!(evaluate("0 && (a = 1), 1 || (b = 1), 1 ? c = 2 : (d = 3)", &mut vars), Ok(2));
+        assert_eq!(vars.get("a"), None);
+        assert_eq!(vars.get("b"), None);
+        assert_eq!(vars.get("c"), Some("2"));
+        assert_eq!(vars.get("d"), None);
+        assert_eq!(evaluate("0 ? 1 : 0 ? 2 : 3", &mut vars), Ok(3));
+    }
+
+    #[test]
+    fn test_arith_errors() {
+        assert_eq!(eval("1 / 0"), Err(ArithError::new("1 / 0: division by 0")));
+        assert_eq!(eval("2 ** -1"), Err(ArithError::new("2 ** -1: exponent less than 0")));
+        assert_eq!(eval("1 +"), Err(ArithError::new("1 +: syntax error: operand expected")));
+        assert_eq!(eval("(1"), Err(ArithError::new("(1: missing `)'")));
+        assert_eq!(eval("1 ? 2"), Err(ArithError::new("1 ? 2: `:' expected for conditional expression")));
+        assert_eq!(eval("1 = 2"), Err(ArithError::new("1 = 2: attempted assignment to non-variable")));
+        assert_eq!(eval("1 2"), Err(ArithError::new("1 2: syntax error in expression (error token is \"2\")")));
+        assert_eq!(eval("1 $ 2"), Err(ArithError::new("1 $ 2: syntax error: invalid arithmetic operator (error token is \"$ 2\")")));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::parser::{ Word, WordPart };
+
+/*
+Brace expansion, the first expansion a word goes through: "a{b,c}d" becomes "abd acd" and "{1..3}" becomes
+"1 2 3". Only unquoted braces and commas count, so "{a,b}" in quotes or $x holding "{a,b}" stay as they are,
+though an expansion can still be one of the alternatives, as in "{$x,default}".
+*/
+
+// a word broken down for brace expansion: its unquoted text a character at a time, and everything else whole.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Item<'w> {
+    Char(char),
+    Part(&'w WordPart),
+}
+
+// expands the braces in a word into the words they stand for, which is just the word itself if it has none.
+pub fn expand(word: &Word) -> Vec<Word> {
+    let has_brace = word.parts.iter().any(|part| matches!(part, WordPart::Literal(text) if text.contains('{')));
+    if !has_brace {
+        return vec![word.clone()];
+    }
+
+    let mut items = Vec::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => items.extend(text.chars().map(Item::Char)),
+            part => items.push(Item::Part(part)),
+        }
+    }
+
+    expand_items(&items).into_iter().map(|items| to_word(&items)).collect()
+}
+
+/**
+* Expands the first brace expression in "items", then whatever follows it. A "{" that doesn't start a valid
+* expression (no matching "}", or neither a comma nor a sequence inside) is skipped over as plain text.
+*/
+fn expand_items<'w>(items: &[Item<'w>]) -> Vec<Vec<Item<'w>>> {
+    for (open, item) in items.iter().enumerate() {
+        if *item != Item::Char('{') {
+            continue;
+        }
+
+        let close = match matching_brace(items, open) {
+            Some(close) => close,
+            None => continue,
+        };
+
+        let body = &items[open + 1..close];
+        let commas = top_level_commas(body);
+        let alternatives: Vec<Vec<Item>> = if !commas.is_empty() {
+            let mut alternatives = Vec::new();
+            let mut start = 0;
+            for end in commas.into_iter().chain(std::iter::once(body.len())) {
+                alternatives.extend(expand_items(&body[start..end]));
+                start = end + 1;
+            }
+            alternatives
+        } else if let Some(sequence) = sequence(body) {
+            sequence.into_iter().map(|text| text.chars().map(Item::Char).collect()).collect()
+        } else {
+            continue;
+        };
+
+        let preamble = &items[..open];
+        let rest = expand_items(&items[close + 1..]);
+        let mut words = Vec::new();
+        for alternative in &alternatives {
+            for tail in &rest {
+                let mut word = preamble.to_vec();
+                word.extend_from_slice(alternative);
+                word.extend_from_slice(tail);
+                words.push(word);
+            }
+        }
+        return words;
+    }
+
+    vec![items.to_vec()]
+}
+
+// the index of the "}" that closes the "{" at "open".
+fn matching_brace(items: &[Item], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, item) in items.iter().enumerate().skip(open) {
+        match item {
+            Item::Char('{') => depth += 1,
+            Item::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+// the commas that separate alternatives, i.e. the ones not inside a nested brace expression.
+fn top_level_commas(items: &[Item]) -> Vec<usize> {
+    let mut commas = Vec::new();
+    let mut depth = 0;
+    for (idx, item) in items.iter().enumerate() {
+        match item {
+            Item::Char('{') => depth += 1,
+            Item::Char('}') if depth > 0 => depth -= 1,
+            Item::Char(',') if depth == 0 => commas.push(idx),
+            _ => {},
+        }
+    }
+    commas
+}
+
+/**
+* The values of a sequence expression, "x..y" or "x..y..step", where x and y are both integers or both single
+* letters. Integers written with leading zeros are padded to the same width, so "{01..10}" gives 01 to 10.
+*/
+fn sequence(items: &[Item]) -> Option<Vec<String>> {
+    let text = items
+        .iter()
+        .map(|item| match item {
+            Item::Char(c) => Some(*c),
+            Item::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+
+    let bounds: Vec<&str> = text.split("..").collect();
+    let (start, end, step) = match bounds.as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (*start, *end, step.parse::<i64>().ok()?.checked_abs()?),
+        _ => return None,
+    };
+    // a step of zero is treated as one, like bash does.
+    let step = step.max(1);
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |bound: &str| bound.trim_start_matches('-').len() > 1 && bound.trim_start_matches('-').starts_with('0');
+        let width = if padded(start) || padded(end) { start.len().max(end.len()) } else { 0 };
+        return Some(steps(first, last, step).map(|n| format!("{:0width$}", n, width = width)).collect());
+    }
+
+    let mut start_chars = start.chars();
+    let mut end_chars = end.chars();
+    match (start_chars.next(), start_chars.next(), end_chars.next(), end_chars.next()) {
+        (Some(first), None, Some(last), None) if first.is_ascii_alphabetic() && last.is_ascii_alphabetic() => Some(
+            steps(first as i64, last as i64, step)
+                .filter_map(|n| char::from_u32(n as u32))
+                .map(|c| c.to_string())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+// counts from first to last in either direction, including last if the steps land on it.
+fn steps(first: i64, last: i64, step: i64) -> impl Iterator<Item = i64> {
+    let count = (first.abs_diff(last) / step as u64) as i64;
+    let step = if first <= last { step } else { -step };
+    (0..=count).map(move |n| first + n * step)
+}
+
+fn to_word(items: &[Item]) -> Word {
+    let mut word = Word::new();
+    for item in items {
+        match item {
+            Item::Char(c) => word.push(WordPart::Literal(c.to_string())),
+            Item::Part(part) => word.push((*part).clone()),
+        }
+    }
+    word
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::parser::{ Parser, RshNode };
+
+    // the brace expansion of the first argument of a command, showing expansions as $name.
+    fn braces(input: &str) -> Vec<String> {
+        let root = Parser::new(&format!("echo {}", input)).parse().unwrap();
+        let word = match root {
+            RshNode::Command { args, .. } => args[0].clone(),
+            other => panic!("not a simple command {:?}", other),
+        };
+
+        expand(&word)
+            .iter()
+            .map(|word| word.parts.iter().map(|part| match part {
+                WordPart::Literal(text) | WordPart::Quoted(text) => text.clone(),
+                WordPart::Param { name, .. } => format!("${}", name),
+                other => panic!("unexpected part {:?}", other),
+            }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_brace_alternatives() {
+        assert_eq!(braces("file.{rs,toml}"), vec!["file.rs", "file.toml"]);
+        assert_eq!(braces("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(braces("x{a,{b,c}d,}y"), vec!["xay", "xbdy", "xcdy", "xy"]);
+        assert_eq!(braces("{$x,default}/bin"), vec!["$x/bin", "default/bin"]);
+    }
+
+    #[test]
+    fn test_brace_sequences() {
+        assert_eq!(braces("{1..5}"), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(braces("{3..-1}"), vec!["3", "2", "1", "0", "-1"]);
+        assert_eq!(braces("{1..10..3}"), vec!["1", "4", "7", "10"]);
+        assert_eq!(braces("{08..11}"), vec!["08", "09", "10", "11"]);
+        assert_eq!(braces("{a..z..5}"), vec!["a", "f", "k", "p", "u", "z"]);
+        assert_eq!(braces("{c..a}"), vec!["c", "b", "a"]);
+        assert_eq!(braces("v{1..2}.{0,1}"), vec!["v1.0", "v1.1", "v2.0", "v2.1"]);
+    }
+
+    #[test]
+    fn test_brace_literal() {
+        assert_eq!(braces("{}"), vec!["{}"]);
+        assert_eq!(braces("{a}"), vec!["{a}"]);
+        assert_eq!(braces("{a,b"), vec!["{a,b"]);
+        assert_eq!(braces("{1..b}"), vec!["{1..b}"]);
+        assert_eq!(braces("\"{a,b}\""), vec!["{a,b}"]);
+        assert_eq!(braces("{a\\,b}"), vec!["{a,b}"]);
+        assert_eq!(braces("{a{b,c}"), vec!["{ab", "{ac"]);
+    }
+}
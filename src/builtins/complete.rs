@@ -0,0 +1,174 @@
+use super::{ error, Builtin };
+use crate::complete::{ self, spec, Action, Spec };
+use crate::state::ShellState;
+
+const COMPLETE_USAGE: &str = "usage: complete [-fdcvuj] [-W words] [-F function] [-X filter] name... | complete -p [name...] \
+    | complete -r [name...]";
+const COMPGEN_USAGE: &str = "usage: compgen [-fdcvuj] [-W words] [-F function] [-X filter] [--] [word]";
+
+/**
+* The spec the options in front of the other arguments make, and where those arguments start. The letters of the
+* actions can be put together, like "-fd", and "--" ends the options.
+*/
+fn options<'a>(args: &'a [&'a str]) -> Result<(Spec, &'a [&'a str]), String> {
+    let mut spec = Spec::default();
+    let mut idx = 0;
+    while let Some(&arg) = args.get(idx) {
+        idx += 1;
+        let mut value = || {
+            idx += 1;
+            args.get(idx - 1).map(|value| value.to_string()).ok_or_else(|| format!("{}: option requires an argument", arg))
+        };
+        match arg {
+            "--" => break,
+            "-W" => spec.words = Some(value()?),
+            "-F" => spec.function = Some(value()?),
+            "-X" => spec.filter = Some(value()?),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                for letter in arg[1..].chars() {
+                    spec.add(Action::from_letter(letter).ok_or_else(|| format!("-{}: invalid option", letter))?);
+                }
+            },
+            _ => {
+                idx -= 1;
+                break;
+            },
+        }
+    }
+    Ok((spec, &args[idx..]))
+}
+
+/**
+* complete [options] name...: says how the arguments of the named commands are completed at the prompt. A name
+* can be a command and a subcommand, like "git remote", whose spec wins over the command's. -f offers files, -d
+* directories, -c commands, -v variables, -u users and -j jobs. -W offers the words in a list, and -F runs a
+* function with the command's name, the word and the word before it, offering each line it prints. -X takes out the
+* candidates matching a pattern, or with a leading "!" those that don't. -p, or no arguments, shows the specs as
+* complete commands, and -r removes the named specs, or all of them.
+*/
+pub struct Complete;
+
+impl Builtin for Complete {
+    fn name(&self) -> &'static str {
+        "complete"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        match args.as_slice() {
+            [] => print_specs(state, &[]),
+            ["-p", names @ ..] => print_specs(state, names),
+            ["-r"] => {
+                state.completions.clear();
+                0
+            },
+            ["-r", names @ ..] => {
+                let mut status = 0;
+                for name in names {
+                    if state.completions.remove(*name).is_none() {
+                        error("complete", &format!("{}: no completion specification", name));
+                        status = 1;
+                    }
+                }
+                status
+            },
+            args => {
+                let (spec, names) = match options(args) {
+                    Ok((spec, names)) if !spec.is_empty() && !names.is_empty() => (spec, names),
+                    Ok(_) => {
+                        error("complete", COMPLETE_USAGE);
+                        return 2;
+                    },
+                    Err(message) => {
+                        error("complete", &message);
+                        return 2;
+                    },
+                };
+                for name in names {
+                    state.completions.insert(name.to_string(), spec.clone());
+                }
+                0
+            },
+        }
+    }
+}
+
+// the specs as commands that would set them again, e.g. "complete -d 'cd'", in the order of their names.
+fn print_specs(state: &ShellState, names: &[&str]) -> i32 {
+    let mut status = 0;
+    let mut specs: Vec<(&String, &Spec)> = state.completions.iter().collect();
+    specs.sort_by_key(|(name, _)| *name);
+    for name in names {
+        if !state.completions.contains_key(*name) {
+            error("complete", &format!("{}: no completion specification", name));
+            status = 1;
+        }
+    }
+    for (name, spec) in specs {
+        if names.is_empty() || names.contains(&name.as_str()) {
+            println!("complete {} {}", spec, spec::quote(name));
+        }
+    }
+    status
+}
+
+/**
+* compgen [options] [word]: prints the candidates complete's options would give for the word, one per line, for use
+* in completion functions. Fails when there are none.
+*/
+pub struct Compgen;
+
+impl Builtin for Compgen {
+    fn name(&self) -> &'static str {
+        "compgen"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        let (spec, word) = match options(&args) {
+            Ok((spec, [])) => (spec, ""),
+            Ok((spec, [word])) => (spec, *word),
+            Ok(_) => {
+                error("compgen", COMPGEN_USAGE);
+                return 2;
+            },
+            Err(message) => {
+                error("compgen", &message);
+                return 2;
+            },
+        };
+
+        let candidates = complete::generate(&spec, word, &[], "", state);
+        for candidate in &candidates {
+            println!("{}", candidate.text);
+        }
+        i32::from(candidates.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_complete() {
+        let mut state = ShellState::default();
+        assert_eq!(Complete.run(&args(&["-df", "-W", "a b", "--", "deploy", "git remote"]), &mut state), 0);
+        assert_eq!(state.completions["git remote"].to_string(), "-df -W 'a b'");
+        assert_eq!(state.completions["deploy"], state.completions["git remote"]);
+        assert_eq!(Complete.run(&args(&["-p", "deploy", "nope"]), &mut state), 1);
+        assert_eq!(Complete.run(&args(&["-r", "deploy"]), &mut state), 0);
+        assert!(!state.completions.contains_key("deploy"));
+        assert_eq!(Complete.run(&args(&["-r", "deploy"]), &mut state), 1);
+        assert_eq!(Complete.run(&args(&["-q", "x"]), &mut state), 2);
+        assert_eq!(Complete.run(&args(&["-W"]), &mut state), 2);
+        assert_eq!(Complete.run(&args(&["-d"]), &mut state), 2);
+        assert_eq!(Complete.run(&args(&["-r"]), &mut state), 0);
+        assert!(state.completions.is_empty());
+
+        assert_eq!(Compgen.run(&args(&["-W", "start stop status", "st"]), &mut state), 0);
+        assert_eq!(Compgen.run(&args(&["-W", "start stop", "x"]), &mut state), 1);
+        assert_eq!(Compgen.run(&args(&["-W", "a", "b", "c"]), &mut state), 2);
+    }
+}
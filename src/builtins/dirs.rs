@@ -0,0 +1,139 @@
+use super::{ error, Builtin };
+use crate::engine::error_message;
+use crate::state::ShellState;
+use std::path::{ Component, Path, PathBuf };
+
+/**
+* cd [dir]: changes the working directory, to $HOME without an argument or to $OLDPWD for "-". Paths are
+* followed logically like other shells do, so "cd link/.." goes back to where we started rather than to the
+* parent of wherever the link points, and $PWD and $OLDPWD are kept up to date.
+*/
+pub struct Cd;
+
+impl Builtin for Cd {
+    fn name(&self) -> &'static str {
+        "cd"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let (target, print) = match args {
+            [] => match state.vars.get("HOME") {
+                Some(home) => (home.to_string(), false),
+                None => {
+                    error("cd", "HOME not set");
+                    return 1;
+                },
+            },
+            [dash] if dash == "-" => match state.vars.get("OLDPWD") {
+                Some(old) => (old.to_string(), true),
+                None => {
+                    error("cd", "OLDPWD not set");
+                    return 1;
+                },
+            },
+            [dir] => (dir.to_string(), false),
+            _ => {
+                error("cd", "too many arguments");
+                return 1;
+            },
+        };
+
+        let current = current_dir(state);
+        let logical = normalize(&current.join(&target));
+        let pwd = if std::env::set_current_dir(&logical).is_ok() {
+            logical
+        } else {
+            // the logical path can go wrong with ".." after a symlink, the real one is the fallback.
+            if let Err(err) = std::env::set_current_dir(&target) {
+                error("cd", &format!("{}: {}", target, error_message(&err)));
+                return 1;
+            }
+            std::env::current_dir().unwrap_or_else(|_| PathBuf::from(&target))
+        };
+
+        let pwd = pwd.to_string_lossy().into_owned();
+        state.vars.set("OLDPWD", &current.to_string_lossy());
+        state.vars.set("PWD", &pwd);
+        if print {
+            println!("{}", pwd);
+        }
+        0
+    }
+}
+
+// pwd [-L|-P]: prints the working directory, as $PWD has it unless -P asks for the one without symlinks.
+pub struct Pwd;
+
+impl Builtin for Pwd {
+    fn name(&self) -> &'static str {
+        "pwd"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let mut physical = false;
+        for arg in args {
+            match arg.as_str() {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                _ => {
+                    error("pwd", &format!("{}: invalid option", arg));
+                    return 2;
+                },
+            }
+        }
+
+        let dir = if physical {
+            std::env::current_dir()
+        } else {
+            Ok(current_dir(state))
+        };
+
+        match dir {
+            Ok(dir) => {
+                println!("{}", dir.to_string_lossy());
+                0
+            },
+            Err(err) => {
+                error("pwd", &error_message(&err));
+                1
+            },
+        }
+    }
+}
+
+// $PWD if it still names the directory we're in, otherwise the real path of the working directory.
+pub(super) fn current_dir(state: &ShellState) -> PathBuf {
+    let actual = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    match state.vars.get("PWD") {
+        Some(pwd) if pwd.starts_with('/') && Path::new(pwd).canonicalize().is_ok_and(|real| real == actual) => PathBuf::from(pwd),
+        _ => actual,
+    }
+}
+
+// removes "." and resolves ".." without looking at the filesystem. "/.." is still "/".
+pub(super) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            Component::Normal(name) => normalized.push(name),
+            _ => {},
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+        assert_eq!(normalize(Path::new("/../..")), PathBuf::from("/"));
+        assert_eq!(normalize(Path::new("/usr/bin/")), PathBuf::from("/usr/bin"));
+        assert_eq!(normalize(&Path::new("/home/me").join("../you")), PathBuf::from("/home/you"));
+    }
+}
@@ -0,0 +1,60 @@
+use super::{ error, Builtin };
+use crate::state::ShellState;
+
+/**
+* exit [n]: leaves the shell with status n, or with the status of the last command. The engine stops running
+* the current command line once this has been asked for, and the shell stops reading new ones.
+*/
+pub struct Exit;
+
+impl Builtin for Exit {
+    fn name(&self) -> &'static str {
+        "exit"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let code = match args {
+            [] => state.last_status.code(),
+            [n] => match n.parse::<i64>() {
+                // statuses are a single byte, so "exit 256" is "exit 0" like everywhere else.
+                Ok(n) => (n & 0xff) as i32,
+                Err(_) => {
+                    error("exit", &format!("{}: numeric argument required", n));
+                    2
+                },
+            },
+            _ => {
+                // the one mistake that doesn't exit, so a typo can't close an interactive shell.
+                error("exit", "too many arguments");
+                return 1;
+            },
+        };
+
+        state.exit_requested = true;
+        code
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+    use crate::engine::Outcome;
+
+    #[test]
+    fn test_exit_status() {
+        let mut state = ShellState {
+            last_status: Outcome::Exited(4),
+            ..ShellState::default()
+        };
+        assert_eq!(Exit.run(&[], &mut state), 4);
+        assert!(state.exit_requested);
+        assert_eq!(Exit.run(&args(&["258"]), &mut state), 2);
+        assert_eq!(Exit.run(&args(&["-1"]), &mut state), 255);
+        assert_eq!(Exit.run(&args(&["x"]), &mut state), 2);
+
+        let mut state = ShellState::default();
+        assert_eq!(Exit.run(&args(&["1", "2"]), &mut state), 1);
+        assert!(!state.exit_requested);
+    }
+}
@@ -0,0 +1,153 @@
+use super::{ error, Builtin };
+use crate::state::{ Jump, ShellState };
+use crate::variables::is_valid_name;
+
+/**
+* local name[=value]...: makes variables local to the running function, so they go back to what they were
+* when it returns. A name without a value starts out unset.
+*/
+pub struct Local;
+
+impl Builtin for Local {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if state.call_depth == 0 {
+            error("local", "can only be used in a function");
+            return 1;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            if !is_valid_name(name) {
+                error("local", &format!("`{}': not a valid identifier", arg));
+                status = 1;
+                continue;
+            }
+
+            state.vars.make_local(name);
+            if let Some(value) = value {
+                state.vars.set(name, value);
+            }
+        }
+        status
+    }
+}
+
+// return [n]: leaves the running function with status n, or with the status of the last command.
+pub struct Return;
+
+impl Builtin for Return {
+    fn name(&self) -> &'static str {
+        "return"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if state.call_depth == 0 {
+            error("return", "can only `return' from a function");
+            return 1;
+        }
+
+        let code = match args {
+            [] => state.last_status.code(),
+            [n] => match n.parse::<i64>() {
+                Ok(n) => (n & 0xff) as i32,
+                Err(_) => {
+                    error("return", &format!("{}: numeric argument required", n));
+                    2
+                },
+            },
+            _ => {
+                error("return", "too many arguments");
+                return 1;
+            },
+        };
+
+        state.jump = Some(Jump::Return);
+        code
+    }
+}
+
+// shift [n]: drops the first n positional parameters, 1 if n is left out, so $2 becomes $1 and so on.
+pub struct Shift;
+
+impl Builtin for Shift {
+    fn name(&self) -> &'static str {
+        "shift"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let n = match args {
+            [] => 1,
+            [n] => match n.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => {
+                    error("shift", &format!("{}: numeric argument required", n));
+                    return 1;
+                },
+            },
+            _ => {
+                error("shift", "too many arguments");
+                return 1;
+            },
+        };
+
+        if n > state.positional.len() {
+            error("shift", &format!("{}: shift count out of range", n));
+            return 1;
+        }
+        state.positional.drain(..n);
+        0
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_functions_outside_function() {
+        let mut state = ShellState::default();
+        assert_eq!(Local.run(&args(&["x=1"]), &mut state), 1);
+        assert_eq!(state.vars.get("x"), None);
+        assert_eq!(Return.run(&args(&["3"]), &mut state), 1);
+        assert_eq!(state.jump, None);
+    }
+
+    #[test]
+    fn test_functions_local_return() {
+        let mut state = ShellState::default();
+        state.vars.set("x", "global");
+        state.call_depth = 1;
+        state.vars.push_scope();
+        assert_eq!(Local.run(&args(&["x=1", "y", "1y"]), &mut state), 1);
+        assert_eq!(state.vars.get("x"), Some("1"));
+        assert_eq!(state.vars.get("y"), None);
+        state.vars.pop_scope();
+        assert_eq!(state.vars.get("x"), Some("global"));
+
+        assert_eq!(Return.run(&args(&["257"]), &mut state), 1);
+        assert_eq!(state.jump, Some(Jump::Return));
+    }
+
+    #[test]
+    fn test_functions_shift() {
+        let mut state = ShellState {
+            positional: args(&["a", "b", "c"]),
+            ..ShellState::default()
+        };
+        assert_eq!(Shift.run(&[], &mut state), 0);
+        assert_eq!(state.positional, args(&["b", "c"]));
+        assert_eq!(Shift.run(&args(&["3"]), &mut state), 1);
+        assert_eq!(Shift.run(&args(&["2"]), &mut state), 0);
+        assert!(state.positional.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+use super::dirs::{ current_dir, normalize };
+use super::{ error, Builtin };
+use crate::engine::error_message;
+use crate::history::{ self, import, Entry, Query };
+use crate::state::ShellState;
+use std::fs;
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: history [n] | history search [-d dir] [-s session | -S] [-x status | -f] [-a time] [-b time] [text] \
+    | history -c | history -d n | history -w|-r [file] | history import bash|zsh [file]";
+
+// prints entries with their numbers, which count from 1 at the oldest entry remembered.
+fn list<'e>(entries: impl Iterator<Item = (usize, &'e Entry)>) {
+    for (idx, entry) in entries {
+        println!("{:5}  {}", idx + 1, entry.command);
+    }
+}
+
+// prints what a search found: when each command was run, how long it took, its status, where it ran and what it was.
+fn show(entries: &[Entry]) {
+    for entry in entries {
+        let time = if entry.time == 0 { "-".to_string() } else { history::format_time(entry.time) };
+        let cwd = if entry.cwd.is_empty() { "-" } else { entry.cwd.as_str() };
+        println!("{:19}  {:>7}  {:>3}  {}  {}", time, duration(entry.duration), entry.status, cwd, entry.command);
+    }
+}
+
+// a duration in milliseconds, like "850ms", "2.5s" or "3m07s".
+fn duration(millis: u64) -> String {
+    match millis {
+        0..=999 => format!("{}ms", millis),
+        1000..=59_999 => format!("{:.1}s", millis as f64 / 1000.0),
+        _ => format!("{}m{:02}s", millis / 60_000, millis / 1000 % 60),
+    }
+}
+
+/**
+* The query "history search" was given: -d for a directory, relative ones being taken from the current one, -s for
+* a session or -S for this one, -x for a status or -f for any failure, and -a and -b for the times it was run after
+* and before. The rest of the words are text the command has to contain.
+*/
+fn query(args: &[&str], state: &ShellState) -> Result<Query, String> {
+    let mut query = Query::default();
+    let mut text = Vec::new();
+    let mut args = args.iter().copied();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{}: option requires an argument", arg));
+        match arg {
+            "-d" => query.cwd = Some(normalize(&current_dir(state).join(value()?)).to_string_lossy().into_owned()),
+            "-s" => query.session = Some(value()?.to_string()),
+            "-S" => query.session = Some(history::session().to_string()),
+            "-x" => {
+                let status = value()?;
+                query.status = Some(status.parse().map_err(|_| format!("{}: bad status", status))?);
+            },
+            "-f" => query.failed = true,
+            "-a" | "-b" => {
+                let time = value()?;
+                let time = history::parse_time(time).ok_or_else(|| format!("{}: bad time", time))?;
+                if arg == "-a" {
+                    query.since = Some(time);
+                } else {
+                    query.until = Some(time);
+                }
+            },
+            _ if arg.starts_with('-') && arg.len() > 1 && text.is_empty() => return Err(format!("{}: invalid option", arg)),
+            _ => text.push(arg),
+        }
+    }
+    if !text.is_empty() {
+        query.text = Some(text.join(" "));
+    }
+    Ok(query)
+}
+
+/**
+* history [n]: lists the commands typed at the prompt, or the last n of them. "history search" lists the saved
+* commands, from other sessions too, that match the filters and text it's given, see query. -c forgets all of the
+* commands and -d n the one numbered n, counting back from the end if n is negative. -w writes them all to the
+* history file, or to the file given, and -r adds the ones in it. "history import bash" or "zsh" adds the commands
+* in that shell's history file, or in the file given.
+*/
+pub struct History;
+
+impl Builtin for History {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        let entries = state.history.entries();
+
+        match args.as_slice() {
+            [] => list(entries.iter().enumerate()),
+            ["search", args @ ..] => {
+                let query = match query(args, state) {
+                    Ok(query) => query,
+                    Err(message) => {
+                        error("history", &message);
+                        return 2;
+                    },
+                };
+                match state.history.search(&query) {
+                    Ok(found) => show(&found),
+                    Err(err) => {
+                        error("history", &error_message(&err));
+                        return 1;
+                    },
+                }
+            },
+            ["-c"] => state.history.clear(),
+            ["-d", n] => {
+                let number = match n.parse::<i64>() {
+                    Ok(n) if n < 0 => entries.len() as i64 + n + 1,
+                    Ok(n) => n,
+                    Err(_) => 0,
+                };
+                if !state.history.delete(usize::try_from(number).unwrap_or(0)) {
+                    error("history", &format!("{}: history position out of range", n));
+                    return 1;
+                }
+            },
+            [flag @ ("-w" | "-r"), file @ ..] if file.len() <= 1 => {
+                let path = match file.first() {
+                    Some(file) => PathBuf::from(file),
+                    None => match state.history.path() {
+                        Some(path) => path.to_path_buf(),
+                        None => {
+                            error("history", "no history file");
+                            return 1;
+                        },
+                    },
+                };
+                let result = if *flag == "-w" { state.history.write(&path) } else { state.history.read(&path) };
+                if let Err(err) = result {
+                    error("history", &format!("{}: {}", path.display(), error_message(&err)));
+                    return 1;
+                }
+            },
+            ["import", shell @ ("bash" | "zsh"), file @ ..] if file.len() <= 1 => {
+                let path = match file.first() {
+                    Some(file) => PathBuf::from(file),
+                    None => match import::default_path(shell, &state.vars) {
+                        Some(path) => path,
+                        None => {
+                            error("history", "no history file");
+                            return 1;
+                        },
+                    },
+                };
+                let imported = fs::read(&path).and_then(|contents| {
+                    let entries = if *shell == "bash" { import::bash(&String::from_utf8_lossy(&contents)) } else { import::zsh(&contents) };
+                    state.history.import(entries, &state.vars)
+                });
+                if let Err(err) = imported {
+                    error("history", &format!("{}: {}", path.display(), error_message(&err)));
+                    return 1;
+                }
+            },
+            [n] if n.parse::<usize>().is_ok() => {
+                let skip = entries.len().saturating_sub(n.parse().unwrap_or(0));
+                list(entries.iter().enumerate().skip(skip));
+            },
+            _ => {
+                error("history", USAGE);
+                return 2;
+            },
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+    use crate::variables::Variables;
+
+    #[test]
+    fn test_history_builtin_delete() {
+        let mut state = ShellState::default();
+        let vars = Variables::new();
+        for command in ["a", "b", "c"] {
+            state.history.add(Entry::new(command, "/", 0), &vars).unwrap();
+        }
+
+        assert_eq!(History.run(&args(&["-d", "-1"]), &mut state), 0);
+        assert_eq!(History.run(&args(&["-d", "5"]), &mut state), 1);
+        assert_eq!(History.run(&args(&["-d", "x"]), &mut state), 1);
+        let commands: Vec<&str> = state.history.entries().iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, vec!["a", "b"]);
+
+        assert_eq!(History.run(&args(&["-w"]), &mut state), 1);
+        assert_eq!(History.run(&args(&["-q"]), &mut state), 2);
+        assert_eq!(History.run(&args(&["-c"]), &mut state), 0);
+        assert!(state.history.entries().is_empty());
+    }
+}
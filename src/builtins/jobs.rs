@@ -0,0 +1,235 @@
+use super::{ error, Builtin };
+use crate::engine::{ error_message, Outcome };
+use crate::state::ShellState;
+
+// the ids the job specs in "args" name, or the current job if there aren't any.
+fn find_jobs(name: &str, args: &[String], state: &ShellState) -> Result<Vec<usize>, i32> {
+    if args.is_empty() {
+        return state.jobs.find("%+").map(|id| vec![id]).map_err(|message| {
+            error(name, &message);
+            1
+        });
+    }
+
+    let mut ids = Vec::new();
+    for arg in args {
+        match state.jobs.find(arg) {
+            Ok(id) => ids.push(id),
+            Err(message) => {
+                error(name, &message);
+                return Err(1);
+            },
+        }
+    }
+    Ok(ids)
+}
+
+// jobs [-l|-p] [jobspec...]: lists jobs and their state, -l adds the pids and -p shows only the pids.
+pub struct Jobs;
+
+impl Builtin for Jobs {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let mut long = false;
+        let mut pids = false;
+        let mut specs = args;
+        while let Some(flag) = specs.first().filter(|arg| arg.starts_with('-')) {
+            match flag.as_str() {
+                "-l" => long = true,
+                "-p" => pids = true,
+                _ => {
+                    error("jobs", &format!("{}: invalid option", flag));
+                    return 2;
+                },
+            }
+            specs = &specs[1..];
+        }
+
+        let ids = if specs.is_empty() {
+            state.jobs.ids()
+        } else {
+            match find_jobs("jobs", specs, state) {
+                Ok(ids) => ids,
+                Err(code) => return code,
+            }
+        };
+
+        if pids {
+            for id in ids {
+                if let Some(pid) = state.jobs.get(id).and_then(|job| job.processes.iter().find_map(|process| process.pid)) {
+                    println!("{}", pid);
+                }
+            }
+        } else {
+            state.jobs.list(&ids, long);
+        }
+        0
+    }
+}
+
+// fg [jobspec]: brings a job to the foreground, continuing it if it was stopped, and waits for it.
+pub struct Fg;
+
+impl Builtin for Fg {
+    fn name(&self) -> &'static str {
+        "fg"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if !state.jobs.job_control() {
+            error("fg", "no job control");
+            return 1;
+        }
+        if args.len() > 1 {
+            error("fg", "too many arguments");
+            return 1;
+        }
+        let id = match find_jobs("fg", args, state) {
+            Ok(ids) => ids[0],
+            Err(code) => return code,
+        };
+
+        match state.jobs.resume_foreground(id) {
+            Ok(outcomes) => {
+                state.note_interrupt(&outcomes);
+                let last = outcomes.last().copied().unwrap_or_default();
+                let outcome = if state.options.pipefail {
+                    outcomes.iter().rev().find(|o| !o.success()).copied().unwrap_or(last)
+                } else {
+                    last
+                };
+                state.pipestatus = outcomes;
+                outcome.code()
+            },
+            Err(err) => {
+                error("fg", &error_message(&err));
+                1
+            },
+        }
+    }
+}
+
+// bg [jobspec...]: continues stopped jobs in the background.
+pub struct Bg;
+
+impl Builtin for Bg {
+    fn name(&self) -> &'static str {
+        "bg"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if !state.jobs.job_control() {
+            error("bg", "no job control");
+            return 1;
+        }
+        let ids = match find_jobs("bg", args, state) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+
+        let mut status = 0;
+        for id in ids {
+            if let Err(err) = state.jobs.resume_background(id) {
+                error("bg", &error_message(&err));
+                status = 1;
+            }
+        }
+        status
+    }
+}
+
+/**
+* wait [jobspec|pid...]: waits for the given jobs to finish and returns the status of the last one, or for every
+* job if none are given, which succeeds unless a signal cuts it short. A spec that doesn't name a job is status 127.
+*/
+pub struct Wait;
+
+impl Builtin for Wait {
+    fn name(&self) -> &'static str {
+        "wait"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if args.is_empty() {
+            for id in state.jobs.ids() {
+                match state.jobs.wait(id) {
+                    // the job is only left in the table if a signal cut the wait short, or it stopped.
+                    Ok(outcome) if state.jobs.get(id).is_some() => return outcome.code(),
+                    Ok(_) => {},
+                    Err(err) => error("wait", &error_message(&err)),
+                }
+            }
+            return 0;
+        }
+
+        let mut outcome = Outcome::default();
+        for arg in args {
+            let waited = match state.jobs.find(arg) {
+                Ok(id) => state.jobs.wait(id),
+                Err(message) => {
+                    error("wait", &message);
+                    outcome = Outcome::Exited(127);
+                    continue;
+                },
+            };
+            match waited {
+                Ok(waited) => outcome = waited,
+                Err(err) => {
+                    error("wait", &error_message(&err));
+                    outcome = Outcome::Exited(127);
+                },
+            }
+        }
+        outcome.code()
+    }
+}
+
+// disown [-a] [jobspec...]: drops jobs from the table, leaving them running. -a drops all of them.
+pub struct Disown;
+
+impl Builtin for Disown {
+    fn name(&self) -> &'static str {
+        "disown"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let ids = match args.first().map(|arg| arg.as_str()) {
+            Some("-a") => state.jobs.ids(),
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 => {
+                error("disown", &format!("{}: invalid option", flag));
+                return 2;
+            },
+            _ => match find_jobs("disown", args, state) {
+                Ok(ids) => ids,
+                Err(code) => return code,
+            },
+        };
+
+        for id in ids {
+            state.jobs.disown(id);
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_jobs_without_jobs() {
+        let mut state = ShellState::default();
+        assert_eq!(Jobs.run(&[], &mut state), 0);
+        assert_eq!(Jobs.run(&args(&["-x"]), &mut state), 2);
+        assert_eq!(Fg.run(&[], &mut state), 1);
+        assert_eq!(Bg.run(&args(&["%1"]), &mut state), 1);
+        assert_eq!(Wait.run(&[], &mut state), 0);
+        assert_eq!(Wait.run(&args(&["%1"]), &mut state), 127);
+        assert_eq!(Disown.run(&[], &mut state), 1);
+        assert_eq!(Disown.run(&args(&["-a"]), &mut state), 0);
+    }
+}
@@ -0,0 +1,87 @@
+use super::{ error, Builtin };
+use crate::state::{ Jump, ShellState };
+
+/**
+* The loop count for break and continue, 1 if it's left out. Asking for more loops than there are is the
+* same as asking for all of them.
+*/
+fn count(name: &str, args: &[String], state: &ShellState) -> Result<u32, i32> {
+    let n = match args {
+        [] => 1,
+        [n] => match n.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                error(name, &format!("{}: loop count out of range", n));
+                return Err(1);
+            },
+        },
+        _ => {
+            error(name, "too many arguments");
+            return Err(1);
+        },
+    };
+
+    if state.loop_depth == 0 {
+        error(name, "only meaningful in a `for', `while', or `until' loop");
+        return Err(0);
+    }
+    Ok(n.min(state.loop_depth))
+}
+
+// break [n]: leaves the innermost loop, or the n innermost ones.
+pub struct Break;
+
+impl Builtin for Break {
+    fn name(&self) -> &'static str {
+        "break"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        match count("break", args, state) {
+            Ok(n) => {
+                state.jump = Some(Jump::Break(n));
+                0
+            },
+            Err(code) => code,
+        }
+    }
+}
+
+// continue [n]: skips to the next round of the innermost loop, or of the n-th one out.
+pub struct Continue;
+
+impl Builtin for Continue {
+    fn name(&self) -> &'static str {
+        "continue"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        match count("continue", args, state) {
+            Ok(n) => {
+                state.jump = Some(Jump::Continue(n));
+                0
+            },
+            Err(code) => code,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_loops_count() {
+        let mut state = ShellState::default();
+        assert_eq!(Break.run(&[], &mut state), 0);
+        assert_eq!(state.jump, None);
+
+        state.loop_depth = 2;
+        assert_eq!(Break.run(&args(&["5"]), &mut state), 0);
+        assert_eq!(state.jump, Some(Jump::Break(2)));
+        assert_eq!(Continue.run(&[], &mut state), 0);
+        assert_eq!(state.jump, Some(Jump::Continue(1)));
+        assert_eq!(Continue.run(&args(&["0"]), &mut state), 1);
+    }
+}
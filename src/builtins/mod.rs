@@ -0,0 +1,84 @@
+use crate::location;
+use crate::state::ShellState;
+
+mod complete;
+mod dirs;
+mod exit;
+mod functions;
+mod history;
+mod jobs;
+mod loops;
+mod options;
+mod trap;
+mod vars;
+
+/**
+* A command that runs inside the shell rather than as a separate program, so that it can change the shell
+* itself: its working directory, its variables, or whether it keeps running. The engine looks builtins up
+* before searching $PATH, and sets up their redirections and pipes the same way it does for programs.
+*/
+pub trait Builtin {
+    fn name(&self) -> &'static str;
+
+    // runs the builtin with its arguments, not including its own name, and returns its exit status.
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32;
+}
+
+// every builtin the shell has.
+static BUILTINS: &[&(dyn Builtin + Sync)] = &[
+    &complete::Complete,
+    &complete::Compgen,
+    &dirs::Cd,
+    &dirs::Pwd,
+    &exit::Exit,
+    &functions::Local,
+    &functions::Return,
+    &functions::Shift,
+    &history::History,
+    &jobs::Jobs,
+    &jobs::Fg,
+    &jobs::Bg,
+    &jobs::Wait,
+    &jobs::Disown,
+    &loops::Break,
+    &loops::Continue,
+    &options::Set,
+    &options::Shopt,
+    &trap::Trap,
+    &vars::Export,
+    &vars::Unset,
+];
+
+pub fn lookup(name: &str) -> Option<&'static dyn Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name() == name).map(|builtin| *builtin as &dyn Builtin)
+}
+
+pub fn names() -> Vec<&'static str> {
+    BUILTINS.iter().map(|builtin| builtin.name()).collect()
+}
+
+// reports a problem the way every builtin does, "rsh: name: message" on stderr, or with the script's file:line for "rsh".
+fn error(name: &str, message: &str) {
+    eprintln!("{}: {}: {}", location::prefix(), name, message);
+}
+
+// the arguments a builtin is run with, for the tests of the builtins.
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_builtins_lookup() {
+        assert_eq!(lookup("cd").map(|builtin| builtin.name()), Some("cd"));
+        assert!(lookup("ls").is_none());
+        let mut names = names();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), BUILTINS.len());
+    }
+}
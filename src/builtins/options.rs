@@ -0,0 +1,157 @@
+use super::{ error, Builtin };
+use crate::state::{ ShellOptions, ShellState };
+
+// the options "set -o" controls, as opposed to the ones "shopt" does.
+const SET_OPTIONS: [&str; 1] = ["pipefail"];
+const SHOPT_OPTIONS: [&str; 3] = ["dotglob", "failglob", "nullglob"];
+
+fn option<'o>(options: &'o mut ShellOptions, name: &str) -> Option<&'o mut bool> {
+    match name {
+        "pipefail" => Some(&mut options.pipefail),
+        "dotglob" => Some(&mut options.dotglob),
+        "failglob" => Some(&mut options.failglob),
+        "nullglob" => Some(&mut options.nullglob),
+        _ => None,
+    }
+}
+
+// one line of "set -o" or "shopt" output.
+fn print_option(name: &str, on: bool) {
+    println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+}
+
+// set -o name turns an option on and set +o name turns it off. Plain "set -o" lists them, and "set -- args" sets $1 and on.
+pub struct Set;
+
+impl Builtin for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if args.is_empty() || args == ["-o"] {
+            for name in SET_OPTIONS {
+                let on = option(&mut state.options, name).is_some_and(|on| *on);
+                print_option(name, on);
+            }
+            return 0;
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let on = match arg.as_str() {
+                "--" => {
+                    state.positional = args.cloned().collect();
+                    return 0;
+                },
+                "-o" => true,
+                "+o" => false,
+                _ => {
+                    error("set", &format!("{}: invalid option", arg));
+                    return 2;
+                },
+            };
+
+            let name = match args.next() {
+                Some(name) if SET_OPTIONS.contains(&name.as_str()) => name,
+                Some(name) => {
+                    error("set", &format!("{}: invalid option name", name));
+                    return 1;
+                },
+                None => {
+                    error("set", &format!("{}: option name required", arg));
+                    return 2;
+                },
+            };
+            if let Some(value) = option(&mut state.options, name) {
+                *value = on;
+            }
+        }
+        0
+    }
+}
+
+/**
+* shopt [-s|-u|-q] [name...]: -s turns options on and -u turns them off. Without either it shows them, and
+* succeeds only if all the named ones are on, which -q checks without printing anything.
+*/
+pub struct Shopt;
+
+impl Builtin for Shopt {
+    fn name(&self) -> &'static str {
+        "shopt"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let (mode, names) = match args.first().map(|arg| arg.as_str()) {
+            Some(flag @ ("-s" | "-u" | "-q")) => (flag, &args[1..]),
+            Some(flag) if flag.starts_with('-') => {
+                error("shopt", &format!("{}: invalid option", flag));
+                return 2;
+            },
+            _ => ("", args),
+        };
+
+        if let Some(name) = names.iter().find(|name| !SHOPT_OPTIONS.contains(&name.as_str())) {
+            error("shopt", &format!("{}: invalid shell option name", name));
+            return 1;
+        }
+
+        let names: Vec<&str> = if names.is_empty() {
+            SHOPT_OPTIONS.to_vec()
+        } else {
+            names.iter().map(|name| name.as_str()).collect()
+        };
+
+        let mut all_on = true;
+        for name in names {
+            let value = match option(&mut state.options, name) {
+                Some(value) => value,
+                None => continue,
+            };
+            match mode {
+                "-s" => *value = true,
+                "-u" => *value = false,
+                "-q" => all_on &= *value,
+                _ => {
+                    all_on &= *value;
+                    print_option(name, *value);
+                },
+            }
+        }
+
+        if all_on { 0 } else { 1 }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_set_option() {
+        let mut state = ShellState::default();
+        assert_eq!(Set.run(&args(&["-o", "pipefail"]), &mut state), 0);
+        assert!(state.options.pipefail);
+        assert_eq!(Set.run(&args(&["+o", "pipefail"]), &mut state), 0);
+        assert!(!state.options.pipefail);
+        assert_eq!(Set.run(&args(&["-o", "nullglob"]), &mut state), 1);
+        assert_eq!(Set.run(&args(&["-x"]), &mut state), 2);
+        assert_eq!(Set.run(&args(&["-o", "pipefail", "--", "a", "-o"]), &mut state), 0);
+        assert!(state.options.pipefail);
+        assert_eq!(state.positional, args(&["a", "-o"]));
+    }
+
+    #[test]
+    fn test_shopt() {
+        let mut state = ShellState::default();
+        assert_eq!(Shopt.run(&args(&["-s", "nullglob", "dotglob"]), &mut state), 0);
+        assert!(state.options.nullglob && state.options.dotglob);
+        assert_eq!(Shopt.run(&args(&["-q", "nullglob"]), &mut state), 0);
+        assert_eq!(Shopt.run(&args(&["-q", "nullglob", "failglob"]), &mut state), 1);
+        assert_eq!(Shopt.run(&args(&["-u", "dotglob"]), &mut state), 0);
+        assert!(!state.options.dotglob);
+        assert_eq!(Shopt.run(&args(&["-s", "pipefail"]), &mut state), 1);
+    }
+}
@@ -0,0 +1,109 @@
+use super::{ error, Builtin };
+use crate::signals;
+use crate::state::ShellState;
+use crate::traps;
+
+/**
+* trap [-lp] [[action] name...]: runs action when the shell gets one of the named signals, or reaches one of the
+* pseudo-signals EXIT, ERR, DEBUG and RETURN. An empty action ignores the signal, and "-" (or leaving the action
+* out) puts it back the way it was. -p, or no arguments at all, shows the traps that are set as trap commands.
+*/
+pub struct Trap;
+
+impl Builtin for Trap {
+    fn name(&self) -> &'static str {
+        "trap"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let args = match args.first().map(|arg| arg.as_str()) {
+            Some("--") => &args[1..],
+            _ => args,
+        };
+
+        match args.first().map(|arg| arg.as_str()) {
+            None => return print_traps(state, &[]),
+            Some("-p") => return print_traps(state, &args[1..]),
+            Some("-l") => {
+                for (name, number) in signals::all() {
+                    println!("{:>2}) SIG{}", number, name);
+                }
+                return 0;
+            },
+            _ => {},
+        }
+
+        // "trap INT" resets INT, the same as "trap - INT".
+        let (action, names) = if args.len() == 1 || args[0] == "-" {
+            (None, if args[0] == "-" { &args[1..] } else { args })
+        } else {
+            (Some(args[0].as_str()), &args[1..])
+        };
+
+        let mut status = 0;
+        for name in names {
+            let trap = match traps::Trap::parse(name) {
+                Some(traps::Trap::Signal(signal)) if signal == libc::SIGKILL || signal == libc::SIGSTOP => {
+                    error("trap", &format!("{}: cannot be trapped", name));
+                    status = 1;
+                    continue;
+                },
+                Some(trap) => trap,
+                None => {
+                    error("trap", &format!("{}: invalid signal specification", name));
+                    status = 1;
+                    continue;
+                },
+            };
+
+            match action {
+                Some(action) => state.traps.set(trap, action),
+                None => state.traps.remove(trap),
+            }
+        }
+        status
+    }
+}
+
+// the traps as commands that would set them again, e.g. "trap -- 'rm -f $tmp' EXIT".
+fn print_traps(state: &ShellState, names: &[String]) -> i32 {
+    let mut wanted = Vec::new();
+    for name in names {
+        match traps::Trap::parse(name) {
+            Some(trap) => wanted.push(trap),
+            None => {
+                error("trap", &format!("{}: invalid signal specification", name));
+                return 1;
+            },
+        }
+    }
+
+    for (trap, action) in state.traps.iter() {
+        if wanted.is_empty() || wanted.contains(&trap) {
+            println!("trap -- '{}' {}", action.replace('\'', "'\\''"), trap);
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_trap() {
+        let mut state = ShellState::default();
+        assert_eq!(Trap.run(&args(&["rm -rf $tmp", "EXIT", "ERR"]), &mut state), 0);
+        assert_eq!(state.traps.get(traps::Trap::Exit), Some("rm -rf $tmp"));
+        assert_eq!(state.traps.get(traps::Trap::Err), Some("rm -rf $tmp"));
+        assert_eq!(Trap.run(&args(&["-", "ERR"]), &mut state), 0);
+        assert_eq!(state.traps.get(traps::Trap::Err), None);
+        assert_eq!(Trap.run(&args(&["EXIT"]), &mut state), 0);
+        assert_eq!(state.traps.get(traps::Trap::Exit), None);
+        assert_eq!(Trap.run(&args(&["echo", "NOPE", "DEBUG"]), &mut state), 1);
+        assert_eq!(state.traps.get(traps::Trap::Debug), Some("echo"));
+        assert_eq!(Trap.run(&args(&["echo", "KILL"]), &mut state), 1);
+        assert_eq!(Trap.run(&args(&["-p", "NOPE"]), &mut state), 1);
+    }
+}
@@ -0,0 +1,121 @@
+use super::{ error, Builtin };
+use crate::state::ShellState;
+use crate::variables::is_valid_name;
+
+/**
+* export [-p] [name[=value]...]: marks variables to be passed on to the commands the shell runs, setting them
+* first if a value is given. Without names it lists the exported variables in a form that can be read back in.
+*/
+pub struct Export;
+
+impl Builtin for Export {
+    fn name(&self) -> &'static str {
+        "export"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        if args.is_empty() || args == ["-p"] {
+            for (name, value) in state.vars.exported() {
+                println!("export {}=\"{}\"", name, escape(value));
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            if !is_valid_name(name) {
+                error("export", &format!("`{}': not a valid identifier", arg));
+                status = 1;
+                continue;
+            }
+
+            if let Some(value) = value {
+                state.vars.set(name, value);
+            }
+            state.vars.export(name);
+        }
+        status
+    }
+}
+
+// unset [-v|-f] name...: removes variables, or functions with -f. Names that aren't set are fine, invalid ones are an error.
+pub struct Unset;
+
+impl Builtin for Unset {
+    fn name(&self) -> &'static str {
+        "unset"
+    }
+
+    fn run(&self, args: &[String], state: &mut ShellState) -> i32 {
+        let names = match args.first().map(|arg| arg.as_str()) {
+            Some("-v") => &args[1..],
+            Some("-f") => {
+                for name in &args[1..] {
+                    state.functions.remove(name);
+                }
+                return 0;
+            },
+            _ => args,
+        };
+
+        let mut status = 0;
+        for name in names {
+            if !is_valid_name(name) {
+                error("unset", &format!("`{}': not a valid identifier", name));
+                status = 1;
+                continue;
+            }
+            state.vars.unset(name);
+        }
+        status
+    }
+}
+
+// a value made safe to put between double quotes.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::builtins::args;
+
+    #[test]
+    fn test_export() {
+        let mut state = ShellState::default();
+        state.vars.set("a", "1");
+        assert_eq!(Export.run(&args(&["a", "b=2=3", "c"]), &mut state), 0);
+        assert_eq!(state.vars.exported(), vec![("a", "1"), ("b", "2=3"), ("c", "")]);
+        assert_eq!(Export.run(&args(&["1x=1", "d=4"]), &mut state), 1);
+        assert_eq!(state.vars.get("d"), Some("4"));
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut state = ShellState::default();
+        state.vars.set("a", "1");
+        state.vars.set("b", "2");
+        assert_eq!(Unset.run(&args(&["-v", "a", "nothing"]), &mut state), 0);
+        assert_eq!(state.vars.get("a"), None);
+        assert_eq!(Unset.run(&args(&["b", "no-no"]), &mut state), 1);
+        assert_eq!(state.vars.get("b"), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a \"b\" $c `d` \\"), "a \\\"b\\\" \\$c \\`d\\` \\\\");
+    }
+}
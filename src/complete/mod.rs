@@ -0,0 +1,653 @@
+use crate::builtins;
+use crate::engine::Engine;
+use crate::expand::Expander;
+use crate::parser::Parser;
+use crate::pattern::Pattern;
+use crate::state::ShellState;
+use crate::token::{ Token, Tokenizer };
+use rustyline::completion::{ Completer, Pair };
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{ Context, Helper };
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ffi::CStr;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+pub mod spec;
+
+pub use spec::{ Action, Spec };
+
+// reserved words that a command name comes straight after.
+const COMMAND_WORDS: [&str; 9] = ["if", "then", "elif", "else", "while", "until", "do", "{", "!"];
+
+// characters that mean something to the shell when they aren't quoted.
+const SPECIAL: &str = " \t\n|&;<>()$`\\\"'*?[]{}!";
+
+/**
+* The line editor's helper, which completes the word before the cursor when tab is pressed. It needs to know the
+* shell's functions, variables and jobs, so the shell lends it its state for as long as a line is being read.
+*/
+#[derive(Default)]
+pub struct Completion {
+    pub state: RefCell<ShellState>,
+}
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete(&line[..pos], &mut self.state.borrow_mut()))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+// every line is accepted as it is. An unfinished command is read on with PS2 by the shell instead, see read_command,
+// since a line rustyline's validator keeps open goes on without any prompt at all.
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/**
+* The word the cursor is at the end of, as far as it's been typed: where it starts in the line, what it stands for
+* with its quotes and backslashes taken out, and the quote that's still open, with where it was opened.
+*/
+#[derive(Debug, Default, PartialEq)]
+struct Word {
+    start: usize,
+    value: String,
+    quote: Option<(char, usize)>,
+}
+
+// where the word being completed goes, which decides what it can be.
+#[derive(Debug, PartialEq)]
+enum Position {
+    Command,
+    Argument,
+    // the file after a redirection.
+    Redirect,
+}
+
+// a line being split into words by scan.
+struct Line {
+    // the word being typed, None between words.
+    word: Option<Word>,
+    // the words of the current command before it, starting with its name.
+    words: Vec<String>,
+    // whether the next word is the command's name, and whether it's the file of a redirection instead.
+    command: bool,
+    redirect: bool,
+}
+
+impl Line {
+    fn new() -> Line {
+        Line { word: None, words: Vec::new(), command: true, redirect: false }
+    }
+
+    fn word(&mut self, start: usize) -> &mut Word {
+        self.word.get_or_insert(Word { start, ..Word::default() })
+    }
+
+    // the word before the cursor has ended, so the next one is an argument unless this one didn't take the name's place.
+    fn end_word(&mut self) {
+        let Some(word) = self.word.take() else {
+            return;
+        };
+        if self.redirect {
+            self.redirect = false;
+        } else if !self.command || !(COMMAND_WORDS.contains(&word.value.as_str()) || is_assignment(&word.value)) {
+            self.command = false;
+            self.words.push(word.value);
+        }
+    }
+}
+
+/**
+* Splits a line up to the cursor into words the way the parser would, to find the last one, whether it's a command
+* name, and the words of its command before it. A new command starts after an operator like "|", ";" or "&&", after
+* "(", and after reserved words like "then" and "do". Assignments in front of a command don't count as its name.
+*/
+fn scan(source: &str) -> (Word, Position, Vec<String>) {
+    let mut tokenizer = Tokenizer::new(source);
+    let mut line = Line::new();
+    while let Some(token) = tokenizer.next_token() {
+        let start = tokenizer.last_start();
+        match token {
+            Token::Space => line.end_word(),
+            Token::IoNumber(_) => {},
+            Token::RedirectOutput
+            | Token::RedirectInput
+            | Token::Append
+            | Token::ReadWrite
+            | Token::DupOutput
+            | Token::DupInput
+            | Token::RedirectAll
+            | Token::AppendAll
+            | Token::HereDoc
+            | Token::HereDocStrip
+            | Token::HereString => {
+                line.end_word();
+                line.redirect = true;
+            },
+            Token::Pipe
+            | Token::Background
+            | Token::Semicolon
+            | Token::CaseBreak
+            | Token::CaseFallthrough
+            | Token::CaseContinue
+            | Token::And
+            | Token::Or
+            | Token::LeftParen
+            | Token::RightParen
+            | Token::Newline => line = Line::new(),
+            Token::Backslash => {
+                let word = line.word(start);
+                match tokenizer.next_char() {
+                    Some('\n') | None => {},
+                    Some(c) => word.value.push(c),
+                }
+            },
+            Token::SingleQuote | Token::DoubleQuote => {
+                let quote = if token == Token::SingleQuote { '\'' } else { '"' };
+                let word = line.word(start);
+                word.quote = Some((quote, start));
+                while let Some(c) = tokenizer.next_char() {
+                    if c == quote {
+                        word.quote = None;
+                        break;
+                    }
+                    // inside double quotes a backslash only escapes the characters that would mean something there.
+                    let escaped = match tokenizer.peek_char() {
+                        Some(next @ ('"' | '\\' | '$' | '`')) if quote == '"' && c == '\\' => Some(next),
+                        _ => None,
+                    };
+                    match escaped {
+                        Some(next) => {
+                            tokenizer.next_char();
+                            word.value.push(next);
+                        },
+                        None => word.value.push(c),
+                    }
+                }
+            },
+            token => line.word(start).value.push_str(&token.to_string()),
+        }
+    }
+
+    let position = if line.redirect {
+        Position::Redirect
+    } else if line.command {
+        Position::Command
+    } else {
+        Position::Argument
+    };
+    (line.word.unwrap_or(Word { start: source.len(), ..Word::default() }), position, line.words)
+}
+
+// NAME=value, which can go in front of a command.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| crate::variables::is_valid_name(name))
+}
+
+/**
+* A name that can go where the word being completed is, and whether it's a directory, which is followed by a "/"
+* rather than a space.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidate {
+    pub text: String,
+    pub directory: bool,
+}
+
+impl Candidate {
+    fn name(text: String) -> Candidate {
+        Candidate { text, directory: false }
+    }
+}
+
+/**
+* The candidates for the word at the end of a line, and where in the line the text they replace starts. After a "$"
+* or "${" that's a variable's name. An argument of a command with a completion spec is whatever the spec generates,
+* and a spec for a subcommand, like "git remote", wins over the command's. Otherwise a word starting with "%" is a
+* job, and one starting with "~" and no "/" yet is a user's home directory. The name of a command is a builtin,
+* function or program on $PATH, and anything else is a file. A finished name is followed by a space, closing the
+* quote it's in first, and a directory by a "/".
+*/
+pub fn complete(line: &str, state: &mut ShellState) -> (usize, Vec<Pair>) {
+    let (word, position, words) = scan(line);
+    let raw = &line[word.start..];
+
+    if let Some((offset, braced)) = variable_prefix(raw).filter(|_| !matches!(word.quote, Some(('\'', _)))) {
+        let close = if braced { "}" } else { "" };
+        let names = variables(&raw[offset..], state).into_iter();
+        let pairs = names.map(|name| Pair { replacement: format!("{}{}", name, close), display: name }).collect();
+        return (word.start + offset, pairs);
+    }
+
+    if position == Position::Argument {
+        // the command's name and the words after it that aren't options, like "git remote" for "git -p remote".
+        let names: Vec<&str> = words
+            .iter()
+            .enumerate()
+            .filter(|(idx, word)| *idx == 0 || !word.starts_with('-'))
+            .map(|(_, word)| word.as_str())
+            .collect();
+        let spec = (1..=names.len()).rev().find_map(|len| state.completions.get(&names[..len].join(" ")));
+        if let Some(spec) = spec.cloned() {
+            let candidates = generate(&spec, &word.value, &words, line, state);
+            return pairs(candidates, &word, raw);
+        }
+    }
+
+    if word.value.starts_with('%') && word.quote.is_none() {
+        let pairs = jobs(&word.value, state).into_iter().map(|(spec, command)| Pair {
+            display: format!("{}  {}", spec, command),
+            replacement: format!("{} ", spec),
+        });
+        return (word.start, pairs.collect());
+    }
+
+    if let Some(prefix) = word.value.strip_prefix('~').filter(|prefix| !prefix.contains('/') && word.quote.is_none()) {
+        let pairs = users(prefix).into_iter().map(|user| Pair {
+            display: format!("~{}", user),
+            replacement: format!("~{}/", escape(&user)),
+        });
+        return (word.start, pairs.collect());
+    }
+
+    if position == Position::Command && !word.value.contains('/') {
+        return pairs(commands(&word.value, state).into_iter().map(Candidate::name).collect(), &word, raw);
+    }
+
+    let mut candidates = paths(&word.value, state);
+    // a command given by its path is a program or a directory on the way to one.
+    if position == Position::Command {
+        let dir = expand_dir(&word.value[..dir_len(&word.value)], state);
+        candidates.retain(|candidate| {
+            let name = &candidate.text[dir_len(&candidate.text)..];
+            candidate.directory || is_executable(&Path::new(&dir).join(name))
+        });
+    }
+    pairs(candidates, &word, raw)
+}
+
+/**
+* The candidates a completion spec generates for a word: the names of each of its actions that start with the word,
+* the words in its list that do, and every line its function prints, see call. Its filter then takes out those that
+* match, or with a leading "!" those that don't, keeping directories. They come back sorted.
+*/
+pub fn generate(spec: &Spec, word: &str, words: &[String], line: &str, state: &mut ShellState) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    for action in &spec.actions {
+        match action {
+            Action::File => candidates.extend(paths(word, state)),
+            Action::Directory => candidates.extend(paths(word, state).into_iter().filter(|candidate| candidate.directory)),
+            Action::Command => candidates.extend(commands(word, state).into_iter().map(Candidate::name)),
+            Action::Variable => candidates.extend(variables(word, state).into_iter().map(Candidate::name)),
+            Action::User => candidates.extend(users(word).into_iter().map(Candidate::name)),
+            Action::Job => candidates.extend(jobs(word, state).into_iter().map(|(spec, _)| Candidate::name(spec))),
+        }
+    }
+    if let Some(list) = &spec.words {
+        let matching = list.split_whitespace().filter(|text| text.starts_with(word));
+        candidates.extend(matching.map(|text| Candidate::name(text.to_string())));
+    }
+    if let Some(function) = &spec.function {
+        candidates.extend(call(function, word, words, line, state).into_iter().map(Candidate::name));
+    }
+
+    if let Some(filter) = &spec.filter {
+        let (keep, pattern) = match filter.strip_prefix('!') {
+            Some(pattern) => (true, Pattern::new(pattern)),
+            None => (false, Pattern::new(filter)),
+        };
+        candidates.retain(|candidate| candidate.directory || pattern.matches(&candidate.text) == keep);
+    }
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/**
+* Runs a completion function in a forked copy of the shell, the way command substitution does, and takes each line
+* it prints as a candidate. Its arguments are the command's name, the word being completed and the word before it,
+* and COMP_LINE, COMP_POINT and COMP_CWORD hold the line up to the cursor, the cursor's offset and the word's number.
+*/
+fn call(function: &str, word: &str, words: &[String], line: &str, state: &mut ShellState) -> Vec<String> {
+    if !state.functions.contains_key(function) {
+        return Vec::new();
+    }
+    let command = words.first().map_or("", String::as_str);
+    let previous = words.last().map_or("", String::as_str);
+    let source = [function, command, word, previous].map(spec::quote).join(" ");
+    let Ok(root) = Parser::new(&source).parse() else {
+        return Vec::new();
+    };
+
+    let comp_vars = [("COMP_LINE", line.to_string()), ("COMP_POINT", line.len().to_string()), ("COMP_CWORD", words.len().to_string())];
+    let saved: Vec<_> = comp_vars.iter().map(|(name, _)| state.vars.get_var(name).cloned()).collect();
+    for (name, value) in &comp_vars {
+        state.vars.set(name, value);
+    }
+    let output = Engine::new(state).capture(&root).map(|(output, _)| output).unwrap_or_default();
+    for ((name, _), var) in comp_vars.iter().zip(saved) {
+        match var {
+            Some(var) => state.vars.insert(name, var),
+            None => {
+                state.vars.unset(name);
+            },
+        }
+    }
+    output.lines().filter(|line| !line.is_empty()).map(String::from).collect()
+}
+
+/**
+* The pairs for the line editor. When every candidate is in the directory the word names so far, like the files in
+* it, only the part after its last "/" is replaced, which keeps a "~" or "$HOME" in it the way it was typed.
+*/
+fn pairs(candidates: Vec<Candidate>, word: &Word, raw: &str) -> (usize, Vec<Pair>) {
+    let dir = &word.value[..dir_len(&word.value)];
+    let (start, skip) = match candidates.iter().all(|candidate| candidate.text.starts_with(dir)) {
+        true => (word.start + dir_len(raw), dir.len()),
+        false => (word.start, 0),
+    };
+    let pairs = candidates.into_iter().map(|candidate| {
+        let name = &candidate.text[skip..];
+        if candidate.directory {
+            Pair { display: format!("{}/", name), replacement: format!("{}/", replacement(name, word, start, false)) }
+        } else {
+            Pair { display: name.to_string(), replacement: replacement(name, word, start, true) }
+        }
+    });
+    (start, pairs.collect())
+}
+
+// how much of a word is the directory part, up to and including the last "/".
+fn dir_len(word: &str) -> usize {
+    word.rfind('/').map_or(0, |idx| idx + 1)
+}
+
+// where the name in a trailing "$name" or "${name" starts, and whether it's braced.
+fn variable_prefix(raw: &str) -> Option<(usize, bool)> {
+    let name_len = raw.chars().rev().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count();
+    let start = raw.len() - name_len;
+    let before = &raw[..start];
+    if before.ends_with("${") {
+        Some((start, true))
+    } else if before.ends_with('$') && !before.ends_with("\\$") {
+        Some((start, false))
+    } else {
+        None
+    }
+}
+
+/**
+* The files whose paths start with a word, written the way the word is: the ones in the directory it names so far
+* whose names start with the rest of it. Names starting with "." only show up once the word's does too.
+*/
+fn paths(word: &str, state: &mut ShellState) -> Vec<Candidate> {
+    let (dir, prefix) = word.split_at(dir_len(word));
+    let listed = if dir.is_empty() { ".".to_string() } else { expand_dir(dir, state) };
+    let Ok(entries) = fs::read_dir(&listed) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<Candidate> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            Some(Candidate { text: format!("{}{}", dir, name), directory: entry.path().is_dir() })
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+// the directory part of a word as a path to list: a leading "~" or "~user" and "$NAME" or "${NAME}" are expanded.
+fn expand_dir(dir: &str, state: &mut ShellState) -> String {
+    let dir = match dir.strip_prefix('~') {
+        Some(rest) => {
+            let end = rest.find('/').unwrap_or(rest.len());
+            match Expander::new(state).tilde(&rest[..end]) {
+                Some(home) => format!("{}{}", home, &rest[end..]),
+                None => dir.to_string(),
+            }
+        },
+        None => dir.to_string(),
+    };
+
+    let mut expanded = String::with_capacity(dir.len());
+    let mut rest = dir.as_str();
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        let braced = after.starts_with('{');
+        let name_start = usize::from(braced);
+        let name_len = after[name_start..].chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count();
+        let name = &after[name_start..name_start + name_len];
+        let end = name_start + name_len + usize::from(braced && after[name_start + name_len..].starts_with('}'));
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(state.vars.get(name).unwrap_or(""));
+        }
+        rest = &after[end..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/**
+* What replaces the word from "start" on for a name: the name quoted the way the word is, so a quote opened in the
+* part being replaced is opened again. A finished name is followed by a space, after closing its quote.
+*/
+fn replacement(name: &str, word: &Word, start: usize, finished: bool) -> String {
+    let mut text = String::new();
+    match word.quote {
+        Some((quote, opened)) => {
+            if opened >= start {
+                text.push(quote);
+            }
+            match quote {
+                '\'' => text.push_str(&name.replace('\'', "'\\''")),
+                _ => name.chars().for_each(|c| {
+                    if matches!(c, '"' | '\\' | '$' | '`') {
+                        text.push('\\');
+                    }
+                    text.push(c);
+                }),
+            }
+            if finished {
+                text.push(quote);
+            }
+        },
+        None => text.push_str(&escape(name)),
+    }
+    if finished {
+        text.push(' ');
+    }
+    text
+}
+
+// a name with backslashes in front of the characters that would otherwise mean something to the shell.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for (idx, c) in name.chars().enumerate() {
+        if SPECIAL.contains(c) || (idx == 0 && matches!(c, '~' | '#')) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// the builtins, functions and programs on $PATH whose names start with prefix.
+fn commands(prefix: &str, state: &ShellState) -> Vec<String> {
+    let mut names: BTreeSet<String> = builtins::names().into_iter().map(String::from).collect();
+    names.extend(state.functions.keys().cloned());
+    names.extend(executables(prefix, state));
+    names.into_iter().filter(|name| name.starts_with(prefix)).collect()
+}
+
+fn variables(prefix: &str, state: &ShellState) -> Vec<String> {
+    state.vars.names().into_iter().filter(|name| name.starts_with(prefix)).map(String::from).collect()
+}
+
+// the specs of the jobs a word starting with "%" can be, by number or by the start of the command, with their commands.
+fn jobs(word: &str, state: &ShellState) -> Vec<(String, String)> {
+    let prefix = word.strip_prefix('%').unwrap_or(word);
+    let jobs = state.jobs.ids().into_iter().filter_map(|id| {
+        let job = state.jobs.get(id)?;
+        let spec = format!("%{}", id);
+        (spec.starts_with(word) || job.command.starts_with(prefix)).then(|| (spec, job.command.clone()))
+    });
+    jobs.collect()
+}
+
+// the programs in the directories on $PATH whose names start with prefix.
+fn executables(prefix: &str, state: &ShellState) -> Vec<String> {
+    let path = state.vars.get("PATH").unwrap_or("");
+    let mut names = Vec::new();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+// the names of the users in the password database that start with prefix.
+fn users(prefix: &str) -> Vec<String> {
+    let mut users = BTreeSet::new();
+    // safety: getpwent's entries are only read before the next call, and the name is copied out.
+    unsafe {
+        libc::setpwent();
+        loop {
+            let entry = libc::getpwent();
+            if entry.is_null() {
+                break;
+            }
+            if !(*entry).pw_name.is_null() {
+                users.insert(CStr::from_ptr((*entry).pw_name).to_string_lossy().into_owned());
+            }
+        }
+        libc::endpwent();
+    }
+    users.into_iter().filter(|user| user.starts_with(prefix)).collect()
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::parser::Parser;
+    use std::rc::Rc;
+
+    fn replacements(line: &str, state: &mut ShellState) -> (usize, Vec<String>) {
+        let (start, pairs) = complete(line, state);
+        (start, pairs.into_iter().map(|pair| pair.replacement).collect())
+    }
+
+    #[test]
+    fn test_complete_scan() {
+        let word = |value: &str, start: usize, quote: Option<(char, usize)>| Word { start, value: value.to_string(), quote };
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<String>>();
+        assert_eq!(scan("ec"), (word("ec", 0, None), Position::Command, words(&[])));
+        assert_eq!(scan("ls -l | gr"), (word("gr", 8, None), Position::Command, words(&[])));
+        assert_eq!(scan("FOO=1 if true; then ma"), (word("ma", 20, None), Position::Command, words(&[])));
+        assert_eq!(scan("cat my\\ fi"), (word("my fi", 4, None), Position::Argument, words(&["cat"])));
+        assert_eq!(scan("cat 'a b'c\"d \\\"e"), (word("a bcd \"e", 4, Some(('"', 10))), Position::Argument, words(&["cat"])));
+        assert_eq!(scan("echo hi >"), (word("", 9, None), Position::Redirect, words(&["echo", "hi"])));
+        assert_eq!(scan("2> err cm"), (word("cm", 7, None), Position::Command, words(&[])));
+        assert_eq!(scan("echo "), (word("", 5, None), Position::Argument, words(&["echo"])));
+        assert_eq!(scan("X=1 git -C dir remote a=b > out re"), (word("re", 32, None), Position::Argument, words(&["git", "-C", "dir", "remote", "a=b"])));
+    }
+
+    #[test]
+    fn test_complete_files() {
+        let dir = std::env::temp_dir().join(format!("rsh-complete-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        for name in ["notes.txt", "it's", ".hidden"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        fs::write(dir.join("run.sh"), "").unwrap();
+        fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        let mut state = ShellState::default();
+        state.vars.set("DIR", &dir.to_string_lossy());
+        let dir = dir.to_string_lossy().into_owned();
+
+        let line = format!("cat {}/", dir);
+        assert_eq!(replacements(&line, &mut state), (line.len(), vec!["it\\'s ".to_string(), "notes.txt ".to_string(), "run.sh ".to_string(), "sub\\ dir/".to_string()]));
+        let line = format!("cat \"{}/su", dir);
+        assert_eq!(replacements(&line, &mut state), (line.len() - 2, vec!["sub dir/".to_string()]));
+        let line = format!("cat {}/'it", dir);
+        assert_eq!(replacements(&line, &mut state), (line.len() - 3, vec!["'it'\\''s' ".to_string()]));
+        assert_eq!(replacements("cat ${DIR}/.h", &mut state).1, vec![".hidden "]);
+        let line = format!("{}/", dir);
+        assert_eq!(replacements(&line, &mut state).1, vec!["run.sh ", "sub\\ dir/"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_complete_names() {
+        let mut state = ShellState::default();
+        state.vars.set("PATH", "");
+        state.vars.set("RSH_TEST_ONE", "1");
+        state.vars.set("RSH_TEST_TWO", "2");
+        state.functions.insert("greet".to_string(), Rc::new(Parser::new("echo hi").parse().unwrap()));
+
+        assert_eq!(replacements("exp", &mut state), (0, vec!["export ".to_string()]));
+        assert_eq!(replacements("true && gr", &mut state), (8, vec!["greet ".to_string()]));
+        assert_eq!(replacements("echo $RSH_TEST_", &mut state), (6, vec!["RSH_TEST_ONE".to_string(), "RSH_TEST_TWO".to_string()]));
+        assert_eq!(replacements("echo \"${RSH_TEST_O", &mut state), (8, vec!["RSH_TEST_ONE}".to_string()]));
+        assert_eq!(replacements("echo '$RSH", &mut state).1, Vec::<String>::new());
+        assert_eq!(replacements("fg %", &mut state), (3, Vec::new()));
+        assert!(replacements("ls ~roo", &mut state).1.contains(&"~root/".to_string()));
+    }
+
+    #[test]
+    fn test_complete_specs() {
+        let dir = std::env::temp_dir().join(format!("rsh-complete-specs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        for name in ["app.yaml", "notes.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let mut state = ShellState::default();
+        state.completions.insert("deploy".to_string(), Spec { words: Some("build release".to_string()), ..Spec::default() });
+        state.completions.insert("deploy release".to_string(), Spec { words: Some("staging production".to_string()), ..Spec::default() });
+        let config = Spec { actions: vec![Action::File], filter: Some("!*.yaml".to_string()), ..Spec::default() };
+        state.completions.insert("config".to_string(), config);
+        state.completions.insert("into".to_string(), Spec { actions: vec![Action::Directory], ..Spec::default() });
+        let dir = dir.to_string_lossy().into_owned();
+
+        assert_eq!(replacements("deploy ", &mut state), (7, vec!["build ".to_string(), "release ".to_string()]));
+        assert_eq!(replacements("X=1 deploy -v release st", &mut state), (22, vec!["staging ".to_string()]));
+        assert_eq!(replacements("deploy > rel", &mut state).1, Vec::<String>::new());
+        let line = format!("config {}/", dir);
+        assert_eq!(replacements(&line, &mut state), (line.len(), vec!["app.yaml ".to_string(), "conf.d/".to_string()]));
+        let line = format!("into {}/", dir);
+        assert_eq!(replacements(&line, &mut state).1, vec!["conf.d/"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::state::ShellState;
+use crate::variables::Variables;
+use std::fmt::{ self, Display, Formatter };
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+/**
+* A kind of name a spec completes: files, directories only, command names, variable names, user names or job
+* specs. Each has a letter, the option that asks for it in complete and compgen, and a key in a spec file.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    File,
+    Directory,
+    Command,
+    Variable,
+    User,
+    Job,
+}
+
+const ACTIONS: [(Action, char, &str); 6] = [
+    (Action::File, 'f', "files"),
+    (Action::Directory, 'd', "directories"),
+    (Action::Command, 'c', "commands"),
+    (Action::Variable, 'v', "variables"),
+    (Action::User, 'u', "users"),
+    (Action::Job, 'j', "jobs"),
+];
+
+impl Action {
+    pub fn from_letter(letter: char) -> Option<Action> {
+        ACTIONS.iter().find(|(_, other, _)| *other == letter).map(|(action, _, _)| *action)
+    }
+
+    pub fn letter(self) -> char {
+        ACTIONS.iter().find(|(other, _, _)| *other == self).map_or('?', |(_, letter, _)| *letter)
+    }
+
+    fn from_key(key: &str) -> Option<Action> {
+        ACTIONS.iter().find(|(_, _, other)| *other == key).map(|(action, _, _)| *action)
+    }
+}
+
+/**
+* How the arguments of a command are completed: the kinds of names to offer, a list of words split on whitespace,
+* and a function whose output lines are more candidates. A filter pattern then removes the candidates matching it,
+* or with a leading "!" those that don't, though directories are kept so that there's a way to reach the files.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Spec {
+    pub actions: Vec<Action>,
+    pub words: Option<String>,
+    pub function: Option<String>,
+    pub filter: Option<String>,
+}
+
+impl Spec {
+    pub fn add(&mut self, action: Action) {
+        if !self.actions.contains(&action) {
+            self.actions.push(action);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Spec::default()
+    }
+}
+
+// the spec as the options to complete that would make it again, e.g. "-f -X '!*.txt'".
+impl Display for Spec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if !self.actions.is_empty() {
+            options.push(format!("-{}", self.actions.iter().map(|action| action.letter()).collect::<String>()));
+        }
+        if let Some(words) = &self.words {
+            options.push(format!("-W {}", quote(words)));
+        }
+        if let Some(function) = &self.function {
+            options.push(format!("-F {}", quote(function)));
+        }
+        if let Some(filter) = &self.filter {
+            options.push(format!("-X {}", quote(filter)));
+        }
+        write!(f, "{}", options.join(" "))
+    }
+}
+
+// text in single quotes, so that it reads back as it is.
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// what's wrong with a line of a spec file, and its number.
+pub type LineError = (usize, String);
+
+/**
+* Reads a spec file. Each spec starts with the command it's for in brackets, and a subcommand can have a spec of
+* its own, like "[git remote]". The lines after it are either the name of an action, like "files" or "directories",
+* or "words = ...", "function = ..." or "filter = ...". Blank lines and lines starting with "#" are skipped. The
+* specs that were read come back along with what was wrong with the other lines, by line number.
+*
+*   [deploy]
+*   words = build test release
+*   [deploy release]
+*   words = staging production
+*   [deploy-config]
+*   files
+*   filter = !*.yaml
+*/
+pub fn parse(contents: &str) -> (Vec<(String, Spec)>, Vec<LineError>) {
+    let mut specs: Vec<(String, Spec)> = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(command) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            let command = command.split_whitespace().collect::<Vec<&str>>().join(" ");
+            if command.is_empty() {
+                errors.push((idx + 1, "missing command name".to_string()));
+            } else {
+                specs.push((command, Spec::default()));
+            }
+            continue;
+        }
+
+        let Some((_, spec)) = specs.last_mut() else {
+            errors.push((idx + 1, "not in a [command] section".to_string()));
+            continue;
+        };
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+            None => (line, None),
+        };
+        match (key, value) {
+            ("words", Some(words)) => spec.words = Some(words),
+            ("function", Some(function)) => spec.function = Some(function),
+            ("filter", Some(filter)) => spec.filter = Some(filter),
+            ("words" | "function" | "filter", None) => errors.push((idx + 1, format!("{}: missing value", key))),
+            (key, value) => match Action::from_key(key) {
+                Some(action) if value.is_none() => spec.add(action),
+                Some(_) => errors.push((idx + 1, format!("{}: takes no value", key))),
+                None => errors.push((idx + 1, format!("{}: unknown key", key))),
+            },
+        }
+    }
+    (specs, errors)
+}
+
+/**
+* Where spec files are loaded from at startup: $RSH_COMPLETIONS, a colon separated list, or "rsh/completions" under
+* $XDG_CONFIG_HOME, which is ~/.config when it isn't set. A directory stands for all of the files in it.
+*/
+pub fn default_paths(vars: &Variables) -> Vec<PathBuf> {
+    if let Some(paths) = vars.get("RSH_COMPLETIONS") {
+        return paths.split(':').filter(|path| !path.is_empty()).map(PathBuf::from).collect();
+    }
+    let config = match vars.get("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => PathBuf::from(config),
+        _ => match vars.get("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => return Vec::new(),
+        },
+    };
+    vec![config.join("rsh/completions")]
+}
+
+// adds the specs in the spec files to the shell's, reporting the lines that are wrong. Missing files are fine.
+pub fn load(state: &mut ShellState) {
+    for path in default_paths(&state.vars) {
+        let files = match fs::read_dir(&path) {
+            Ok(entries) => {
+                let mut files: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+                files.sort();
+                files
+            },
+            Err(_) => vec![path],
+        };
+        for file in files {
+            load_file(&file, state);
+        }
+    }
+}
+
+fn load_file(path: &Path, state: &mut ShellState) {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+        Err(err) => {
+            eprintln!("rsh: {}: {}", path.display(), crate::engine::error_message(&err));
+            return;
+        },
+    };
+    let (specs, errors) = parse(&contents);
+    for (line, message) in errors {
+        eprintln!("rsh: {}:{}: {}", path.display(), line, message);
+    }
+    state.completions.extend(specs);
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_spec_parse() {
+        let contents = "\
+            # deploy's completions\n\
+            [deploy]\n\
+            words = build test release\n\
+            \n\
+            [deploy   release]\n\
+            words = staging production\n\
+            directories\n\
+            files\n\
+            filter = !*.yaml\n\
+            colour = blue\n\
+            function\n";
+        let (specs, errors) = parse(contents);
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0], ("deploy".to_string(), Spec { words: Some("build test release".to_string()), ..Spec::default() }));
+        assert_eq!(specs[1].0, "deploy release");
+        assert_eq!(specs[1].1.to_string(), "-df -W 'staging production' -X '!*.yaml'");
+        assert_eq!(errors, vec![(10, "colour: unknown key".to_string()), (11, "function: missing value".to_string())]);
+
+        assert_eq!(parse("files\n").1, vec![(1, "not in a [command] section".to_string())]);
+    }
+
+    #[test]
+    fn test_spec_default_paths() {
+        let mut vars = Variables::new();
+        vars.set("HOME", "/home/me");
+        assert_eq!(default_paths(&vars), vec![PathBuf::from("/home/me/.config/rsh/completions")]);
+        vars.set("RSH_COMPLETIONS", "/a:/b/c");
+        assert_eq!(default_paths(&vars), vec![PathBuf::from("/a"), PathBuf::from("/b/c")]);
+    }
+}
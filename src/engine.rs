@@ -1,162 +1,947 @@
-use crate::parser::{ RshNode, RedirectMode };
-use std::process::{ Child, ChildStdout, Command, Stdio };
-use std::io::{ self };
-use std::fs::{ File, OpenOptions };
+use crate::builtins::{ self, Builtin };
+use crate::expand::{ ExpandError, Expander };
+use crate::jobs::{ Job, Process, ProcessGroup };
+use crate::location;
+use crate::parser::{ Assignment, CaseEnd, CaseItem, Parser, Redirect, RedirectMode, RshNode, Word };
+use crate::pattern::Pattern;
+use crate::redirect::{ self, FdAction, RedirectPlan, SavedFds };
+use crate::signals;
+use crate::state::{ Jump, ShellState };
+use crate::traps::Trap;
+use std::process::{ Command, ExitStatus };
+use std::rc::Rc;
+use std::os::fd::{ AsRawFd, OwnedFd };
+use std::os::unix::process::{ CommandExt, ExitStatusExt };
+use std::io::{ self, PipeReader, PipeWriter, Read, Write };
 
 /**
-* The context that the engine will use to execute commands. Keeps track of the number of commands executed
-keeps refs to all of the children, and other useful information.
+* The result of running a command. A process either exits with a code or is terminated by a signal,
+* which we keep separate so callers can report it properly. A job stopped with ^Z also counts as a result,
+* since the shell stops waiting for it.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Exited(i32),
+    Signaled(i32),
+    Stopped(i32),
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        matches!(self, Outcome::Exited(0))
+    }
+
+    // the value used for $?, signals are reported as 128 + the signal number like other shells.
+    pub fn code(&self) -> i32 {
+        match self {
+            Outcome::Exited(code) => *code,
+            Outcome::Signaled(signal) | Outcome::Stopped(signal) => 128 + signal,
+        }
+    }
+}
+
+impl Default for Outcome {
+    fn default() -> Outcome {
+        Outcome::Exited(0)
+    }
+}
+
+impl From<ExitStatus> for Outcome {
+    fn from(status: ExitStatus) -> Outcome {
+        match (status.code(), status.signal(), status.stopped_signal()) {
+            (Some(code), _, _) => Outcome::Exited(code),
+            (None, Some(signal), _) => Outcome::Signaled(signal),
+            (None, None, Some(signal)) => Outcome::Stopped(signal),
+            (None, None, None) => Outcome::Exited(1),
+        }
+    }
+}
+
+/**
+* A single stage of a pipeline. Stages that couldn't be started (e.g. command not found) still take up
+* a slot so that the per-stage statuses line up with the commands the user typed.
 */
 #[derive(Debug)]
+pub enum Stage {
+    // a child process, either a program or a forked copy of the shell running a builtin.
+    Running(libc::pid_t),
+    Finished(Outcome),
+}
+
+/**
+* The context that the engine will use to execute commands. Keeps track of the number of commands executed
+* keeps refs to the stages of the pipeline currently running, and other useful information.
+*/
+#[derive(Debug, Default)]
 pub struct EngineCtx {
     command_count: u32,
-    should_pipe: bool,
-    pub children: Vec<Child>,
+    pub stages: Vec<Stage>,
+    // how many conditions we're inside of, like the left of "&&". A command failing there isn't an error for ERR.
+    pub condition: u32,
 }
 
 impl EngineCtx {
     pub fn new() -> EngineCtx {
         EngineCtx {
             command_count: 0,
-            should_pipe: false,
-            children: Vec::new(),
+            stages: Vec::new(),
+            condition: 0,
         }
     }
 
-    pub fn add_child(&mut self, child: Child) {
-        self.children.push(child);
+    pub fn add_stage(&mut self, stage: Stage) {
+        self.stages.push(stage);
         self.command_count += 1;
     }
 
-    pub fn get_child(&mut self, index: usize) -> Option<&Child> {
-        self.children.get(index)
+    pub fn command_count(&self) -> u32 {
+        self.command_count
     }
 
-    pub fn get_child_mut(&mut self, index: usize) -> Option<&mut Child> {
-        self.children.get_mut(index)
-    }
-
-    pub fn get_last_child(&mut self) -> Option<&Child> {
-        self.children.last()
+    // hands the stages of the current pipeline over as the processes of its job.
+    pub fn take_processes(&mut self) -> Vec<Process> {
+        self.stages.drain(..).map(|stage| match stage {
+            Stage::Running(pid) => Process::running(pid),
+            Stage::Finished(outcome) => Process::finished(outcome),
+        }).collect()
     }
+}
 
-    pub fn get_last_child_mut(&mut self) -> Option<&mut Child> {
-        self.children.last_mut()
-    }
+// how deep function calls can nest when FUNCNEST doesn't say, which keeps runaway recursion from overflowing the stack.
+const MAX_CALL_DEPTH: u32 = 1000;
 
-    pub fn take_last_child(&mut self) -> Option<Child> {
-        self.children.pop()
-    }
+/**
+* The stack the engine is given to run on. Every level of function call takes several kilobytes of it, more in a
+* debug build, so the 8 MiB a main thread usually gets runs out well before MAX_CALL_DEPTH, or a FUNCNEST above it.
+*/
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
 
-    pub fn should_pipe(&self) -> bool {
-        self.should_pipe
-    }
+// a command that runs inside the shell rather than as a program, a function or a builtin.
+enum Internal {
+    Function(Rc<RshNode>),
+    Builtin(&'static dyn Builtin),
+}
 
-    pub fn set_pipe(&mut self, should_pipe: bool) {
-        self.should_pipe = should_pipe;
-    }
+// a simple command after expansion.
+struct ExpandedCommand {
+    argv: Vec<String>,
+    env: Vec<(String, String)>,
+    // the outcome of the last command substitution in the words, if there was one.
+    substituted: Option<Outcome>,
 }
 
 // This module is the engine that takes a syntax tree and executes it.
 // it handles passing along io etc and any threading that needs to happen on the shell side. 
 // The "shell" in shell.rs is the orchestrator that manages startup and state of the program throughout. i.e., history, current working directory, etc.
-pub struct Engine {
-    root: RshNode,
+pub struct Engine<'sh> {
+    state: &'sh mut ShellState,
 }
 
-impl Engine {
-    pub fn new(root: RshNode) -> Engine {
+impl<'sh> Engine<'sh> {
+    pub fn new(state: &'sh mut ShellState) -> Engine<'sh> {
         Engine {
-            root: root,
+            state,
         }
     }
 
-    pub fn execute(&self) -> Result<EngineCtx, io::Error> {
+    /**
+    * Runs the tree to completion and returns the outcome of the last command, which is also
+    * recorded in the shell state.
+    */
+    pub fn execute(&mut self, root: &RshNode) -> Result<Outcome, io::Error> {
         let mut ctx = EngineCtx::new();
-        self.execute_node(&self.root, &mut ctx)?;
-        Ok(ctx)
+        self.execute_node(root, &mut ctx)
+    }
+
+    /**
+    * Runs the tree in a forked copy of the shell with its stdout going into a pipe, for command substitution.
+    * Returns everything it wrote minus any trailing newlines. Since it runs in a copy, assignments and the
+    * like made by the commands don't affect this shell.
+    */
+    pub fn capture(&mut self, root: &RshNode) -> Result<(String, Outcome), io::Error> {
+        let (mut reader, writer) = io::pipe()?;
+
+        let pid = self.fork(None, move |engine| {
+            // safety: only duplicates an fd we own over stdout.
+            if unsafe { libc::dup2(writer.as_raw_fd(), 1) } < 0 {
+                return 1;
+            }
+            drop(writer);
+            match engine.execute(root) {
+                Ok(outcome) => outcome.code(),
+                Err(err) => {
+                    eprintln!("{}: {}", location::prefix(), error_message(&err));
+                    1
+                },
+            }
+        })?;
+
+        // the child's copy of the write end is the only one left now, so reading stops when it exits.
+        let mut output = Vec::new();
+        let read = reader.read_to_end(&mut output);
+        // the child is reaped even if reading failed.
+        let outcome = wait_pid(pid)?;
+        read?;
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        self.state.last_status = outcome;
+        Ok((output, outcome))
+    }
+
+    /**
+    * Forks a copy of the shell that runs "body" and exits with the status it returns. The copy has its own
+    * state from then on, so nothing it does is seen by us. The closure is dropped unrun in the parent, which
+    * closes any fds that were moved into it. A copy that's part of a job goes into the job's process group.
+    */
+    fn fork<F: FnOnce(&mut Engine) -> i32>(&mut self, group: Option<ProcessGroup>, body: F) -> Result<libc::pid_t, io::Error> {
+        // anything still buffered would otherwise be written twice, once by each process.
+        io::stdout().flush()?;
+
+        // safety: the child only gets a copy of the thread that forked. The shell's one other thread, main's, is parked
+        // in join with every signal blocked and holds no locks, so none are left taken in the child and it's free to
+        // carry on running rust code. Under the test harness other threads could hold one, e.g. stdout's, so the
+        // tests that fork only run commands that don't print from the shell itself.
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if pid == 0 {
+            self.state.jobs.disable();
+            self.state.traps.reset();
+            if let Some(group) = group {
+                group.enter();
+                signals::reset();
+            }
+            // an EXIT trap the copy set runs as it leaves, with its status as $?, and an exit in it has the last word.
+            let code = body(self);
+            self.state.last_status = Outcome::Exited(code);
+            self.run_exit_trap();
+            let code = self.state.last_status.code();
+            let _ = io::stdout().flush();
+            // safety: leaves without running destructors or exit handlers that belong to the parent.
+            unsafe { libc::_exit(code) };
+        }
+
+        Ok(pid)
     }
 
-    fn execute_node(&self, root: &RshNode, ctx: &mut EngineCtx) -> Result<(), io::Error> {
+    fn execute_node(&mut self, root: &RshNode, ctx: &mut EngineCtx) -> Result<Outcome, io::Error> {
+        // after "exit" the rest of the command line is skipped, and after "break" the rest of the loop body.
+        if self.interrupted() {
+            return Ok(self.state.last_status);
+        }
+
         match root {
-            RshNode::Command { name, args } => {
-                if ctx.should_pipe() {
-                    if ctx.children.len() > 0 {
-                        self.execute_cmd_pipe(name, args, ctx)?;
-                    } else {
-                        self.execute_cmd_std(name, args, ctx)?;
-                    }
-                } else {
-                    self.execute_cmd_inherit(name, args, ctx)?;
+            RshNode::Command { .. } | RshNode::Pipe { .. } | RshNode::Arith { .. } | RshNode::Subshell { .. } => {
+                let mut stages = Vec::new();
+                pipeline_stages(root, &mut stages);
+                self.run_trap(Trap::Debug);
+                let outcome = self.execute_pipeline(&stages, false, ctx)?;
+                if !outcome.success() && ctx.condition == 0 {
+                    self.run_trap(Trap::Err);
                 }
+                self.run_signal_traps();
+                Ok(outcome)
             },
 
-            RshNode::Redirect { command, file, mode } => {
-                let name = command.get_name().unwrap();
-                let args = command.get_args().unwrap();
-                self.execute_cmd_redir(name, args, file, mode, ctx)?; 
+            RshNode::Background { command } => match command.as_ref() {
+                RshNode::Command { .. } | RshNode::Pipe { .. } | RshNode::Arith { .. } | RshNode::Subshell { .. } => {
+                    let mut stages = Vec::new();
+                    pipeline_stages(command, &mut stages);
+                    self.execute_pipeline(&stages, true, ctx)
+                },
+                _ => self.execute_background(command),
             },
 
-            RshNode::Pipe { left, right } => {
-                ctx.set_pipe(true);
+            RshNode::Sequence { left, right } => {
                 self.execute_node(left, ctx)?;
-                self.execute_node(right, ctx)?;
-                ctx.set_pipe(false);
+                self.execute_node(right, ctx)
+            },
+
+            RshNode::And { left, right } => {
+                let outcome = self.execute_condition(left, ctx)?;
+                if !outcome.success() {
+                    return Ok(outcome);
+                }
+                self.execute_node(right, ctx)
+            },
+
+            RshNode::Or { left, right } => {
+                let outcome = self.execute_condition(left, ctx)?;
+                if outcome.success() {
+                    return Ok(outcome);
+                }
+                self.execute_node(right, ctx)
+            },
+
+            RshNode::If { branches, otherwise } => {
+                for (condition, body) in branches {
+                    let tested = self.execute_condition(condition, ctx)?;
+                    if self.interrupted() {
+                        return Ok(self.state.last_status);
+                    }
+                    if tested.success() {
+                        return self.execute_node(body, ctx);
+                    }
+                }
+                match otherwise {
+                    Some(otherwise) => self.execute_node(otherwise, ctx),
+                    // no condition held and there's no else, which still counts as success.
+                    None => Ok(self.finish(Outcome::default())),
+                }
+            },
+
+            RshNode::While { condition, body, until } => self.in_loop(|engine| {
+                let mut outcome = Outcome::default();
+                loop {
+                    let tested = engine.execute_condition(condition, ctx)?;
+                    if engine.leave_loop() || tested.success() == *until {
+                        break;
+                    }
+                    outcome = engine.execute_node(body, ctx)?;
+                    if engine.leave_loop() {
+                        break;
+                    }
+                }
+                Ok(outcome)
+            }),
+
+            RshNode::For { name, words, body } => {
+                let words: Vec<&Word> = words.iter().collect();
+                let values = match Expander::new(self.state).expand_words(&words) {
+                    Ok(values) => values,
+                    Err(err) => return Ok(self.expansion_failed(&err)),
+                };
+
+                self.in_loop(|engine| {
+                    let mut outcome = Outcome::default();
+                    for value in values {
+                        engine.state.vars.set(name, &value);
+                        outcome = engine.execute_node(body, ctx)?;
+                        if engine.leave_loop() {
+                            break;
+                        }
+                    }
+                    Ok(outcome)
+                })
+            },
+
+            RshNode::ArithFor { init, condition, step, body } => self.in_loop(|engine| {
+                let mut outcome = Outcome::default();
+                if engine.loop_arith(init).is_none() {
+                    return Ok(Outcome::Exited(1));
+                }
+                loop {
+                    match engine.loop_arith(condition) {
+                        None => return Ok(Outcome::Exited(1)),
+                        Some(0) => break,
+                        Some(_) => {},
+                    }
+                    outcome = engine.execute_node(body, ctx)?;
+                    if engine.leave_loop() {
+                        break;
+                    }
+                    if engine.loop_arith(step).is_none() {
+                        return Ok(Outcome::Exited(1));
+                    }
+                }
+                Ok(outcome)
+            }),
+
+            RshNode::Case { word, items } => self.execute_case(word, items, ctx),
+
+            RshNode::Function { name, body } => {
+                self.state.functions.insert(name.clone(), Rc::new(body.as_ref().clone()));
+                Ok(self.finish(Outcome::default()))
+            },
+
+            RshNode::Group { body } => self.execute_node(body, ctx),
+
+            RshNode::Redirected { command, redirects } => self.execute_redirected(command, redirects, ctx),
+
+            // leaves $? as it was, like a blank line does.
+            RshNode::Empty => Ok(self.state.last_status),
+        }
+    }
+
+    // a compound command with its redirections applied for as long as it runs, undone afterwards.
+    fn execute_redirected(&mut self, command: &RshNode, redirects: &[Redirect], ctx: &mut EngineCtx) -> Result<Outcome, io::Error> {
+        let mut plan = RedirectPlan::new();
+        for redirect in redirects {
+            if let Err(message) = self.plan_redirect(&mut plan, redirect) {
+                eprintln!("{}: {}", location::prefix(), message);
+                return Ok(self.finish(Outcome::Exited(1)));
+            }
+        }
+
+        let actions = plan.actions();
+        let _ = io::stdout().flush();
+        let saved = SavedFds::save(&actions).and_then(|saved| redirect::apply_actions(&actions).map(|_| saved));
+        let saved = match saved {
+            Ok(saved) => saved,
+            Err(err) => {
+                eprintln!("{}: {}", location::prefix(), error_message(&err));
+                return Ok(self.finish(Outcome::Exited(1)));
+            },
+        };
+
+        let outcome = self.execute_node(command, ctx);
+        let _ = io::stdout().flush();
+        saved.restore();
+        drop(plan);
+        outcome
+    }
+
+    // whether exit, break, continue or a ^C has cut the current command short.
+    fn interrupted(&self) -> bool {
+        self.state.exit_requested || self.state.jump.is_some() || self.state.interrupted
+    }
+
+    /**
+    * Reports an expansion that couldn't be done, which fails the command it's in. A fatal one, like ${x:?}, ends a
+    * shell that isn't interactive with status 127, the way bash does.
+    */
+    fn expansion_failed(&mut self, err: &ExpandError) -> Outcome {
+        eprintln!("{}: {}", location::prefix(), err);
+        if err.fatal && !self.state.interactive {
+            self.state.last_status = Outcome::Exited(127);
+            self.state.exit_requested = true;
+            return Outcome::Exited(127);
+        }
+        self.finish(Outcome::Exited(1))
+    }
+
+    // records the outcome of a compound command as $?, unless exit has already settled what that is.
+    fn finish(&mut self, outcome: Outcome) -> Outcome {
+        if !self.state.exit_requested {
+            self.state.last_status = outcome;
+        }
+        outcome
+    }
+
+    // runs a loop, which break and continue inside of it are aimed at.
+    fn in_loop<F>(&mut self, run: F) -> Result<Outcome, io::Error>
+    where
+        F: FnOnce(&mut Engine<'sh>) -> Result<Outcome, io::Error>,
+    {
+        self.state.loop_depth += 1;
+        let outcome = run(self);
+        self.state.loop_depth -= 1;
+        Ok(self.finish(outcome?))
+    }
+
+    /**
+    * Checked by a loop after each part of a round. Returns true if the loop is over, because of a break, a ^C or
+    * an exit, or because of a continue that's meant for a loop further out. A continue for this loop is used up and the loop goes on.
+    */
+    fn leave_loop(&mut self) -> bool {
+        match self.state.jump {
+            None => self.state.exit_requested || self.state.interrupted,
+            Some(Jump::Break(n)) => {
+                self.state.jump = (n > 1).then(|| Jump::Break(n - 1));
+                true
+            },
+            Some(Jump::Continue(n)) => {
+                self.state.jump = (n > 1).then(|| Jump::Continue(n - 1));
+                n > 1
+            },
+            // left for the function call to pick up.
+            Some(Jump::Return) => true,
+        }
+    }
+
+    // one of the expressions of "for ((...))", None if it can't be evaluated, which ends the loop.
+    fn loop_arith(&mut self, expr: &Word) -> Option<i64> {
+        match Expander::new(self.state).expand_arith(expr) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                eprintln!("{}: {}", location::prefix(), err);
+                None
+            },
+        }
+    }
+
+    /**
+    * Runs the body of the first item with a pattern matching the word. The item's ending decides what's next:
+    * ";;" is the end of it, ";&" runs the next body as well, and ";;&" goes on trying the patterns after it.
+    */
+    fn execute_case(&mut self, word: &Word, items: &[CaseItem], ctx: &mut EngineCtx) -> Result<Outcome, io::Error> {
+        let text = match Expander::new(self.state).expand_string(word) {
+            Ok(text) => text,
+            Err(err) => return Ok(self.expansion_failed(&err)),
+        };
+
+        let mut outcome = Outcome::default();
+        let mut fallthrough = false;
+        for item in items {
+            if !fallthrough {
+                match self.case_matches(&text, &item.patterns) {
+                    Ok(true) => {},
+                    Ok(false) => continue,
+                    Err(err) => return Ok(self.expansion_failed(&err)),
+                }
+            }
+
+            if let Some(body) = &item.body {
+                outcome = self.execute_node(body, ctx)?;
+            }
+            match item.end {
+                CaseEnd::Break => break,
+                CaseEnd::Fallthrough => fallthrough = true,
+                CaseEnd::Continue => fallthrough = false,
+            }
+        }
+
+        Ok(self.finish(outcome))
+    }
+
+    fn case_matches(&mut self, text: &str, patterns: &[Word]) -> Result<bool, ExpandError> {
+        let mut expander = Expander::new(self.state);
+        for pattern in patterns {
+            if Pattern::new(&expander.expand_pattern(pattern)?).matches(text) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // runs a command whose status decides what runs next, where failing is just an answer.
+    fn execute_condition(&mut self, root: &RshNode, ctx: &mut EngineCtx) -> Result<Outcome, io::Error> {
+        ctx.condition += 1;
+        let outcome = self.execute_node(root, ctx);
+        ctx.condition -= 1;
+        outcome
+    }
+
+    /**
+    * Runs the command set for a trap, if there is one, leaving $? the way it was unless the command exits the shell.
+    * A trap's command doesn't set off other traps, and nothing runs once the shell is on its way out.
+    */
+    fn run_trap(&mut self, trap: Trap) {
+        if self.state.traps.running || self.state.exit_requested {
+            return;
+        }
+        let action = match self.state.traps.get(trap) {
+            Some(action) if !action.is_empty() => action.to_string(),
+            _ => return,
+        };
+
+        let status = self.state.last_status;
+        self.state.traps.running = true;
+        match Parser::new(&action).parse() {
+            Ok(root) => {
+                if let Err(err) = self.execute(&root) {
+                    eprintln!("{}: trap: {}", location::prefix(), error_message(&err));
+                }
             },
-            _ => { todo!("implement the rest of the node types"); }
+            Err(err) => eprintln!("{}: trap: {}", location::prefix(), err),
+        }
+        self.state.traps.running = false;
+
+        if !self.state.exit_requested {
+            self.state.last_status = status;
+        }
+    }
+
+    // runs the traps for every signal that has arrived since the last time, which happens between commands.
+    pub fn run_signal_traps(&mut self) {
+        if self.state.traps.running {
+            return;
+        }
+        for signal in signals::take_caught() {
+            self.run_trap(Trap::Signal(signal));
+        }
+    }
+
+    /**
+    * Runs the EXIT trap as the shell leaves, once. The shell exits with the status it was going to, unless the trap
+    * calls exit itself.
+    */
+    pub fn run_exit_trap(&mut self) {
+        if self.state.traps.get(Trap::Exit).is_none_or(|action| action.is_empty()) {
+            return;
         }
-        Ok(())
+
+        let exiting = self.state.exit_requested;
+        self.state.exit_requested = false;
+        self.run_trap(Trap::Exit);
+        self.state.traps.remove(Trap::Exit);
+        self.state.exit_requested |= exiting;
     }
 
-    fn execute_cmd_inherit(&self, name: &str, args: &[String], ctx: &mut EngineCtx) -> Result<(), io::Error> {
-        let mut command = self.setup_command(name, args); 
-        let child = command.spawn()?;
-        ctx.add_child(child);
-        Ok(())
+    // lists in the background run in a forked copy of the shell, which is the one process of their job.
+    fn execute_background(&mut self, root: &RshNode) -> Result<Outcome, io::Error> {
+        let mut group = self.state.jobs.process_group(false);
+        let pid = self.fork(group, |engine| match engine.execute(root) {
+            Ok(outcome) => outcome.code(),
+            Err(err) => {
+                eprintln!("{}: {}", location::prefix(), error_message(&err));
+                1
+            },
+        })?;
+
+        if let Some(group) = &mut group {
+            group.adopt(pid);
+        }
+        let job = Job::new(group.map_or(0, |group| group.pgid), vec![Process::running(pid)], root.to_string());
+        self.state.jobs.background(job);
+        self.state.last_status = Outcome::default();
+        Ok(Outcome::default())
     }
 
-    fn execute_cmd_std(&self, name: &str, args: &[String], ctx: &mut EngineCtx) -> Result<(), io::Error> {
-        let mut command = self.setup_command(name, args); 
-        self.setup_io::<Stdio>(&mut command, None, None, None);
-        let child = command.spawn()?;
-        ctx.add_child(child);
-        Ok(())
+    /**
+    * Starts every stage of a pipeline connected by pipes, then waits for all of them. The first stage reads
+    * from the shell's stdin and the last one writes to the shell's stdout. The per-stage outcomes are kept
+    * in the shell state, and the pipeline's outcome is the last stage's, or with "pipefail" the rightmost failure.
+    * The stages make up a job, which goes into the job table instead of being waited for in the background.
+    */
+    fn execute_pipeline(&mut self, stages: &[&RshNode], background: bool, ctx: &mut EngineCtx) -> Result<Outcome, io::Error> {
+        let mut stdin: Option<PipeReader> = None;
+        let mut group = self.state.jobs.process_group(!background);
+        // stages that could change the shell run in a copy of it instead, unless the pipeline is just them.
+        let fork = background || stages.len() > 1;
+
+        for (idx, node) in stages.iter().enumerate() {
+            let (next_stdin, stdout) = if idx + 1 < stages.len() {
+                let (reader, writer) = io::pipe()?;
+                (Some(reader), Some(writer))
+            } else {
+                (None, None)
+            };
+
+            // our copies of the pipe ends are dropped as soon as the stage is spawned, which lets
+            // each reader see EOF once the stage before it exits.
+            match self.spawn_stage(node, stdin.take(), stdout, fork, group) {
+                Ok(stage) => {
+                    if let (Some(group), Stage::Running(pid)) = (&mut group, &stage) {
+                        group.adopt(*pid);
+                    }
+                    ctx.add_stage(stage);
+                },
+                Err(err) => {
+                    let job = Job::new(0, ctx.take_processes(), String::new());
+                    self.state.jobs.foreground(job)?;
+                    return Err(err);
+                },
+            }
+            stdin = next_stdin;
+        }
+
+        let command = stages.iter().map(|stage| stage.to_string()).collect::<Vec<String>>().join(" | ");
+        let job = Job::new(group.map_or(0, |group| group.pgid), ctx.take_processes(), command);
+        if background {
+            self.state.jobs.background(job);
+            self.state.last_status = Outcome::default();
+            return Ok(Outcome::default());
+        }
+
+        let outcomes = self.state.jobs.foreground(job)?;
+        self.state.note_interrupt(&outcomes);
+        let last = outcomes.last().copied().unwrap_or_default();
+        let outcome = if self.state.options.pipefail {
+            outcomes.iter().rev().find(|o| !o.success()).copied().unwrap_or(last)
+        } else {
+            last
+        };
+
+        self.state.pipestatus = outcomes;
+        self.state.last_status = outcome;
+        Ok(outcome)
     }
 
-    fn execute_cmd_redir(&self, name: &str, args: &[String], file: &str, mode: &RedirectMode, ctx: &mut EngineCtx) -> Result<(), io::Error> {
-        let mut command = self.setup_command(name, args); 
+    fn spawn_stage(
+        &mut self,
+        node: &RshNode,
+        stdin: Option<PipeReader>,
+        stdout: Option<PipeWriter>,
+        fork: bool,
+        group: Option<ProcessGroup>,
+    ) -> Result<Stage, io::Error> {
+        match node {
+            RshNode::Command { assignments, name, args, redirects } => {
+                let ExpandedCommand { argv, env, substituted } = match self.expand_command(assignments, name, args) {
+                    Ok(expanded) => expanded,
+                    Err(err) => return Ok(Stage::Finished(self.expansion_failed(&err))),
+                };
 
-        match mode {
-            RedirectMode::Read => {
-                let file = File::open(file).expect("failed to open file");
-                self.setup_io::<File>(&mut command, Some(file), None, None);
+                let mut plan = RedirectPlan::new();
+                for redirect in redirects {
+                    if let Err(message) = self.plan_redirect(&mut plan, redirect) {
+                        eprintln!("{}: {}", location::prefix(), message);
+                        return Ok(Stage::Finished(Outcome::Exited(1)));
+                    }
+                }
+
+                // without a command, assignments set shell variables and redirects just open (or create) their files.
+                // the status is that of the last command substitution, so "x=$(false)" fails.
+                if argv.is_empty() {
+                    if !fork {
+                        for (name, value) in env {
+                            self.state.vars.set(&name, &value);
+                        }
+                    }
+                    return Ok(Stage::Finished(substituted.unwrap_or_default()));
+                }
+
+                // functions come first, so one can wrap a builtin or a program of the same name.
+                let internal = match self.state.functions.get(&argv[0]) {
+                    Some(body) => Some(Internal::Function(body.clone())),
+                    None => builtins::lookup(&argv[0]).map(Internal::Builtin),
+                };
+                if let Some(internal) = internal {
+                    return self.spawn_internal(internal, &argv, env, plan, stdin, stdout, fork.then_some(group));
+                }
+
+                // assignments in front of a command only go into that command's environment.
+                let mut command = self.setup_command(&argv[0], &argv[1..]);
+                command.env_clear();
+                command.envs(self.state.vars.exported());
+                command.envs(env);
+                self.setup_io(&mut command, stdin, stdout);
+
+                let actions = plan.actions();
+                // safety: entering the group, resetting signals and apply_actions only make async-signal-safe calls.
+                unsafe {
+                    command.pre_exec(move || {
+                        if let Some(group) = group {
+                            group.enter();
+                        }
+                        signals::reset();
+                        redirect::apply_actions(&actions)
+                    });
+                }
+
+                // the plan holds the opened files, so it has to outlive the spawn.
+                let stage = self.spawn(&argv[0], &mut command);
+                drop(plan);
+                stage
             },
-            RedirectMode::Write => {
-                let file = File::create(file).expect("failed to create file");
-                self.setup_io::<File>(&mut command, None, Some(file), None);
+
+            // nothing to start, so the stage is over as soon as the expression has been evaluated.
+            RshNode::Arith { expr } if !fork => Ok(Stage::Finished(self.arith_command(expr))),
+            RshNode::Arith { expr } => {
+                let pid = self.fork(group, |engine| engine.arith_command(expr).code())?;
+                Ok(Stage::Running(pid))
             },
-            RedirectMode::Append => {
-                let file = OpenOptions::new().append(true).open(file).expect("failed to open file");
-                self.setup_io::<File>(&mut command, Some(file), None, None);
+
+            // compound commands in a pipeline run in a forked copy of the shell, like builtins do.
+            _ => {
+                let pid = self.fork(group, move |engine| {
+                    if connect_pipes(stdin, stdout).is_err() {
+                        return 1;
+                    }
+                    // the fork is already the copy of the shell a subshell asks for.
+                    let node = match node {
+                        RshNode::Subshell { body } => body,
+                        _ => node,
+                    };
+                    match engine.execute(node) {
+                        Ok(outcome) => outcome.code(),
+                        Err(err) => {
+                            eprintln!("{}: {}", location::prefix(), error_message(&err));
+                            1
+                        },
+                    }
+                })?;
+                Ok(Stage::Running(pid))
             },
         }
+    }
+
+    /**
+    * Builtins and functions run in the shell itself so that they can change it, unless they're part of a pipeline
+    * or in the background. Then they run in a forked copy like the other stages, which keeps them from blocking on
+    * a full pipe before the stage that reads it has even started. "fork" has the process group for the copy, if any.
+    */
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_internal(
+        &mut self,
+        internal: Internal,
+        argv: &[String],
+        env: Vec<(String, String)>,
+        plan: RedirectPlan,
+        stdin: Option<PipeReader>,
+        stdout: Option<PipeWriter>,
+        fork: Option<Option<ProcessGroup>>,
+    ) -> Result<Stage, io::Error> {
+        let actions = plan.actions();
 
-        let child = command.spawn()?;
-        ctx.add_child(child);
-        Ok(())
+        if let Some(group) = fork {
+            let pid = self.fork(group, move |engine| {
+                if connect_pipes(stdin, stdout).is_err() {
+                    return 1;
+                }
+                engine.run_internal(&internal, argv, env, &actions).code()
+            })?;
+            drop(plan);
+            return Ok(Stage::Running(pid));
+        }
+
+        let outcome = self.run_internal(&internal, argv, env, &actions);
+        drop(plan);
+        Ok(Stage::Finished(outcome))
     }
 
-    fn execute_cmd_pipe(&self, name: &str, args: &[String], ctx: &mut EngineCtx) -> Result<(), io::Error> {
-        let mut command = self.setup_command(name, args); 
+    /**
+    * Runs a builtin or function here and now with its redirections applied, and undoes them afterwards. Assignments
+    * in front of it only last as long as it runs, like they would for any other command.
+    */
+    fn run_internal(&mut self, internal: &Internal, argv: &[String], env: Vec<(String, String)>, actions: &[FdAction]) -> Outcome {
+        let saved = if actions.is_empty() {
+            None
+        } else {
+            let _ = io::stdout().flush();
+            let saved = SavedFds::save(actions).and_then(|saved| redirect::apply_actions(actions).map(|_| saved));
+            match saved {
+                Ok(saved) => Some(saved),
+                Err(err) => {
+                    eprintln!("{}: {}: {}", location::prefix(), argv[0], error_message(&err));
+                    return Outcome::Exited(1);
+                },
+            }
+        };
+
+        let previous: Vec<_> = env.iter().map(|(name, _)| (name.clone(), self.state.vars.get_var(name).cloned())).collect();
+        for (name, value) in &env {
+            self.state.vars.set(name, value);
+        }
+
+        let outcome = match internal {
+            Internal::Function(body) => self.call_function(&argv[0], body, &argv[1..]),
+            Internal::Builtin(builtin) => Outcome::Exited(builtin.run(&argv[1..], self.state)),
+        };
+
+        for (name, var) in previous.into_iter().rev() {
+            match var {
+                Some(var) => self.state.vars.insert(&name, var),
+                None => {
+                    self.state.vars.unset(&name);
+                },
+            }
+        }
 
-        if let Some(last_child) = ctx.get_last_child_mut() {
-            let stdout = last_child.stdout.take().unwrap();
-            self.setup_io::<ChildStdout>(&mut command, Some(stdout), None, None);
-            let piped_child = command.spawn()?;
-            ctx.add_child(piped_child);
+        let _ = io::stdout().flush();
+        if let Some(saved) = saved {
+            saved.restore();
+        }
+        outcome
+    }
+
+    /**
+    * Runs the body of a function with the arguments as its positional parameters and a scope for its local
+    * variables. break and continue inside it can't reach the loops of the caller, and return ends it early.
+    */
+    fn call_function(&mut self, name: &str, body: &RshNode, args: &[String]) -> Outcome {
+        let limit = self.state.vars.get("FUNCNEST").and_then(|n| n.parse::<u32>().ok()).filter(|n| *n > 0);
+        let limit = limit.unwrap_or(MAX_CALL_DEPTH);
+        if self.state.call_depth >= limit {
+            eprintln!("{}: {}: maximum function nesting level exceeded ({})", location::prefix(), name, limit);
+            return Outcome::Exited(1);
+        }
+
+        let positional = std::mem::replace(&mut self.state.positional, args.to_vec());
+        let loop_depth = std::mem::take(&mut self.state.loop_depth);
+        self.state.vars.push_scope();
+        self.state.call_depth += 1;
+
+        let mut outcome = match self.execute(body) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                eprintln!("{}: {}: {}", location::prefix(), name, error_message(&err));
+                Outcome::Exited(1)
+            },
+        };
+        if self.state.jump == Some(Jump::Return) {
+            self.state.jump = None;
+            outcome = self.state.last_status;
+        }
+        self.finish(outcome);
+        self.run_trap(Trap::Return);
+
+        self.state.call_depth -= 1;
+        self.state.vars.pop_scope();
+        self.state.loop_depth = loop_depth;
+        self.state.positional = positional;
+        outcome
+    }
+
+    // ((expr)) succeeds when the expression is non-zero, and fails when it's zero or can't be evaluated.
+    fn arith_command(&mut self, expr: &Word) -> Outcome {
+        match Expander::new(self.state).expand_arith(expr) {
+            Ok(0) => Outcome::Exited(1),
+            Ok(_) => Outcome::Exited(0),
+            Err(err) => {
+                eprintln!("{}: {}", location::prefix(), err);
+                Outcome::Exited(1)
+            },
+        }
+    }
+
+    // expands the words of a simple command into its argv, along with the values of its assignments.
+    fn expand_command(&mut self, assignments: &[Assignment], name: &Word, args: &[Word]) -> Result<ExpandedCommand, ExpandError> {
+        let mut words: Vec<&Word> = Vec::new();
+        if !name.is_empty() {
+            words.push(name);
+        }
+        words.extend(args.iter());
+
+        let mut expander = Expander::new(self.state);
+        let argv = expander.expand_words(&words)?;
+        let mut env = Vec::new();
+        for assignment in assignments {
+            env.push((assignment.name.clone(), expander.expand_assignment(&assignment.value)?));
+        }
+
+        Ok(ExpandedCommand { argv, env, substituted: expander.substituted() })
+    }
+
+    // resolves one redirect into the plan, returning the message to show the user if it can't be done.
+    fn plan_redirect(&mut self, plan: &mut RedirectPlan, redirect: &Redirect) -> Result<(), String> {
+        let target = Expander::new(self.state).expand_string(&redirect.target).map_err(|err| err.message)?;
+
+        match redirect.mode {
+            RedirectMode::Duplicate => {
+                let src = target.parse::<i32>().map_err(|_| format!("{}: ambiguous redirect", target))?;
+                plan.duplicate(redirect.fd, src).map_err(|err| format!("{}: {}", src, error_message(&err)))
+            },
+            RedirectMode::Close => {
+                plan.close(redirect.fd);
+                Ok(())
+            },
+            RedirectMode::HereDoc => {
+                plan.here_document(redirect.fd, &target).map_err(|err| error_message(&err))
+            },
+            // here-strings get a trailing newline, like they do in bash.
+            RedirectMode::HereString => {
+                plan.here_document(redirect.fd, &format!("{}\n", target)).map_err(|err| error_message(&err))
+            },
+            _ => plan.open(redirect.fd, &target, &redirect.mode).map_err(|err| format!("{}: {}", target, error_message(&err))),
+        }
+    }
+
+    fn spawn(&self, name: &str, command: &mut Command) -> Result<Stage, io::Error> {
+        match command.spawn() {
+            // the child is waited for by pid from here on, so the handle can go.
+            Ok(child) => Ok(Stage::Running(child.id() as libc::pid_t)),
+            Err(err) => Ok(Stage::Finished(self.spawn_failure(name, err)?)),
+        }
+    }
+
+    // commands that can't be started still produce a status, the same ones other shells use.
+    fn spawn_failure(&self, name: &str, err: io::Error) -> Result<Outcome, io::Error> {
+        match err.kind() {
+            io::ErrorKind::NotFound => {
+                eprintln!("{}: {}: command not found", location::prefix(), name);
+                Ok(Outcome::Exited(127))
+            },
+            io::ErrorKind::PermissionDenied => {
+                eprintln!("{}: {}: permission denied", location::prefix(), name);
+                Ok(Outcome::Exited(126))
+            },
+            _ => Err(err),
         }
-        
-        return Ok(())
     }
 
     fn setup_command(&self, name: &str, args: &[String]) -> Command {
@@ -167,23 +952,133 @@ impl Engine {
         command
     }
 
-    fn setup_io<T: Into<Stdio>>(&self, command: &mut Command, stdin: Option<T>, stdout: Option<T>, stderr: Option<T>) {
-        if let Some(stdin) = stdin { 
-            command.stdin(stdin); 
-        } else { 
-            command.stdin(Stdio::piped()); 
+    // anything not connected to a pipe is inherited from the shell.
+    fn setup_io(&self, command: &mut Command, stdin: Option<PipeReader>, stdout: Option<PipeWriter>) {
+        if let Some(stdin) = stdin {
+            command.stdin(stdin);
+        }
+
+        if let Some(stdout) = stdout {
+            command.stdout(stdout);
+        }
+    }
+}
+
+// flattens a tree of pipes, a | (b | c), into its stages in order.
+fn pipeline_stages<'n>(node: &'n RshNode, stages: &mut Vec<&'n RshNode>) {
+    match node {
+        RshNode::Pipe { left, right } => {
+            pipeline_stages(left, stages);
+            pipeline_stages(right, stages);
+        },
+        _ => stages.push(node),
+    }
+}
+
+// puts the pipes a forked stage was given in place of its stdin and stdout.
+fn connect_pipes(stdin: Option<PipeReader>, stdout: Option<PipeWriter>) -> Result<(), io::Error> {
+    let pipes = [(stdin.map(OwnedFd::from), 0), (stdout.map(OwnedFd::from), 1)];
+    for (pipe, fd) in pipes {
+        // safety: only duplicates fds we own over stdin and stdout.
+        if let Some(pipe) = pipe {
+            if unsafe { libc::dup2(pipe.as_raw_fd(), fd) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
         }
+    }
+    Ok(())
+}
 
-        if let Some(stdout) = stdout { 
-            command.stdout(stdout); 
-        } else { 
-            command.stdout(Stdio::piped()); 
+// waits for a child we forked ourselves, retrying if a signal interrupts the wait.
+fn wait_pid(pid: libc::pid_t) -> Result<Outcome, io::Error> {
+    let mut status = 0;
+    loop {
+        // safety: status is a valid place for waitpid to write to.
+        if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+            return Ok(ExitStatus::from_raw(status).into());
         }
 
-        if let Some(stderr) = stderr { 
-            command.stderr(stderr); 
-        } else { 
-            command.stderr(Stdio::piped()); 
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
         }
     }
-}
\ No newline at end of file
+}
+
+// io::Error's display includes "(os error N)", which isn't something a shell user needs to see.
+pub fn error_message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(idx) => message[..idx].to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_pipeline_stages() {
+        let root = Parser::new("ls -l | grep .rs | wc -l").parse().unwrap();
+        let mut stages = Vec::new();
+        pipeline_stages(&root, &mut stages);
+        let names: Vec<&Word> = stages.iter().map(|s| s.get_name().unwrap()).collect();
+        assert_eq!(names, vec![&Word::from("ls"), &Word::from("grep"), &Word::from("wc")]);
+    }
+
+    #[test]
+    fn test_outcome_code() {
+        assert!(Outcome::Exited(0).success());
+        assert!(!Outcome::Signaled(9).success());
+        assert_eq!(Outcome::Exited(3).code(), 3);
+        assert_eq!(Outcome::Signaled(9).code(), 137);
+    }
+
+    #[test]
+    fn test_engine_subshell_exit_trap() {
+        let mut state = ShellState::default();
+        let root = Parser::new("(trap 'echo bye' EXIT; echo hi); echo after").parse().unwrap();
+        let (output, outcome) = Engine::new(&mut state).capture(&root).unwrap();
+        assert_eq!(output, "hi\nbye\nafter");
+        assert_eq!(outcome, Outcome::Exited(0));
+
+        // the trap sees the subshell's status, and an exit in it changes it.
+        let root = Parser::new("(trap 'echo $?; exit 4' EXIT; false)").parse().unwrap();
+        assert_eq!(Engine::new(&mut state).capture(&root).unwrap(), ("1".to_string(), Outcome::Exited(4)));
+    }
+
+    #[test]
+    fn test_engine_interrupted_loop() {
+        let mut state = ShellState::default();
+        state.vars.set("PATH", "/usr/bin:/bin");
+        // the child dies of SIGINT the way a ^C from the terminal would kill it.
+        let root = Parser::new("while true; do ((n += 1)); sh -c 'kill -INT $$'; done; m=1").parse().unwrap();
+        assert_eq!(Engine::new(&mut state).execute(&root).unwrap(), Outcome::Signaled(libc::SIGINT));
+        assert!(state.interrupted);
+        assert_eq!(state.vars.get("n"), Some("1"));
+        assert_eq!(state.vars.get("m"), None);
+    }
+
+    #[test]
+    fn test_engine_call_depth() {
+        // on a stack of the size main gives the engine.
+        let recurse = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(|| {
+            let mut state = ShellState::default();
+            let root = Parser::new("n=0; f() { ((n += 1)); f; }; f").parse().unwrap();
+            let outcome = Engine::new(&mut state).execute(&root).unwrap();
+            (outcome, state.vars.get("n").map(String::from), state.call_depth)
+        });
+        let (outcome, n, depth) = recurse.unwrap().join().unwrap();
+        assert_eq!(outcome, Outcome::Exited(1));
+        assert_eq!(n.as_deref(), Some("1000"));
+        assert_eq!(depth, 0);
+    }
+
+    #[test]
+    fn test_error_message() {
+        let err = io::Error::from_raw_os_error(2);
+        assert_eq!(error_message(&err), "No such file or directory");
+    }
+}
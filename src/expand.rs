@@ -0,0 +1,677 @@
+use crate::arith;
+use crate::brace;
+use crate::engine::{ self, Engine, Outcome };
+use crate::glob;
+use crate::parser::{ ParamOp, RshNode, Word, WordPart };
+use crate::pattern::{ self, Pattern };
+use crate::state::ShellState;
+use crate::variables::is_valid_name;
+use std::ffi::{ CStr, CString };
+use std::fmt::{ self, Display, Formatter };
+
+// used when IFS isn't set at all.
+const DEFAULT_IFS: &str = " \t\n";
+
+// an expansion that can't be completed, e.g. a glob with no matches under failglob. The command it belongs to fails.
+#[derive(Debug, PartialEq)]
+pub struct ExpandError {
+    pub message: String,
+    // ${x:?} with x unset, which ends a shell that isn't interactive rather than just the command.
+    pub fatal: bool,
+}
+
+impl ExpandError {
+    pub fn new(message: String) -> ExpandError {
+        ExpandError { message, fatal: false }
+    }
+
+    pub fn fatal(message: String) -> ExpandError {
+        ExpandError { message, fatal: true }
+    }
+}
+
+impl Display for ExpandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/**
+* A piece of expanded text. Quoted text is never split or treated as a pattern, and only text that
+* came out of an unquoted expansion is split into fields.
+*/
+#[derive(Debug)]
+struct Segment {
+    text: String,
+    quoted: bool,
+    split: bool,
+    // the space between two positional parameters of "$@", where one field ends and the next begins.
+    field_break: bool,
+}
+
+/**
+* A field after splitting. "pattern" is the same text with everything that was quoted escaped, which is what
+* pathname expansion matches against.
+*/
+#[derive(Debug)]
+struct Field {
+    text: String,
+    pattern: String,
+}
+
+/**
+* Turns the words of the syntax tree into the strings a command actually gets, resolving
+* parameters against the shell state and splitting the results into fields.
+*/
+pub struct Expander<'sh> {
+    state: &'sh mut ShellState,
+    substituted: Option<Outcome>,
+}
+
+impl<'sh> Expander<'sh> {
+    pub fn new(state: &'sh mut ShellState) -> Expander<'sh> {
+        Expander {
+            state,
+            substituted: None,
+        }
+    }
+
+    // the outcome of the last command substitution expanded so far, if there was one.
+    pub fn substituted(&self) -> Option<Outcome> {
+        self.substituted
+    }
+
+    /**
+    * Expands a command line into its argument vector. Brace expansion comes first, and each word it
+    * produces can expand into any number of fields.
+    */
+    pub fn expand_words(&mut self, words: &[&Word]) -> Result<Vec<String>, ExpandError> {
+        let mut fields = Vec::new();
+        for word in words {
+            for word in brace::expand(word) {
+                fields.extend(self.expand_fields(&word)?);
+            }
+        }
+        Ok(fields)
+    }
+
+    pub fn expand_fields(&mut self, word: &Word) -> Result<Vec<String>, ExpandError> {
+        let mut segments = Vec::new();
+        self.expand_word(word, false, &mut segments)?;
+
+        let mut fields = Vec::new();
+        for field in self.split_fields(segments) {
+            fields.extend(self.expand_pathnames(field)?);
+        }
+        Ok(fields)
+    }
+
+    // expands without field splitting, for redirect targets and here-documents.
+    pub fn expand_string(&mut self, word: &Word) -> Result<String, ExpandError> {
+        let mut segments = Vec::new();
+        self.expand_word(word, false, &mut segments)?;
+        Ok(segments.into_iter().map(|segment| segment.text).collect())
+    }
+
+    // the value of NAME=value, which also gets tilde expansion after every ":".
+    pub fn expand_assignment(&mut self, word: &Word) -> Result<String, ExpandError> {
+        let mut segments = Vec::new();
+        self.expand_word(word, true, &mut segments)?;
+        Ok(segments.into_iter().map(|segment| segment.text).collect())
+    }
+
+    // expands into a pattern in which anything that was quoted only matches itself.
+    pub fn expand_pattern(&mut self, word: &Word) -> Result<String, ExpandError> {
+        let mut segments = Vec::new();
+        self.expand_parts(&word.parts, false, false, &mut segments)?;
+        Ok(segments
+            .into_iter()
+            .map(|segment| if segment.quoted { pattern::escape(&segment.text) } else { segment.text })
+            .collect())
+    }
+
+    // evaluates an arithmetic expression, once the parameters and command substitutions in it are expanded.
+    pub fn expand_arith(&mut self, expr: &Word) -> Result<i64, ExpandError> {
+        let text = self.expand_string(expr)?;
+        arith::evaluate(&text, &mut self.state.vars).map_err(|err| ExpandError::new(err.message))
+    }
+
+    // a whole word, which unlike the words inside ${...} starts with tilde expansion.
+    fn expand_word(&mut self, word: &Word, assignment: bool, out: &mut Vec<Segment>) -> Result<(), ExpandError> {
+        for (idx, part) in word.parts.iter().enumerate() {
+            match part {
+                WordPart::Literal(text) => self.expand_tildes(text, idx == 0, assignment, idx + 1 == word.parts.len(), out),
+                part => self.expand_parts(std::slice::from_ref(part), false, false, out)?,
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Tilde expansion of unquoted text. A tilde prefix is only recognized at the start of a word, or after a ":" in
+    * an assignment so that PATH=~/bin:~/.cargo/bin works. It runs up to the next "/" (or ":") and has to be all
+    * unquoted text, so ~"user" and ~$x are left alone.
+    */
+    fn expand_tildes(&self, text: &str, word_start: bool, assignment: bool, word_end: bool, out: &mut Vec<Segment>) {
+        let mut rest = text;
+        let mut at_start = word_start;
+
+        loop {
+            if at_start && rest.starts_with('~') {
+                let end = rest.find(|c| c == '/' || (assignment && c == ':')).unwrap_or(rest.len());
+                if end < rest.len() || word_end {
+                    if let Some(dir) = self.tilde(&rest[1..end]) {
+                        out.push(Segment { text: dir, quoted: true, split: false, field_break: false });
+                        rest = &rest[end..];
+                    }
+                }
+            }
+
+            let end = match rest.find(':') {
+                Some(idx) if assignment => idx + 1,
+                _ => rest.len(),
+            };
+            out.push(Segment { text: rest[..end].to_string(), quoted: false, split: false, field_break: false });
+            rest = &rest[end..];
+
+            if rest.is_empty() {
+                break;
+            }
+            at_start = true;
+        }
+    }
+
+    // what a tilde prefix stands for: ~ is $HOME, ~+ is $PWD, ~- is $OLDPWD, and ~user is that user's home.
+    pub fn tilde(&self, prefix: &str) -> Option<String> {
+        match prefix {
+            "" => self.state.vars.get("HOME").map(String::from).or_else(|| {
+                // safety: getuid can't fail, and the passwd entry is copied out by home_dir.
+                home_dir(unsafe { libc::getpwuid(libc::getuid()) })
+            }),
+            "+" => self.state.vars.get("PWD").map(String::from).or_else(|| {
+                std::env::current_dir().ok().map(|dir| dir.to_string_lossy().into_owned())
+            }),
+            "-" => self.state.vars.get("OLDPWD").map(String::from),
+            user => {
+                let name = CString::new(user).ok()?;
+                // safety: name is a valid C string for the duration of the call.
+                home_dir(unsafe { libc::getpwnam(name.as_ptr()) })
+            },
+        }
+    }
+
+    /**
+    * "quoted" is set inside double quotes. "split_literals" is set for the word of an unquoted ${x:-word},
+    * whose plain text gets split along with the rest of the expansion.
+    */
+    fn expand_parts(&mut self, parts: &[WordPart], quoted: bool, split_literals: bool, out: &mut Vec<Segment>) -> Result<(), ExpandError> {
+        for part in parts {
+            match part {
+                WordPart::Literal(text) => out.push(Segment { text: text.clone(), quoted, split: split_literals && !quoted, field_break: false }),
+                WordPart::Quoted(text) => out.push(Segment { text: text.clone(), quoted: true, split: false, field_break: false }),
+                WordPart::Param { name, op, quoted: param_quoted } => self.expand_param(name, op, quoted || *param_quoted, out)?,
+                WordPart::Command { node, quoted: command_quoted } => self.expand_command(node, quoted || *command_quoted, out)?,
+                WordPart::Arith { expr, quoted: arith_quoted } => {
+                    let quoted = quoted || *arith_quoted;
+                    out.push(Segment { text: self.expand_arith(expr)?.to_string(), quoted, split: !quoted, field_break: false });
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_param(&mut self, name: &str, op: &ParamOp, quoted: bool, out: &mut Vec<Segment>) -> Result<(), ExpandError> {
+        if matches!(name, "@" | "*") && *op == ParamOp::Plain {
+            self.expand_positional(name == "@", quoted, out);
+            return Ok(());
+        }
+
+        let value = self.lookup(name);
+        let missing = |colon: bool| match &value {
+            None => true,
+            Some(value) => colon && value.is_empty(),
+        };
+        let push_value = |out: &mut Vec<Segment>, text: String| out.push(Segment { text, quoted, split: !quoted, field_break: false });
+
+        match op {
+            ParamOp::Plain => push_value(out, value.unwrap_or_default()),
+
+            ParamOp::Length => push_value(out, value.map(|v| v.chars().count()).unwrap_or(0).to_string()),
+
+            ParamOp::Default { word, colon } => {
+                if missing(*colon) {
+                    self.expand_parts(&word.parts, quoted, !quoted, out)?;
+                } else {
+                    push_value(out, value.unwrap_or_default());
+                }
+            },
+
+            ParamOp::Assign { word, colon } => {
+                if missing(*colon) {
+                    if !is_valid_name(name) {
+                        return Err(ExpandError::new(format!("${}: cannot assign in this way", name)));
+                    }
+                    let new_value = self.expand_string(word)?;
+                    self.state.vars.set(name, &new_value);
+                    push_value(out, new_value);
+                } else {
+                    push_value(out, value.unwrap_or_default());
+                }
+            },
+
+            ParamOp::Error { word, colon } => {
+                if missing(*colon) {
+                    let mut message = self.expand_string(word)?;
+                    if message.is_empty() {
+                        message = "parameter null or not set".to_string();
+                    }
+                    return Err(ExpandError::fatal(format!("{}: {}", name, message)));
+                }
+                push_value(out, value.unwrap_or_default());
+            },
+
+            ParamOp::Alternative { word, colon } => {
+                if !missing(*colon) {
+                    self.expand_parts(&word.parts, quoted, !quoted, out)?;
+                }
+            },
+
+            ParamOp::RemovePrefix { pattern, longest } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?);
+                push_value(out, remove_prefix(&value.unwrap_or_default(), &pattern, *longest));
+            },
+
+            ParamOp::RemoveSuffix { pattern, longest } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?);
+                push_value(out, remove_suffix(&value.unwrap_or_default(), &pattern, *longest));
+            },
+
+            ParamOp::Replace { pattern, replacement, all } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?);
+                let replacement = self.expand_string(replacement)?;
+                push_value(out, replace(&value.unwrap_or_default(), &pattern, &replacement, *all));
+            },
+        }
+
+        Ok(())
+    }
+
+    /**
+    * $@ and $*, which are a field for each positional parameter. Between double quotes "$@" still is, so it passes
+    * the parameters on exactly as they are, while "$*" joins them into one field with the first character of IFS.
+    */
+    fn expand_positional(&mut self, at: bool, quoted: bool, out: &mut Vec<Segment>) {
+        if quoted && !at {
+            let separator = self.state.vars.get("IFS").unwrap_or(DEFAULT_IFS).chars().next().map(String::from).unwrap_or_default();
+            out.push(Segment { text: self.state.positional.join(&separator), quoted, split: false, field_break: false });
+            return;
+        }
+
+        for (idx, arg) in self.state.positional.iter().enumerate() {
+            if idx > 0 {
+                out.push(Segment { text: " ".to_string(), quoted, split: false, field_break: true });
+            }
+            out.push(Segment { text: arg.clone(), quoted, split: !quoted, field_break: false });
+        }
+    }
+
+    // the output of a command substitution, which is split like a parameter's value when unquoted.
+    fn expand_command(&mut self, node: &RshNode, quoted: bool, out: &mut Vec<Segment>) -> Result<(), ExpandError> {
+        let (text, outcome) = Engine::new(self.state)
+            .capture(node)
+            .map_err(|err| ExpandError::new(engine::error_message(&err)))?;
+        self.substituted = Some(outcome);
+        out.push(Segment { text, quoted, split: !quoted, field_break: false });
+        Ok(())
+    }
+
+    // the value of a parameter, None if it isn't set.
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.state.last_status.code().to_string()),
+            "$" => Some(self.state.pid.to_string()),
+            "!" => self.state.jobs.last_background().map(|pid| pid.to_string()),
+            "0" => Some(self.state.name.clone()),
+            "#" => Some(self.state.positional.len().to_string()),
+            "@" | "*" => Some(self.state.positional.join(" ")),
+            "PIPESTATUS" => Some(self.state.pipestatus
+                .iter()
+                .map(|outcome| outcome.code().to_string())
+                .collect::<Vec<String>>()
+                .join(" ")),
+            _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                let idx = name.parse::<usize>().ok()?;
+                self.state.positional.get(idx.checked_sub(1)?).cloned()
+            },
+            _ => self.state.vars.get(name).map(|value| value.to_string()),
+        }
+    }
+
+    // replaces a field that has unquoted wildcards with the paths it matches.
+    fn expand_pathnames(&self, field: Field) -> Result<Vec<String>, ExpandError> {
+        if !Pattern::new(&field.pattern).is_wild() {
+            return Ok(vec![field.text]);
+        }
+
+        let options = &self.state.options;
+        let paths = glob::glob(&field.pattern, options.dotglob);
+        if !paths.is_empty() {
+            Ok(paths)
+        } else if options.failglob {
+            Err(ExpandError::new(format!("no match: {}", field.text)))
+        } else if options.nullglob {
+            Ok(Vec::new())
+        } else {
+            Ok(vec![field.text])
+        }
+    }
+
+    /**
+    * Joins the segments back together, breaking the splittable ones on the characters in IFS. Whitespace
+    * in IFS collapses, so "a  b" is two fields, while any other IFS character ends a field on its own,
+    * so with IFS=: "a::b" is three.
+    */
+    fn split_fields(&self, segments: Vec<Segment>) -> Vec<Field> {
+        let ifs = self.state.vars.get("IFS").unwrap_or(DEFAULT_IFS).to_string();
+        let mut fields = Vec::new();
+        let mut current = Field { text: String::new(), pattern: String::new() };
+        // whether "current" is a field yet. A quoted empty string is, an empty expansion isn't.
+        let mut exists = false;
+        let take = |current: &mut Field| std::mem::replace(current, Field { text: String::new(), pattern: String::new() });
+
+        for segment in segments {
+            // a quoted "$@" keeps even its empty parameters as fields.
+            if segment.field_break {
+                if exists || segment.quoted {
+                    fields.push(take(&mut current));
+                }
+                exists = false;
+                continue;
+            }
+
+            if !segment.split || ifs.is_empty() {
+                exists |= segment.quoted || !segment.text.is_empty();
+                current.text.push_str(&segment.text);
+                if segment.quoted {
+                    current.pattern.push_str(&pattern::escape(&segment.text));
+                } else {
+                    current.pattern.push_str(&segment.text);
+                }
+                continue;
+            }
+
+            for c in segment.text.chars() {
+                if !ifs.contains(c) {
+                    current.text.push(c);
+                    current.pattern.push(c);
+                    exists = true;
+                } else if c.is_whitespace() {
+                    if exists {
+                        fields.push(take(&mut current));
+                        exists = false;
+                    }
+                } else {
+                    fields.push(take(&mut current));
+                    exists = false;
+                }
+            }
+        }
+
+        if exists {
+            fields.push(current);
+        }
+
+        fields
+    }
+}
+
+// the home directory from a passwd entry, which may be null if there was no such user.
+fn home_dir(entry: *mut libc::passwd) -> Option<String> {
+    // safety: a non-null entry points at the C library's static record, which is only read here.
+    unsafe {
+        if entry.is_null() || (*entry).pw_dir.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr((*entry).pw_dir).to_string_lossy().into_owned())
+    }
+}
+
+// the byte offsets of every character boundary in text, including the end.
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices().map(|(idx, _)| idx).chain(std::iter::once(text.len())).collect()
+}
+
+fn remove_prefix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+
+    match ends.into_iter().find(|end| pattern.matches(&value[..*end])) {
+        Some(end) => value[end..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn remove_suffix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+
+    match starts.into_iter().find(|start| pattern.matches(&value[*start..])) {
+        Some(start) => value[..start].to_string(),
+        None => value.to_string(),
+    }
+}
+
+// replaces the longest match at the leftmost position, or every non-overlapping match when "all" is set.
+fn replace(value: &str, pattern: &Pattern, replacement: &str, all: bool) -> String {
+    let bounds = boundaries(value);
+    let mut res = String::new();
+    let mut idx = 0;
+
+    while idx < bounds.len() - 1 {
+        let start = bounds[idx];
+        let matched = (idx + 1..bounds.len()).rev().find(|end| pattern.matches(&value[start..bounds[*end]]));
+
+        match matched {
+            Some(end) => {
+                res.push_str(replacement);
+                idx = end;
+                if !all {
+                    break;
+                }
+            },
+            None => {
+                res.push_str(&value[start..bounds[idx + 1]]);
+                idx += 1;
+            },
+        }
+    }
+
+    res.push_str(&value[bounds[idx]..]);
+    res
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::parser::{ Parser, RshNode };
+
+    // expands the arguments of a single command.
+    fn expand(state: &mut ShellState, input: &str) -> Result<Vec<String>, ExpandError> {
+        let root = Parser::new(input).parse().unwrap();
+        match root {
+            RshNode::Command { args, .. } => Expander::new(state).expand_words(&args.iter().collect::<Vec<&Word>>()),
+            other => panic!("not a simple command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_expand_plain() {
+        let mut state = ShellState::default();
+        state.vars.set("x", "hello world");
+        assert_eq!(expand(&mut state, "echo $x ${x}!"), Ok(vec!["hello", "world", "hello", "world!"].into_iter().map(String::from).collect()));
+        assert_eq!(expand(&mut state, "echo \"$x\" '$x'"), Ok(vec!["hello world".to_string(), "$x".to_string()]));
+        assert_eq!(expand(&mut state, "echo $unset \"$unset\""), Ok(vec!["".to_string()]));
+    }
+
+    #[test]
+    fn test_expand_ifs() {
+        let mut state = ShellState::default();
+        state.vars.set("IFS", ":");
+        state.vars.set("path", "a::b:");
+        assert_eq!(expand(&mut state, "echo $path"), Ok(vec!["a".to_string(), "".to_string(), "b".to_string()]));
+        state.vars.set("IFS", "");
+        assert_eq!(expand(&mut state, "echo $path"), Ok(vec!["a::b:".to_string()]));
+    }
+
+    #[test]
+    fn test_expand_defaults() {
+        let mut state = ShellState::default();
+        state.vars.set("empty", "");
+        assert_eq!(expand(&mut state, "echo ${empty:-a b} ${empty-x}"), Ok(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(expand(&mut state, "echo \"${unset:-a b}\" ${unset:-\"c d\"}"), Ok(vec!["a b".to_string(), "c d".to_string()]));
+        assert_eq!(expand(&mut state, "echo ${empty:+alt} ${unset+alt} ${empty+alt}"), Ok(vec!["alt".to_string()]));
+        assert_eq!(expand(&mut state, "echo ${new:=value} $new"), Ok(vec!["value".to_string(), "value".to_string()]));
+        assert_eq!(state.vars.get("new"), Some("value"));
+    }
+
+    #[test]
+    fn test_expand_error() {
+        let mut state = ShellState::default();
+        assert_eq!(expand(&mut state, "echo ${x:?is required}"), Err(ExpandError::fatal("x: is required".to_string())));
+        assert_eq!(expand(&mut state, "echo ${x?}"), Err(ExpandError::fatal("x: parameter null or not set".to_string())));
+    }
+
+    #[test]
+    fn test_expand_patterns() {
+        let mut state = ShellState::default();
+        state.vars.set("file", "src/main.tar.gz");
+        assert_eq!(expand(&mut state, "echo ${#file} ${file#*/} ${file##*.} ${file%.*} ${file%%.*}"), Ok(vec![
+            "15".to_string(),
+            "main.tar.gz".to_string(),
+            "gz".to_string(),
+            "src/main.tar".to_string(),
+            "src/main".to_string(),
+        ]));
+        assert_eq!(expand(&mut state, "echo ${file/a/A} ${file//a/A} ${file//[.\\/]} ${file/\"*\"/x}"), Ok(vec![
+            "src/mAin.tar.gz".to_string(),
+            "src/mAin.tAr.gz".to_string(),
+            "srcmaintargz".to_string(),
+            "src/main.tar.gz".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn test_expand_special() {
+        let mut state = ShellState {
+            last_status: crate::engine::Outcome::Exited(3),
+            pid: 4242,
+            ..ShellState::default()
+        };
+        assert_eq!(expand(&mut state, "echo $? ${#?} $$"), Ok(vec!["3".to_string(), "1".to_string(), "4242".to_string()]));
+    }
+
+    #[test]
+    fn test_expand_positional() {
+        let mut state = ShellState {
+            positional: vec!["a b".to_string(), String::new(), "c".to_string()],
+            ..ShellState::default()
+        };
+        let fields = |fields: &[&str]| Ok(fields.iter().map(|field| field.to_string()).collect::<Vec<String>>());
+        assert_eq!(expand(&mut state, "echo $# $1 ${3}"), fields(&["3", "a", "b", "c"]));
+        assert_eq!(expand(&mut state, "echo \"$@\""), fields(&["a b", "", "c"]));
+        assert_eq!(expand(&mut state, "echo x\"$@\"y"), fields(&["xa b", "", "cy"]));
+        assert_eq!(expand(&mut state, "echo $@"), fields(&["a", "b", "c"]));
+        assert_eq!(expand(&mut state, "echo \"$*\" $4"), fields(&["a b  c"]));
+
+        state.positional.clear();
+        assert_eq!(expand(&mut state, "echo \"$@\" \"$*\""), fields(&[""]));
+    }
+
+    #[test]
+    fn test_expand_command_substitution() {
+        let mut state = ShellState::default();
+        state.vars.set("IFS", " ");
+        assert_eq!(expand(&mut state, "echo $(echo a b) \"$(echo a b)\" x$(printf 'y\\n\\n')"), Ok(vec![
+            "a".to_string(),
+            "b".to_string(),
+            "a b".to_string(),
+            "xy".to_string(),
+        ]));
+        assert_eq!(expand(&mut state, "echo $(x=1; false)"), Ok(vec![]));
+        assert_eq!(state.last_status, Outcome::Exited(1));
+        assert_eq!(state.vars.get("x"), None);
+        // a subshell's $$ is still the shell's.
+        state.pid = 4242;
+        assert_eq!(expand(&mut state, "echo $(echo $$)"), Ok(vec!["4242".to_string()]));
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let dir = std::env::temp_dir().join(format!("rsh-expand-glob-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.rs", "b.rs", ".c.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let dir = dir.to_string_lossy().into_owned();
+
+        let mut state = ShellState::default();
+        state.vars.set("d", &dir);
+        state.vars.set("star", "*.rs");
+        let paths = |names: &[&str]| Ok(names.iter().map(|name| format!("{}/{}", dir, name)).collect::<Vec<String>>());
+        assert_eq!(expand(&mut state, "echo $d/*.rs"), paths(&["a.rs", "b.rs"]));
+        assert_eq!(expand(&mut state, "echo $d/$star"), paths(&["a.rs", "b.rs"]));
+        assert_eq!(expand(&mut state, "echo \"$d\"/[a]*"), paths(&["a.rs"]));
+        assert_eq!(expand(&mut state, "echo \"$d/*.rs\" $d/\\*.rs \"$d/$star\""), paths(&["*.rs", "*.rs", "*.rs"]));
+        assert_eq!(expand(&mut state, "echo $d/*.py"), paths(&["*.py"]));
+
+        state.options.dotglob = true;
+        assert_eq!(expand(&mut state, "echo $d/*.rs"), paths(&[".c.rs", "a.rs", "b.rs"]));
+        state.options.nullglob = true;
+        assert_eq!(expand(&mut state, "echo $d/*.py"), Ok(vec![]));
+        state.options.failglob = true;
+        assert_eq!(expand(&mut state, "echo $d/*.py"), Err(ExpandError::new(format!("no match: {}/*.py", dir))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let mut state = ShellState::default();
+        state.vars.set("HOME", "/home/me");
+        state.vars.set("PWD", "/work");
+        assert_eq!(expand(&mut state, "echo ~ ~/src a~ \"~\" ~\"/x\" ~+/y ~-"), Ok(vec![
+            "/home/me".to_string(),
+            "/home/me/src".to_string(),
+            "a~".to_string(),
+            "~".to_string(),
+            "~/x".to_string(),
+            "/work/y".to_string(),
+            "~-".to_string(),
+        ]));
+        assert_eq!(expand(&mut state, "echo ~root ~no-such-user-here/x"), Ok(vec![
+            home_dir(unsafe { libc::getpwnam(c"root".as_ptr()) }).unwrap(),
+            "~no-such-user-here/x".to_string(),
+        ]));
+
+        let value = crate::parser::Word::from("~/bin:~/.cargo/bin:a~");
+        assert_eq!(Expander::new(&mut state).expand_assignment(&value), Ok("/home/me/bin:/home/me/.cargo/bin:a~".to_string()));
+        assert_eq!(Expander::new(&mut state).expand_string(&value), Ok("/home/me/bin:~/.cargo/bin:a~".to_string()));
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        let mut state = ShellState::default();
+        state.vars.set("HOME", "/home/me");
+        state.vars.set("x", "mid");
+        assert_eq!(expand(&mut state, "echo a{b,$x}c ~/{1..2}"), Ok(vec![
+            "abc".to_string(),
+            "amidc".to_string(),
+            "/home/me/1".to_string(),
+            "/home/me/2".to_string(),
+        ]));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::pattern::Pattern;
+use std::fs;
+
+/**
+* Pathname expansion: returns the sorted paths that match "pattern", or nothing if none do. Each part of the
+* pattern between slashes is matched against the names in one directory, and a part that is exactly "**"
+* matches any number of directories, so "src/**/*.rs" finds every .rs file under src. Names starting with a
+* dot are only matched by a pattern that starts with a dot too, unless "dotglob" is set.
+*/
+pub fn glob(pattern: &str, dotglob: bool) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest.trim_start_matches('/')),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components: Vec<&str> = rest.split('/').collect();
+    for (idx, component) in components.iter().enumerate() {
+        let last = idx + 1 == components.len();
+
+        if *component == "**" {
+            let mut dirs = Vec::new();
+            for path in &paths {
+                dirs.push(path.clone());
+                walk_dirs(path, dotglob, &mut dirs);
+            }
+            // a trailing "**" is "**/*", everything at any depth.
+            paths = if last {
+                dirs.iter().flat_map(|dir| matching_entries(dir, &Pattern::new("*"), dotglob, false)).collect()
+            } else {
+                dirs
+            };
+            continue;
+        }
+
+        let pattern = Pattern::new(component);
+        paths = match pattern.literal() {
+            Some(name) => paths.iter().map(|path| join(path, &name)).collect(),
+            None => paths.iter().flat_map(|path| matching_entries(path, &pattern, dotglob, !last)).collect(),
+        };
+    }
+
+    // literal parts are joined on without looking at the disk, so check that the result is really there.
+    paths.retain(|path| fs::symlink_metadata(path).is_ok());
+    paths.sort();
+    paths
+}
+
+// the paths of the entries in "dir" whose names match, only the directories if "dirs_only" is set.
+fn matching_entries(dir: &str, pattern: &Pattern, dotglob: bool, dirs_only: bool) -> Vec<String> {
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !dotglob && !pattern.starts_with_char('.') {
+            continue;
+        }
+        if !pattern.matches(&name) {
+            continue;
+        }
+
+        let path = join(dir, &name);
+        // symlinks to directories count as directories here.
+        if dirs_only && !fs::metadata(&path).is_ok_and(|meta| meta.is_dir()) {
+            continue;
+        }
+        paths.push(path);
+    }
+    paths
+}
+
+// every directory below "dir". Symlinks aren't followed, so a link back up the tree can't loop forever.
+fn walk_dirs(dir: &str, dotglob: bool, out: &mut Vec<String>) {
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !dotglob {
+            continue;
+        }
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            let path = join(dir, &name);
+            out.push(path.clone());
+            walk_dirs(&path, dotglob, out);
+        }
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use std::path::PathBuf;
+
+    // a scratch directory tree, removed again when the test is done with it.
+    struct Tree {
+        root: PathBuf,
+    }
+
+    impl Tree {
+        fn new(name: &str, files: &[&str]) -> Tree {
+            let root = std::env::temp_dir().join(format!("rsh-glob-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                if !file.ends_with('/') {
+                    fs::write(&path, "").unwrap();
+                }
+            }
+            Tree { root }
+        }
+
+        fn glob(&self, pattern: &str, dotglob: bool) -> Vec<String> {
+            let root = self.root.to_string_lossy().into_owned();
+            glob(&format!("{}/{}", root, pattern), dotglob)
+                .into_iter()
+                .map(|path| path[root.len() + 1..].to_string())
+                .collect()
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn test_glob_simple() {
+        let tree = Tree::new("simple", &["b.rs", "a.rs", "c.toml", ".hidden.rs", "src/main.rs", "[x].rs"]);
+        assert_eq!(tree.glob("*.rs", false), vec!["[x].rs", "a.rs", "b.rs"]);
+        assert_eq!(tree.glob("?.*", false), vec!["a.rs", "b.rs", "c.toml"]);
+        assert_eq!(tree.glob("[ab].rs", false), vec!["a.rs", "b.rs"]);
+        assert_eq!(tree.glob("\\[x\\].rs", false), vec!["[x].rs"]);
+        assert_eq!(tree.glob("*/*.rs", false), vec!["src/main.rs"]);
+        assert_eq!(tree.glob("*/", false), vec!["src/"]);
+        assert!(tree.glob("*.py", false).is_empty());
+    }
+
+    #[test]
+    fn test_glob_hidden() {
+        let tree = Tree::new("hidden", &["a.rs", ".hidden.rs", ".git/config"]);
+        assert_eq!(tree.glob("*.rs", false), vec!["a.rs"]);
+        assert_eq!(tree.glob(".*", false), vec![".git", ".hidden.rs"]);
+        assert_eq!(tree.glob("*.rs", true), vec![".hidden.rs", "a.rs"]);
+        assert_eq!(tree.glob("**/config", false), Vec::<String>::new());
+        assert_eq!(tree.glob("**/config", true), vec![".git/config"]);
+    }
+
+    #[test]
+    fn test_glob_recursive() {
+        let tree = Tree::new("recursive", &["top.rs", "src/lib.rs", "src/a/b/deep.rs", "src/a/notes.txt", "empty/"]);
+        assert_eq!(tree.glob("**/*.rs", false), vec!["src/a/b/deep.rs", "src/lib.rs", "top.rs"]);
+        assert_eq!(tree.glob("src/**/*.rs", false), vec!["src/a/b/deep.rs", "src/lib.rs"]);
+        assert_eq!(tree.glob("src/**", false), vec!["src/a", "src/a/b", "src/a/b/deep.rs", "src/a/notes.txt", "src/lib.rs"]);
+    }
+}
@@ -0,0 +1,117 @@
+use super::Entry;
+use crate::variables::Variables;
+use std::path::PathBuf;
+
+/**
+* The commands in a bash history file. A "#" line with a time in it, which bash writes before each command when
+* HISTTIMEFORMAT is set, is when the command after it was run. A command without one is given the time of the one
+* before it, so that the commands keep their order when they're sorted in among others.
+*/
+pub fn bash(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut time = 0;
+    for line in contents.lines() {
+        if let Some(stamp) = line.strip_prefix('#').and_then(|stamp| stamp.parse().ok()) {
+            time = stamp;
+        } else if !line.trim().is_empty() {
+            entries.push(Entry { command: line.to_string(), time, ..Entry::default() });
+        }
+    }
+    entries
+}
+
+/**
+* The commands in a zsh history file. With EXTENDED_HISTORY each one starts with ": <time>:<seconds it ran>;", and
+* each line of a command typed over several ends in a backslash but the last. zsh writes some bytes as 0x83 followed
+* by the byte xor 32, which are put back the way they were typed.
+*/
+pub fn zsh(contents: &[u8]) -> Vec<Entry> {
+    let contents = unmetafy(contents);
+    let text = String::from_utf8_lossy(&contents);
+    let mut entries = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let mut command = line.to_string();
+        while command.ends_with('\\') {
+            let Some(next) = lines.next() else {
+                break;
+            };
+            command.pop();
+            command.push('\n');
+            command.push_str(next);
+        }
+
+        let entry = match extended(&command) {
+            Some((time, duration, command)) => Entry { command: command.to_string(), time, duration, ..Entry::default() },
+            None => Entry { command, ..Entry::default() },
+        };
+        if !entry.command.trim().is_empty() {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+// splits ": 1700000000:5;make" into its time, its duration in milliseconds and its command.
+fn extended(line: &str) -> Option<(u64, u64, &str)> {
+    let (stamp, command) = line.strip_prefix(':')?.split_once(';')?;
+    let (time, seconds) = stamp.split_once(':')?;
+    Some((time.trim().parse().ok()?, seconds.parse::<u64>().ok()?.saturating_mul(1000), command))
+}
+
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut unmetafied = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x83 => unmetafied.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => unmetafied.push(byte),
+        }
+    }
+    unmetafied
+}
+
+// where bash or zsh keep their history unless told otherwise: ~/.bash_history, or .zsh_history in $ZDOTDIR or ~.
+pub fn default_path(shell: &str, vars: &Variables) -> Option<PathBuf> {
+    let home = PathBuf::from(vars.get("HOME")?);
+    match shell {
+        "bash" => Some(home.join(".bash_history")),
+        "zsh" => {
+            let dir = vars.get("ZDOTDIR").filter(|dir| !dir.is_empty()).map_or(home, PathBuf::from);
+            Some(dir.join(".zsh_history"))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    fn summary(entries: &[Entry]) -> Vec<(&str, u64, u64)> {
+        entries.iter().map(|entry| (entry.command.as_str(), entry.time, entry.duration)).collect()
+    }
+
+    #[test]
+    fn test_import_bash() {
+        let entries = bash("ls\n#1700000000\nmake\n\ngit status\n#1700000100\n#not a time\n");
+        assert_eq!(summary(&entries), vec![("ls", 0, 0), ("make", 1700000000, 0), ("git status", 1700000000, 0), ("#not a time", 1700000100, 0)]);
+    }
+
+    #[test]
+    fn test_import_zsh() {
+        let mut contents = b": 1700000000:3;make\n: 1700000050:0;for x in a b\\\ndo echo $x\\\ndone\nplain\n: 1700000060:0;echo ".to_vec();
+        // "é" is 0xc3 0xa9, and zsh writes the 0xa9 as 0x83 0x89.
+        contents.extend([0xc3, 0x83, 0x89, b'\n']);
+        let entries = zsh(&contents);
+        assert_eq!(
+            summary(&entries),
+            vec![
+                ("make", 1700000000, 3000),
+                ("for x in a b\ndo echo $x\ndone", 1700000050, 0),
+                ("plain", 0, 0),
+                ("echo é", 1700000060, 0),
+            ]
+        );
+    }
+}
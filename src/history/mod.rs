@@ -0,0 +1,847 @@
+use crate::pattern::Pattern;
+use crate::variables::Variables;
+use std::ffi::CString;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Read, Seek, SeekFrom, Write };
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{ Path, PathBuf };
+use std::sync::OnceLock;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+pub mod import;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+// how many commands are remembered, and kept in the file, when HISTSIZE and HISTFILESIZE don't say.
+pub const DEFAULT_SIZE: usize = 1000;
+
+/**
+* A command as the history remembers it: when it was run and for how long, on which host, in which session and
+* directory, and its exit status. Lines of the file that aren't in the history's own format, like those of a plain
+* bash history file, are commands with none of that known, a time of 0 and an empty directory.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub command: String,
+    // seconds since the epoch.
+    pub time: u64,
+    // milliseconds.
+    pub duration: u64,
+    pub cwd: String,
+    pub hostname: String,
+    pub session: String,
+    pub status: i32,
+}
+
+impl Entry {
+    // a command run just now, by this shell.
+    pub fn new(command: &str, cwd: &str, status: i32) -> Entry {
+        Entry {
+            command: command.to_string(),
+            time: now(),
+            cwd: cwd.to_string(),
+            hostname: hostname().to_string(),
+            session: session().to_string(),
+            status,
+            ..Entry::default()
+        }
+    }
+
+    /**
+    * The entry as one line of the history file: the time, status, duration, host, session, directory and command
+    * separated by tabs. Tabs, newlines and backslashes are escaped, so a command typed over several lines still
+    * takes up one line, and one write appends all of it. Older lines with only the time, status, directory and
+    * command are still read.
+    */
+    fn to_line(&self) -> String {
+        let fields = [&self.hostname, &self.session, &self.cwd, &self.command].map(|field| escape(field));
+        format!("{}\t{}\t{}\t{}\n", self.time, self.status, self.duration, fields.join("\t"))
+    }
+
+    fn from_line(line: &str) -> Entry {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (time, status, duration, hostname, session, cwd, command) = match fields.as_slice() {
+            [time, status, cwd, command] => (*time, *status, "0", "", "", *cwd, *command),
+            [time, status, duration, hostname, session, cwd, command] => (*time, *status, *duration, *hostname, *session, *cwd, *command),
+            _ => return Entry { command: line.to_string(), ..Entry::default() },
+        };
+        match (time.parse(), status.parse(), duration.parse()) {
+            (Ok(time), Ok(status), Ok(duration)) => Entry {
+                command: unescape(command),
+                time,
+                duration,
+                cwd: unescape(cwd),
+                hostname: unescape(hostname),
+                session: unescape(session),
+                status,
+            },
+            _ => Entry { command: line.to_string(), ..Entry::default() },
+        }
+    }
+}
+
+/**
+* What "history search" looks for. Each part that's set has to match: text the command contains, a directory it ran
+* in or below, the session it was typed in, its exact status or just that it failed, and a time range that includes
+* since but not until.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub text: Option<String>,
+    pub cwd: Option<String>,
+    pub session: Option<String>,
+    pub status: Option<i32>,
+    pub failed: bool,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.text.as_ref().is_none_or(|text| entry.command.contains(text.as_str()))
+            && self.cwd.as_ref().is_none_or(|dir| within(&entry.cwd, dir))
+            && self.session.as_ref().is_none_or(|session| entry.session == *session)
+            && self.status.is_none_or(|status| entry.status == status)
+            && (!self.failed || entry.status != 0)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+    }
+}
+
+// whether cwd is dir or a directory below it.
+fn within(cwd: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    cwd == dir || cwd.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+// where the history is saved.
+#[derive(Debug, Default)]
+enum Store {
+    #[default]
+    Nowhere,
+    File(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Database(sqlite::Database),
+}
+
+impl Store {
+    // adds entries to the end of what's saved.
+    fn append(&self, entries: &[Entry]) -> io::Result<()> {
+        match self {
+            Store::Nowhere => Ok(()),
+            Store::File(path) => {
+                let mut file = OpenOptions::new().append(true).create(true).mode(0o600).open(path)?;
+                lock(&file)?;
+                let lines: String = entries.iter().map(Entry::to_line).collect();
+                file.write_all(lines.as_bytes())
+            },
+            #[cfg(feature = "sqlite")]
+            Store::Database(database) => database.insert(entries).map_err(io::Error::other),
+        }
+    }
+
+    // adds entries from elsewhere, which in the file go in among the others in the order they were run.
+    fn import(&self, entries: &[Entry]) -> io::Result<()> {
+        match self {
+            Store::Nowhere => Ok(()),
+            Store::File(path) => {
+                let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600).open(path)?;
+                lock(&file)?;
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+                let mut saved: Vec<Entry> = contents.lines().filter(|line| !line.is_empty()).map(Entry::from_line).collect();
+                saved.extend_from_slice(entries);
+                saved.sort_by_key(|entry| entry.time);
+
+                let lines: String = saved.iter().map(Entry::to_line).collect();
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(lines.as_bytes())
+            },
+            #[cfg(feature = "sqlite")]
+            Store::Database(database) => database.insert(entries).map_err(io::Error::other),
+        }
+    }
+}
+
+/**
+* The commands typed at the prompt, oldest first. Each one is saved as soon as it has run: appended to the history
+* file with the file locked while it's written, so several shells can share a file without mixing up their lines,
+* or, when rsh is built with the "sqlite" feature, added to a database the shells share.
+*/
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<Entry>,
+    store: Store,
+    // set when entries are deleted or read in, which the line editor's copy of them has to catch up with.
+    pub edited: bool,
+}
+
+impl History {
+    /**
+    * The history the shell starts with: the database if rsh is built with the "sqlite" feature and RSH_HISTDB isn't
+    * set to nothing, otherwise the history file. Nothing is saved if there's nowhere to save it.
+    */
+    pub fn open(vars: &Variables) -> io::Result<History> {
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = sqlite::default_path(vars) {
+            return History::load_database(path, vars);
+        }
+        match default_path(vars) {
+            Some(path) => History::load(path, vars),
+            None => Ok(History::default()),
+        }
+    }
+
+    /**
+    * Reads the history file, creating it and the directories above it if need be. A file that has grown past
+    * HISTFILESIZE entries is cut down to the newest ones, and the newest HISTSIZE of those are remembered.
+    */
+    pub fn load(path: PathBuf, vars: &Variables) -> io::Result<History> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600).open(&path)?;
+        lock(&file)?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let lines: Vec<&str> = contents.lines().filter(|line| !line.is_empty()).collect();
+
+        let file_size = limit(vars, "HISTFILESIZE", limit(vars, "HISTSIZE", DEFAULT_SIZE));
+        let kept = &lines[lines.len().saturating_sub(file_size)..];
+        if kept.len() < lines.len() {
+            let mut trimmed = kept.join("\n");
+            trimmed.push('\n');
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(trimmed.as_bytes())?;
+        }
+
+        let size = limit(vars, "HISTSIZE", DEFAULT_SIZE);
+        let entries = kept[kept.len().saturating_sub(size)..].iter().map(|line| Entry::from_line(line)).collect();
+        Ok(History { entries, store: Store::File(path), edited: false })
+    }
+
+    /**
+    * Opens the history database, creating it and the directories above it if need be, and remembers the newest
+    * HISTSIZE commands in it. Unlike the file, the database only loses old commands when HISTFILESIZE is set, since
+    * searching everything that was ever run is what it's for.
+    */
+    #[cfg(feature = "sqlite")]
+    pub fn load_database(path: PathBuf, vars: &Variables) -> io::Result<History> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // created first so that only its owner can read it, like the file.
+        OpenOptions::new().write(true).create(true).truncate(false).mode(0o600).open(&path)?;
+        let database = sqlite::Database::open(&path).map_err(io::Error::other)?;
+        if let Some(size) = vars.get("HISTFILESIZE").and_then(|size| size.parse().ok()) {
+            database.trim(size).map_err(io::Error::other)?;
+        }
+        let entries = database.newest(limit(vars, "HISTSIZE", DEFAULT_SIZE)).map_err(io::Error::other)?;
+        Ok(History { entries, store: Store::Database(database), edited: false })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    // the history file, None if the history isn't saved in one.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.store {
+            Store::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /**
+    * Remembers a command that was run and saves it, unless HISTCONTROL or HISTIGNORE say to leave it out. Returns
+    * whether it was kept. An error means the entry was remembered but couldn't be saved.
+    */
+    pub fn add(&mut self, entry: Entry, vars: &Variables) -> io::Result<bool> {
+        if self.ignored(&entry.command, vars) {
+            return Ok(false);
+        }
+
+        let saved = self.store.append(std::slice::from_ref(&entry));
+        self.entries.push(entry);
+        self.trim(vars);
+        saved.map(|_| true)
+    }
+
+    /**
+    * Adds commands from another shell's history, which go in among the ones already there by when they were run.
+    * They're saved, but HISTCONTROL and HISTIGNORE don't apply to them.
+    */
+    pub fn import(&mut self, entries: Vec<Entry>, vars: &Variables) -> io::Result<()> {
+        self.store.import(&entries)?;
+        self.entries.extend(entries);
+        self.entries.sort_by_key(|entry| entry.time);
+        self.trim(vars);
+        self.edited = true;
+        Ok(())
+    }
+
+    /**
+    * The commands matching a query, oldest first. Everything saved is searched, not just the commands remembered,
+    * so commands from other sessions turn up too.
+    */
+    pub fn search(&self, query: &Query) -> io::Result<Vec<Entry>> {
+        let saved = match &self.store {
+            Store::Nowhere => self.entries.clone(),
+            Store::File(path) => {
+                let file = File::open(path)?;
+                lock(&file)?;
+                io::read_to_string(file)?.lines().filter(|line| !line.is_empty()).map(Entry::from_line).collect()
+            },
+            #[cfg(feature = "sqlite")]
+            Store::Database(database) => return database.search(query).map_err(io::Error::other),
+        };
+        Ok(saved.into_iter().filter(|entry| query.matches(entry)).collect())
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.edited = true;
+    }
+
+    // deletes the entry with the given number, counted from 1. Returns false if there's no such entry.
+    pub fn delete(&mut self, number: usize) -> bool {
+        if number == 0 || number > self.entries.len() {
+            return false;
+        }
+        self.entries.remove(number - 1);
+        self.edited = true;
+        true
+    }
+
+    // replaces the contents of a history file with every entry remembered now.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).mode(0o600).open(path)?;
+        lock(&file)?;
+        file.set_len(0)?;
+        let lines: String = self.entries.iter().map(|entry| entry.to_line()).collect();
+        file.write_all(lines.as_bytes())
+    }
+
+    // adds the entries of a history file to the ones remembered now.
+    pub fn read(&mut self, path: &Path) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        self.entries.extend(contents.lines().filter(|line| !line.is_empty()).map(Entry::from_line));
+        self.edited = true;
+        Ok(())
+    }
+
+    // forgets all but the newest HISTSIZE entries.
+    fn trim(&mut self, vars: &Variables) {
+        let size = limit(vars, "HISTSIZE", DEFAULT_SIZE);
+        if self.entries.len() > size {
+            self.entries.drain(..self.entries.len() - size);
+        }
+    }
+
+    /**
+    * HISTCONTROL is a colon separated list of "ignorespace", to leave out commands that start with a space,
+    * "ignoredups", to leave out a command that's the same as the one before it, or "ignoreboth". HISTIGNORE is a
+    * colon separated list of patterns, a command matching any of them is left out, and "&" stands for the command
+    * before it. A HISTSIZE of 0 leaves out everything.
+    */
+    fn ignored(&self, command: &str, vars: &Variables) -> bool {
+        if limit(vars, "HISTSIZE", DEFAULT_SIZE) == 0 {
+            return true;
+        }
+
+        let previous = self.entries.last().map(|entry| entry.command.as_str());
+        for control in vars.get("HISTCONTROL").unwrap_or("").split(':') {
+            let space = matches!(control, "ignorespace" | "ignoreboth") && command.starts_with([' ', '\t']);
+            let dup = matches!(control, "ignoredups" | "ignoreboth") && previous == Some(command);
+            if space || dup {
+                return true;
+            }
+        }
+
+        vars.get("HISTIGNORE").unwrap_or("").split(':').filter(|pattern| !pattern.is_empty()).any(|pattern| match pattern {
+            "&" => previous == Some(command),
+            _ => Pattern::new(pattern).matches(command),
+        })
+    }
+}
+
+/**
+* History expansion, done on a line typed at the prompt before it's parsed. "!!" is the previous command, "!n" the
+* one numbered n, "!-n" the n-th one back, "!text" the latest one starting with text and "!?text?" the latest one
+* containing it. A word designator after a ":" picks words out of it: "n", "^" for the first argument, "$" for the
+* last, "*" for all of them and ranges like "1-3". "!$", "!^" and "!*" are short for those on the previous command.
+* "^old^new" at the start of the line is the previous command with old replaced by new. Nothing happens between
+* single quotes, after a backslash, or to a "!" followed by a space, "=" or "(", which keeps "$!" and "[!a]" as
+* they are too. The error says which event or substitution couldn't be found.
+*/
+pub fn expand(line: &str, entries: &[Entry]) -> Result<String, String> {
+    if let Some(rest) = line.strip_prefix('^') {
+        let mut parts = rest.splitn(3, '^');
+        let (old, new, tail) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let previous = entries.last().map(|entry| entry.command.as_str()).unwrap_or("");
+        if old.is_empty() || !previous.contains(old) {
+            return Err(format!("^{}^{}: substitution failed", old, new));
+        }
+        return Ok(format!("{}{}", previous.replacen(old, new, 1), tail));
+    }
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut expanded = String::with_capacity(line.len());
+    let (mut single, mut double) = (false, false);
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        match c {
+            '\\' if !single => {
+                expanded.extend(&chars[idx..(idx + 2).min(chars.len())]);
+                idx += 2;
+                continue;
+            },
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '!' if !single && !literal_bang(&chars, idx, double) => {
+                let (text, used) = expand_event(&chars[idx + 1..], entries)?;
+                expanded.push_str(&text);
+                idx += 1 + used;
+                continue;
+            },
+            _ => {},
+        }
+        expanded.push(c);
+        idx += 1;
+    }
+    Ok(expanded)
+}
+
+// a "!" that doesn't start a history expansion.
+fn literal_bang(chars: &[char], idx: usize, double: bool) -> bool {
+    let after = chars.get(idx + 1).copied();
+    let before = idx.checked_sub(1).map(|before| chars[before]);
+    matches!(after, None | Some(' ' | '\t' | '\n' | '=' | '('))
+        || (double && after == Some('"'))
+        || matches!(before, Some('$' | '['))
+        || (before == Some('{') && idx >= 2 && chars[idx - 2] == '$')
+}
+
+// the expansion of the event and word designator after a "!", and how many characters of "rest" they took up.
+fn expand_event(rest: &[char], entries: &[Entry]) -> Result<(String, usize), String> {
+    let back = |n: usize| entries.len().checked_sub(n).and_then(|idx| entries.get(idx));
+    let digits = |chars: &[char]| chars.iter().take_while(|c| c.is_ascii_digit()).collect::<String>();
+
+    let (entry, used) = match rest.first() {
+        Some('!') => (back(1), 1),
+        // "!$" and friends, a designator straight on the previous command.
+        Some('$' | '^' | '*' | ':') => (back(1), 0),
+        Some('-') if rest.get(1).is_some_and(|c| c.is_ascii_digit()) => {
+            let n = digits(&rest[1..]);
+            (n.parse().ok().and_then(back), 1 + n.len())
+        },
+        Some(c) if c.is_ascii_digit() => {
+            let n = digits(rest);
+            (n.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|idx| entries.get(idx)), n.len())
+        },
+        Some('?') => {
+            let text: String = rest[1..].iter().take_while(|c| **c != '?' && **c != '\n').collect();
+            let closed = rest.get(1 + text.chars().count()) == Some(&'?');
+            let entry = entries.iter().rev().find(|entry| entry.command.contains(&text));
+            (entry, 1 + text.chars().count() + usize::from(closed))
+        },
+        _ => {
+            let prefix: String = rest.iter().take_while(|c| !c.is_whitespace() && !":;&|<>()\"'".contains(**c)).collect();
+            let entry = entries.iter().rev().find(|entry| entry.command.starts_with(&prefix));
+            (entry, prefix.chars().count())
+        },
+    };
+
+    let event: String = rest[..used].iter().collect();
+    let Some(entry) = entry else {
+        return Err(format!("!{}: event not found", event));
+    };
+
+    // a designator comes after a ":", which can be left out in front of "^", "$" and "*".
+    let spec_start = match rest.get(used) {
+        Some(':') => used + 1,
+        Some('$' | '^' | '*') => used,
+        _ => return Ok((entry.command.clone(), used)),
+    };
+    let spec: String = rest[spec_start..].iter().take_while(|c| c.is_ascii_digit() || "^$*-".contains(**c)).collect();
+    let words = split_words(&entry.command);
+    match select_words(&words, &spec) {
+        Some(selected) if !spec.is_empty() => Ok((selected, spec_start + spec.chars().count())),
+        _ => {
+            let text: String = rest[..spec_start + spec.chars().count()].iter().collect();
+            Err(format!("!{}: bad word specifier", text))
+        },
+    }
+}
+
+// the words a designator like "2", "^", "$", "*", "1-3", "2-" or "2*" picks out of a command, joined by spaces.
+fn select_words(words: &[String], spec: &str) -> Option<String> {
+    let last = words.len().checked_sub(1)?;
+    let word = |text: &str| match text {
+        "^" => Some(1),
+        "$" => Some(last),
+        _ => text.parse::<usize>().ok(),
+    };
+
+    let (start, end) = if spec == "*" {
+        (1, last)
+    } else if let Some(start) = spec.strip_suffix('*') {
+        (word(start)?, last)
+    } else if let Some((start, end)) = spec.split_once('-') {
+        let start = if start.is_empty() { 0 } else { word(start)? };
+        // "2-" stops short of the last word, like it does in bash.
+        let end = if end.is_empty() { last.checked_sub(1)? } else { word(end)? };
+        (start, end)
+    } else {
+        let n = word(spec)?;
+        (n, n)
+    };
+
+    if start > end {
+        return (spec.ends_with('*') || spec == "*").then(String::new);
+    }
+    words.get(start..=end).map(|words| words.join(" "))
+}
+
+/**
+* A command split into words the way history expansion counts them: quotes and escapes keep their text together,
+* and the operators between commands are words of their own.
+*/
+fn split_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) => {
+                word.push(c);
+                if c == q {
+                    quote = None;
+                }
+            },
+            (None, '\'' | '"') => {
+                word.push(c);
+                quote = Some(c);
+            },
+            (None, '\\') => {
+                word.push(c);
+                word.extend(chars.next());
+            },
+            (None, c) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            },
+            (None, c) if "|&;<>()".contains(c) => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                let mut operator = c.to_string();
+                while let Some(next) = chars.next_if(|next| "|&;<>".contains(*next)) {
+                    operator.push(next);
+                }
+                words.push(operator);
+            },
+            (None, c) => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/**
+* Where the history is saved: $RSH_HISTFILE, or "rsh/history" under $XDG_STATE_HOME, which is ~/.local/state when
+* it isn't set. None if RSH_HISTFILE is set but empty, which turns saving off.
+*/
+pub fn default_path(vars: &Variables) -> Option<PathBuf> {
+    if let Some(path) = vars.get("RSH_HISTFILE") {
+        return (!path.is_empty()).then(|| PathBuf::from(path));
+    }
+    Some(state_dir(vars)?.join("rsh/history"))
+}
+
+// $XDG_STATE_HOME, or ~/.local/state.
+fn state_dir(vars: &Variables) -> Option<PathBuf> {
+    match vars.get("XDG_STATE_HOME") {
+        Some(state) if !state.is_empty() => Some(PathBuf::from(state)),
+        _ => Some(PathBuf::from(vars.get("HOME")?).join(".local/state")),
+    }
+}
+
+// seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+// the name of the machine the shell runs on.
+pub fn hostname() -> &'static str {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        let mut name = [0u8; 256];
+        // safety: gethostname writes at most name.len() bytes into name.
+        if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } < 0 {
+            return String::new();
+        }
+        let len = name.iter().position(|byte| *byte == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..len]).into_owned()
+    })
+}
+
+/**
+* What the commands typed in this shell have in common that those typed in other shells sharing the history don't:
+* when the shell started and its process id.
+*/
+pub fn session() -> &'static str {
+    static SESSION: OnceLock<String> = OnceLock::new();
+    SESSION.get_or_init(|| format!("{}-{}", now(), std::process::id()))
+}
+
+/**
+* A time as "history search" takes it: seconds since the epoch, a local date and time like "2024-05-01",
+* "2024-05-01 13:30" or "2024-05-01T13:30:15", or how long ago, like "90s", "15m", "2h", "3d" or "1w".
+*/
+pub fn parse_time(text: &str) -> Option<u64> {
+    if let Ok(time) = text.parse() {
+        return Some(time);
+    }
+    let unit = match text.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => 0,
+    };
+    if unit > 0 {
+        if let Ok(count) = text[..text.len() - 1].parse::<u64>() {
+            return Some(now().saturating_sub(count.saturating_mul(unit)));
+        }
+    }
+
+    let text = CString::new(text.replacen('T', " ", 1)).ok()?;
+    for format in [c"%Y-%m-%d %H:%M:%S", c"%Y-%m-%d %H:%M", c"%Y-%m-%d"] {
+        // safety: a zeroed tm is a valid one, and strptime only reads the two strings up to their nuls.
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let end = unsafe { libc::strptime(text.as_ptr(), format.as_ptr(), &mut tm) };
+        // safety: strptime returns a pointer into text, which is still there.
+        if !end.is_null() && unsafe { *end } == 0 {
+            // mktime works out whether daylight saving time was on.
+            tm.tm_isdst = -1;
+            return u64::try_from(unsafe { libc::mktime(&mut tm) }).ok();
+        }
+    }
+    None
+}
+
+// a time in seconds since the epoch as a local date and time, like "2024-05-01 13:30:15".
+pub fn format_time(time: u64) -> String {
+    let time = libc::time_t::try_from(time).unwrap_or(libc::time_t::MAX);
+    let mut buf = [0u8; 64];
+    // safety: a zeroed tm is a valid one for localtime_r to fill in, and strftime writes at most buf.len() bytes.
+    let len = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return time.to_string();
+        }
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), c"%Y-%m-%d %H:%M:%S".as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// a size limit from a variable, the default if it isn't set to a number.
+pub fn limit(vars: &Variables, name: &str, default: usize) -> usize {
+    vars.get(name).and_then(|size| size.parse().ok()).unwrap_or(default)
+}
+
+// an exclusive lock on the whole file, which lasts until it's closed.
+fn lock(file: &File) -> io::Result<()> {
+    // safety: flock only looks at the fd, which the file keeps open.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    fn vars(values: &[(&str, &str)]) -> Variables {
+        let mut vars = Variables::new();
+        for (name, value) in values {
+            vars.set(name, value);
+        }
+        vars
+    }
+
+    #[test]
+    fn test_history_lines() {
+        let entry = Entry { command: "for x in a\tb; do\n  echo \\$x\ndone".to_string(), time: 42, cwd: "/tmp".to_string(), status: 1, ..Entry::default() };
+        let line = entry.to_line();
+        assert_eq!(line.matches('\n').count(), 1);
+        assert_eq!(Entry::from_line(line.trim_end_matches('\n')), entry);
+        let entry = Entry { duration: 1500, ..Entry::new("make", "/src", 2) };
+        assert_eq!(Entry::from_line(entry.to_line().trim_end_matches('\n')), entry);
+
+        // a line written before the duration, host and session were kept.
+        let entry = Entry::from_line("42\t1\t/tmp\tls");
+        assert_eq!((entry.command.as_str(), entry.time, entry.cwd.as_str(), entry.session.as_str()), ("ls", 42, "/tmp", ""));
+
+        // a line from a bash history file.
+        let entry = Entry::from_line("ls -l\t| wc");
+        assert_eq!((entry.command.as_str(), entry.time), ("ls -l\t| wc", 0));
+    }
+
+    #[test]
+    fn test_history_ignored() {
+        let mut history = History::default();
+        let vars = vars(&[("HISTCONTROL", "ignoreboth"), ("HISTIGNORE", "ls:cd *")]);
+        assert!(history.add(Entry::new("make", "/", 0), &vars).unwrap());
+        assert!(!history.add(Entry::new("make", "/", 0), &vars).unwrap());
+        assert!(!history.add(Entry::new(" secret", "/", 0), &vars).unwrap());
+        assert!(!history.add(Entry::new("cd /tmp", "/", 0), &vars).unwrap());
+        assert!(history.add(Entry::new("ls -l", "/", 0), &vars).unwrap());
+        let commands: Vec<&str> = history.entries().iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, vec!["make", "ls -l"]);
+
+        assert!(!history.add(Entry::new("pwd", "/", 0), &self::vars(&[("HISTSIZE", "0")])).unwrap());
+    }
+
+    #[test]
+    fn test_history_file() {
+        let path = std::env::temp_dir().join(format!("rsh-history-{}/history", std::process::id()));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        let vars = vars(&[("HISTSIZE", "2"), ("HISTFILESIZE", "3")]);
+
+        let mut history = History::load(path.clone(), &vars).unwrap();
+        for command in ["a", "b", "c", "d"] {
+            history.add(Entry::new(command, "/", 0), &vars).unwrap();
+        }
+        assert_eq!(history.entries().len(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        let history = History::load(path.clone(), &vars).unwrap();
+        let commands: Vec<&str> = history.entries().iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, vec!["c", "d"]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_history_search() {
+        let path = std::env::temp_dir().join(format!("rsh-history-search-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let vars = Variables::new();
+        let mut history = History::load(path.clone(), &vars).unwrap();
+        history.add(Entry { time: 200, ..Entry::new("make", "/src/rsh", 2) }, &vars).unwrap();
+        history.add(Entry { time: 300, ..Entry::new("cargo test", "/src/rsh/tests", 0) }, &vars).unwrap();
+        history.import(vec![Entry { command: "ls".to_string(), time: 100, ..Entry::default() }], &vars).unwrap();
+        let commands = |entries: &[Entry]| entries.iter().map(|entry| entry.command.clone()).collect::<Vec<String>>();
+        assert_eq!(commands(history.entries()), vec!["ls", "make", "cargo test"]);
+
+        // what another shell has saved since turns up too.
+        history.store.append(&[Entry { time: 400, ..Entry::new("make", "/src/web", 0) }]).unwrap();
+        let search = |query: Query| commands(&history.search(&query).unwrap());
+        assert_eq!(search(Query { text: Some("make".to_string()), ..Query::default() }), vec!["make", "make"]);
+        assert_eq!(search(Query { cwd: Some("/src/rsh".to_string()), ..Query::default() }), vec!["make", "cargo test"]);
+        assert_eq!(search(Query { failed: true, ..Query::default() }), vec!["make"]);
+        assert_eq!(search(Query { session: Some(session().to_string()), since: Some(300), ..Query::default() }), vec!["cargo test", "make"]);
+        assert_eq!(search(Query { until: Some(200), ..Query::default() }), vec!["ls"]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_history_parse_time() {
+        assert_eq!(parse_time("1700000000"), Some(1700000000));
+        assert!(parse_time("2h").is_some_and(|time| time.abs_diff(now() - 7200) <= 1));
+        assert_eq!(parse_time("2024-05-01"), parse_time("2024-05-01 00:00:00"));
+        assert_eq!(parse_time("2024-05-01T13:30"), parse_time("2024-05-01 13:30:00"));
+        assert_eq!(parse_time("2024-05-01").map(format_time).as_deref(), Some("2024-05-01 00:00:00"));
+        assert_eq!(parse_time("yesterday"), None);
+        assert_eq!(parse_time("2x"), None);
+    }
+
+    #[test]
+    fn test_history_expand() {
+        let entries: Vec<Entry> = ["make test", "echo 'a b' c | wc -l", "ls -la /tmp"]
+            .iter()
+            .map(|command| Entry::new(command, "/", 0))
+            .collect();
+        let expand = |line: &str| expand(line, &entries);
+
+        assert_eq!(expand("sudo !!"), Ok("sudo ls -la /tmp".to_string()));
+        assert_eq!(expand("!1 && !-1"), Ok("make test && ls -la /tmp".to_string()));
+        assert_eq!(expand("!ma; !?wc?"), Ok("make test; echo 'a b' c | wc -l".to_string()));
+        assert_eq!(expand("cd !$"), Ok("cd /tmp".to_string()));
+        assert_eq!(expand("echo !-2:1 !-2:3* !-2:1-2 !^"), Ok("echo 'a b' | wc -l 'a b' c -la".to_string()));
+        assert_eq!(expand("^tmp^var"), Ok("ls -la /var".to_string()));
+
+        // left alone.
+        assert_eq!(expand("echo '!!' \\!! $! [!a] ! x != y"), Ok("echo '!!' \\!! $! [!a] ! x != y".to_string()));
+
+        assert_eq!(expand("!nope"), Err("!nope: event not found".to_string()));
+        assert_eq!(expand("!9"), Err("!9: event not found".to_string()));
+        assert_eq!(expand("!!:7"), Err("!!:7: bad word specifier".to_string()));
+        assert_eq!(expand("^zzz^y"), Err("^zzz^y: substitution failed".to_string()));
+    }
+
+    #[test]
+    fn test_history_edit() {
+        let mut history = History::default();
+        let vars = Variables::new();
+        for command in ["a", "b", "c"] {
+            history.add(Entry::new(command, "/", 0), &vars).unwrap();
+        }
+        assert!(history.delete(2));
+        assert!(!history.delete(3));
+        let commands: Vec<&str> = history.entries().iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, vec!["a", "c"]);
+
+        let path = std::env::temp_dir().join(format!("rsh-history-edit-{}", std::process::id()));
+        history.write(&path).unwrap();
+        history.clear();
+        assert!(history.entries().is_empty());
+        history.read(&path).unwrap();
+        assert_eq!(history.entries().len(), 2);
+        assert!(history.edited);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_history_default_path() {
+        assert_eq!(default_path(&vars(&[("RSH_HISTFILE", "/h"), ("HOME", "/home/me")])), Some(PathBuf::from("/h")));
+        assert_eq!(default_path(&vars(&[("RSH_HISTFILE", "")])), None);
+        assert_eq!(default_path(&vars(&[("XDG_STATE_HOME", "/state")])), Some(PathBuf::from("/state/rsh/history")));
+        assert_eq!(default_path(&vars(&[("HOME", "/home/me")])), Some(PathBuf::from("/home/me/.local/state/rsh/history")));
+    }
+}
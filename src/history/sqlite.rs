@@ -0,0 +1,205 @@
+use super::{ Entry, Query };
+use crate::variables::Variables;
+use rusqlite::types::Value;
+use rusqlite::{ params, params_from_iter, Connection, Result, Row };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        command TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        duration INTEGER NOT NULL,
+        cwd TEXT NOT NULL,
+        hostname TEXT NOT NULL,
+        session TEXT NOT NULL,
+        status INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_time ON history (time);
+";
+
+// the columns an entry is made from, in the order entry() reads them.
+const COLUMNS: &str = "command, time, duration, cwd, hostname, session, status";
+
+/**
+* The history kept in a SQLite database, a row for each command. Shells sharing it wait their turn for a while
+* when another one is writing, rather than losing the command.
+*/
+#[derive(Debug)]
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Database> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(Duration::from_secs(5))?;
+        // readers don't hold up a shell that's adding a command.
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Database { connection })
+    }
+
+    // the newest count entries, oldest first.
+    pub fn newest(&self, count: usize) -> Result<Vec<Entry>> {
+        let sql = format!(
+            "SELECT {} FROM (SELECT * FROM history ORDER BY time DESC, id DESC LIMIT ?1) ORDER BY time, id",
+            COLUMNS
+        );
+        let mut statement = self.connection.prepare(&sql)?;
+        let entries = statement.query_map(params![integer(count as u64)], entry)?.collect();
+        entries
+    }
+
+    // deletes all but the newest count entries.
+    pub fn trim(&self, count: usize) -> Result<()> {
+        self.connection.execute(
+            "DELETE FROM history WHERE id NOT IN (SELECT id FROM history ORDER BY time DESC, id DESC LIMIT ?1)",
+            params![integer(count as u64)],
+        )?;
+        Ok(())
+    }
+
+    // adds entries all at once, so that a failure adds none of them.
+    pub fn insert(&self, entries: &[Entry]) -> Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        {
+            let sql = format!("INSERT INTO history ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", COLUMNS);
+            let mut statement = transaction.prepare_cached(&sql)?;
+            for entry in entries {
+                statement.execute(params![
+                    entry.command,
+                    integer(entry.time),
+                    integer(entry.duration),
+                    entry.cwd,
+                    entry.hostname,
+                    entry.session,
+                    entry.status,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    // the entries a query matches, oldest first. It's the same test as Query::matches, done by the database.
+    pub fn search(&self, query: &Query) -> Result<Vec<Entry>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(text) = &query.text {
+            conditions.push("instr(command, ?) > 0");
+            values.push(Value::Text(text.clone()));
+        }
+        if let Some(dir) = &query.cwd {
+            let dir = dir.trim_end_matches('/');
+            conditions.push("(cwd = ? OR instr(cwd, ?) = 1)");
+            values.push(Value::Text(dir.to_string()));
+            values.push(Value::Text(format!("{}/", dir)));
+        }
+        if let Some(session) = &query.session {
+            conditions.push("session = ?");
+            values.push(Value::Text(session.clone()));
+        }
+        if let Some(status) = query.status {
+            conditions.push("status = ?");
+            values.push(Value::Integer(status.into()));
+        }
+        if query.failed {
+            conditions.push("status != 0");
+        }
+        if let Some(since) = query.since {
+            conditions.push("time >= ?");
+            values.push(Value::Integer(integer(since)));
+        }
+        if let Some(until) = query.until {
+            conditions.push("time < ?");
+            values.push(Value::Integer(integer(until)));
+        }
+
+        let filter = if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) };
+        let sql = format!("SELECT {} FROM history {} ORDER BY time, id", COLUMNS, filter);
+        let mut statement = self.connection.prepare(&sql)?;
+        let entries = statement.query_map(params_from_iter(values), entry)?.collect();
+        entries
+    }
+}
+
+fn entry(row: &Row) -> Result<Entry> {
+    Ok(Entry {
+        command: row.get(0)?,
+        time: row.get::<_, i64>(1)?.try_into().unwrap_or(0),
+        duration: row.get::<_, i64>(2)?.try_into().unwrap_or(0),
+        cwd: row.get(3)?,
+        hostname: row.get(4)?,
+        session: row.get(5)?,
+        status: row.get(6)?,
+    })
+}
+
+// SQLite's integers are signed.
+fn integer(n: u64) -> i64 {
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+/**
+* Where the database is: $RSH_HISTDB, or "rsh/history.db" next to where the history file would be. None if
+* RSH_HISTDB is set but empty, which keeps the history in the file instead.
+*/
+pub fn default_path(vars: &Variables) -> Option<PathBuf> {
+    if let Some(path) = vars.get("RSH_HISTDB") {
+        return (!path.is_empty()).then(|| PathBuf::from(path));
+    }
+    Some(super::state_dir(vars)?.join("rsh/history.db"))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    fn entry(command: &str, time: u64, cwd: &str, session: &str, status: i32) -> Entry {
+        Entry { command: command.to_string(), time, cwd: cwd.to_string(), session: session.to_string(), status, ..Entry::default() }
+    }
+
+    #[test]
+    fn test_sqlite_search() {
+        let path = std::env::temp_dir().join(format!("rsh-history-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let database = Database::open(&path).unwrap();
+        let entries = vec![
+            entry("make", 100, "/src/rsh", "a", 0),
+            entry("make test", 200, "/src/rsh/tests", "a", 101),
+            entry("ls", 300, "/src/rshell", "b", 0),
+            entry("git push", 400, "/src/rsh", "b", 1),
+        ];
+        database.insert(&entries).unwrap();
+
+        // each filter gives the same answer as Query::matches does.
+        let queries = [
+            Query { text: Some("make".to_string()), ..Query::default() },
+            Query { cwd: Some("/src/rsh/".to_string()), ..Query::default() },
+            Query { session: Some("b".to_string()), failed: true, ..Query::default() },
+            Query { status: Some(0), since: Some(100), until: Some(300), ..Query::default() },
+            Query::default(),
+        ];
+        for query in &queries {
+            let expected: Vec<Entry> = entries.iter().filter(|entry| query.matches(entry)).cloned().collect();
+            assert_eq!(database.search(query).unwrap(), expected, "{:?}", query);
+        }
+        assert_eq!(database.search(&queries[1]).unwrap().len(), 3);
+
+        assert_eq!(database.newest(2).unwrap(), entries[2..].to_vec());
+        database.trim(1).unwrap();
+        assert_eq!(database.newest(10).unwrap(), entries[3..].to_vec());
+        drop(database);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_sqlite_default_path() {
+        let mut vars = Variables::new();
+        vars.set("HOME", "/home/me");
+        assert_eq!(default_path(&vars), Some(PathBuf::from("/home/me/.local/state/rsh/history.db")));
+        vars.set("RSH_HISTDB", "");
+        assert_eq!(default_path(&vars), None);
+    }
+}
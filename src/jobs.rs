@@ -0,0 +1,687 @@
+use crate::engine::Outcome;
+use crate::signals;
+use std::fmt;
+use std::io::{ self, Write };
+use std::os::fd::RawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+// where a process of a job is at, as far as the last waitpid told us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Stopped(i32),
+    Done(Outcome),
+}
+
+// a job is running while any of its processes are, and stopped once the rest are stopped or done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+/**
+* One stage of a job. Stages that never started (a command that wasn't found, a builtin that ran in the shell)
+* have no pid and are done from the start, so the outcomes of a job still line up with its commands.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Process {
+    pub pid: Option<libc::pid_t>,
+    pub state: ProcessState,
+}
+
+impl Process {
+    pub fn running(pid: libc::pid_t) -> Process {
+        Process { pid: Some(pid), state: ProcessState::Running }
+    }
+
+    pub fn finished(outcome: Outcome) -> Process {
+        Process { pid: None, state: ProcessState::Done(outcome) }
+    }
+}
+
+/**
+* A pipeline, or a list run in the background, that the shell keeps track of. With job control every job has a
+* process group of its own, which is what the terminal gets handed to and what signals like SIGCONT are sent to.
+*/
+#[derive(Debug)]
+pub struct Job {
+    // the number used in "%N", given out when the job goes into the table.
+    pub id: usize,
+    // 0 when job control is off and the processes are in the shell's own group.
+    pub pgid: libc::pid_t,
+    pub processes: Vec<Process>,
+    pub command: String,
+    // the state the user was last told about, so every change is only reported once.
+    reported: JobState,
+}
+
+impl Job {
+    pub fn new(pgid: libc::pid_t, processes: Vec<Process>, command: String) -> Job {
+        Job {
+            id: 0,
+            pgid,
+            processes,
+            command,
+            reported: JobState::Running,
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        let states = self.processes.iter().map(|process| process.state);
+        if states.clone().any(|state| state == ProcessState::Running) {
+            JobState::Running
+        } else if states.clone().any(|state| matches!(state, ProcessState::Stopped(_))) {
+            JobState::Stopped
+        } else {
+            JobState::Done
+        }
+    }
+
+    // the outcome of each process in order, processes that are stopped count as stopped by their signal.
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        self.processes.iter().map(|process| match process.state {
+            ProcessState::Running => Outcome::default(),
+            ProcessState::Stopped(signal) => Outcome::Stopped(signal),
+            ProcessState::Done(outcome) => outcome,
+        }).collect()
+    }
+
+    // the pid "$!" and "jobs -p" show, the last process for "$!" like other shells.
+    pub fn last_pid(&self) -> Option<libc::pid_t> {
+        self.processes.iter().rev().find_map(|process| process.pid)
+    }
+
+    fn leader(&self) -> Option<libc::pid_t> {
+        match self.pgid {
+            0 => self.processes.iter().find_map(|process| process.pid),
+            pgid => Some(pgid),
+        }
+    }
+
+    // what "jobs" shows in the state column.
+    fn status(&self) -> String {
+        match self.state() {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => {
+                let signal = self.processes.iter().find_map(|process| match process.state {
+                    ProcessState::Stopped(signal) => Some(signal),
+                    _ => None,
+                });
+                signal.map(signals::name).unwrap_or_else(|| "Stopped".to_string())
+            },
+            JobState::Done => match self.outcomes().last().copied().unwrap_or_default() {
+                Outcome::Exited(0) => "Done".to_string(),
+                Outcome::Exited(code) => format!("Exit {}", code),
+                Outcome::Signaled(signal) | Outcome::Stopped(signal) => signals::name(signal),
+            },
+        }
+    }
+
+    fn update(&mut self, pid: libc::pid_t, state: ProcessState) -> bool {
+        match self.processes.iter_mut().find(|process| process.pid == Some(pid)) {
+            Some(process) => {
+                process.state = state;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/**
+* Where the processes of a new job go. The first process leads a new group, the rest join it, and a foreground
+* job is handed the terminal as well. Both the child and the shell do this, whichever runs first, so the job is
+* in its group before it can read from the terminal or be signalled.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessGroup {
+    // 0 until the first process has been started.
+    pub pgid: libc::pid_t,
+    terminal: Option<RawFd>,
+}
+
+impl ProcessGroup {
+    /**
+    * Moves the calling process into the group and, for the foreground, gives it the terminal. It runs between
+    * fork and exec, so it sticks to async-signal-safe calls and ignores failures the shell will also try to fix.
+    */
+    pub fn enter(&self) {
+        // safety: plain system calls on our own process and the terminal fd.
+        unsafe {
+            libc::setpgid(0, self.pgid);
+            if let Some(terminal) = self.terminal {
+                libc::tcsetpgrp(terminal, libc::getpgrp());
+            }
+        }
+    }
+
+    // the shell's side of enter, for a child that was just started.
+    pub fn adopt(&mut self, pid: libc::pid_t) {
+        if self.pgid == 0 {
+            self.pgid = pid;
+        }
+        // safety: as above, and a child that has already exec'd just makes setpgid fail.
+        unsafe {
+            libc::setpgid(pid, self.pgid);
+            if let Some(terminal) = self.terminal {
+                libc::tcsetpgrp(terminal, self.pgid);
+            }
+        }
+    }
+}
+
+// the shell's own process group and the terminal it controls, once job control is on.
+#[derive(Clone, Copy)]
+struct Control {
+    shell_pgid: libc::pid_t,
+    terminal: RawFd,
+    // the terminal settings the shell started with, in case a job leaves them in a mess.
+    modes: libc::termios,
+}
+
+// termios has no Debug without libc's extra traits.
+impl fmt::Debug for Control {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Control")
+            .field("shell_pgid", &self.shell_pgid)
+            .field("terminal", &self.terminal)
+            .finish_non_exhaustive()
+    }
+}
+
+/**
+* The job table. Jobs go in when they're started in the background or stopped in the foreground, and come out once
+* they're done and the user has heard about it, or when they're disowned.
+*/
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    // ids from the least to the most recently started or stopped job, the last one is "%+" and the one before it "%-".
+    recent: Vec<usize>,
+    control: Option<Control>,
+    // the pid of the last job started in the background, $!.
+    last_background: Option<libc::pid_t>,
+}
+
+impl Jobs {
+    /**
+    * Turns job control on if stdin is a terminal: the shell takes a process group of its own and the terminal with it.
+    * The terminal's settings are saved too, and put back whenever the shell takes the terminal back from a job.
+    */
+    pub fn enable(&mut self) -> Result<(), io::Error> {
+        // safety: plain system calls on our own process and stdin.
+        unsafe {
+            if libc::isatty(0) == 0 {
+                return Ok(());
+            }
+
+            // started in the background, wait until we're brought to the foreground before taking the terminal.
+            loop {
+                let foreground = libc::tcgetpgrp(0);
+                if foreground < 0 || foreground == libc::getpgrp() {
+                    break;
+                }
+                libc::kill(0, libc::SIGTTIN);
+            }
+
+            // taking the terminal from a group that doesn't have it would stop us otherwise.
+            libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+
+            // fails for a session leader, which already has a group of its own.
+            libc::setpgid(0, 0);
+            let shell_pgid = libc::getpgrp();
+
+            // a copy of the terminal that stays put, whatever gets redirected over stdin.
+            let terminal = libc::fcntl(0, libc::F_DUPFD_CLOEXEC, 10);
+            if terminal < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::tcsetpgrp(terminal, shell_pgid);
+            let mut modes = std::mem::zeroed();
+            if libc::tcgetattr(terminal, &mut modes) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.control = Some(Control { shell_pgid, terminal, modes });
+        }
+        Ok(())
+    }
+
+    pub fn job_control(&self) -> bool {
+        self.control.is_some()
+    }
+
+    /**
+    * For a forked copy of the shell, which leaves the terminal and process groups to the shell it was copied from.
+    * The table stays as it was, so "kill $(jobs -p)" still sees the jobs.
+    */
+    pub fn disable(&mut self) {
+        self.control = None;
+    }
+
+    pub fn last_background(&self) -> Option<libc::pid_t> {
+        self.last_background
+    }
+
+    // where a new job's processes should go, or None without job control.
+    pub fn process_group(&self, foreground: bool) -> Option<ProcessGroup> {
+        self.control.map(|control| ProcessGroup {
+            pgid: 0,
+            terminal: if foreground { Some(control.terminal) } else { None },
+        })
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    /**
+    * Waits for a job in the foreground until it's done or stopped, then takes the terminal back. A stopped job goes
+    * into the table so it can be resumed later. Returns the outcome of each of its processes.
+    */
+    pub fn foreground(&mut self, mut job: Job) -> Result<Vec<Outcome>, io::Error> {
+        let waited = wait_job(&mut job, self.job_control(), true);
+        self.take_terminal();
+        waited?;
+
+        let outcomes = job.outcomes();
+        if let Some(message) = outcomes.iter().rev().find_map(|outcome| signals::describe(*outcome)) {
+            // the ^C or ^\ the terminal echoed is still on the job's last line.
+            let from_terminal = outcomes.iter().any(|outcome| matches!(outcome, Outcome::Signaled(libc::SIGINT | libc::SIGQUIT)));
+            if self.job_control() && from_terminal {
+                eprintln!();
+            }
+            eprintln!("{}", message);
+        }
+        if job.state() == JobState::Stopped {
+            job.reported = JobState::Stopped;
+            let id = self.insert(job);
+            // the ^Z was echoed on the line the job was using.
+            eprintln!();
+            self.print(id, false);
+        }
+        Ok(outcomes)
+    }
+
+    // adds a job started in the background, telling an interactive user its number and pid.
+    pub fn background(&mut self, job: Job) -> usize {
+        self.last_background = job.last_pid();
+        let pid = job.leader();
+        let id = self.insert(job);
+        if let (true, Some(pid)) = (self.job_control(), pid) {
+            eprintln!("[{}] {}", id, pid);
+        }
+        id
+    }
+
+    // fg: continues a job with the terminal handed to it, and waits for it like any other foreground job.
+    pub fn resume_foreground(&mut self, id: usize) -> Result<Vec<Outcome>, io::Error> {
+        let Some(control) = self.control else {
+            return Err(io::Error::other("no job control"));
+        };
+        let Some(mut job) = self.remove(id) else {
+            return Err(io::Error::other(format!("%{}: no such job", id)));
+        };
+
+        println!("{}", job.command);
+        let _ = io::stdout().flush();
+        // safety: hands the terminal to a group of ours and wakes it up.
+        unsafe {
+            libc::tcsetpgrp(control.terminal, job.pgid);
+            libc::kill(-job.pgid, libc::SIGCONT);
+        }
+        continued(&mut job);
+        self.foreground(job)
+    }
+
+    // bg: continues a stopped job where it is, in the background.
+    pub fn resume_background(&mut self, id: usize) -> Result<(), io::Error> {
+        if !self.job_control() {
+            return Err(io::Error::other("no job control"));
+        }
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return Err(io::Error::other(format!("%{}: no such job", id)));
+        };
+
+        // safety: wakes up a group of ours.
+        unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+        continued(job);
+        job.reported = JobState::Running;
+        let marker = self.marker(id);
+        if let Some(job) = self.get(id) {
+            println!("[{}]{} {} &", id, marker, job.command);
+        }
+        Ok(())
+    }
+
+    /**
+    * wait: blocks until a job is done (or stopped, with job control) and returns its outcome. A job that's done is
+    * taken out of the table, since its status has been collected. A signal cuts the wait short, and the outcome is
+    * then as if the shell had been killed by it, e.g. 130 for ^C.
+    */
+    pub fn wait(&mut self, id: usize) -> Result<Outcome, io::Error> {
+        let untraced = self.job_control();
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return Err(io::Error::other(format!("%{}: no such job", id)));
+        };
+
+        if let Some(signal) = wait_job(job, untraced, false)? {
+            return Ok(Outcome::Signaled(signal));
+        }
+        let outcome = job.outcomes().last().copied().unwrap_or_default();
+        if job.state() == JobState::Done {
+            self.remove(id);
+        }
+        Ok(outcome)
+    }
+
+    // disown: forgets about a job without doing anything to it.
+    pub fn disown(&mut self, id: usize) -> bool {
+        self.remove(id).is_some()
+    }
+
+    /**
+    * Collects the status of every child that has changed since we last looked, without blocking. Children we
+    * don't know about (e.g. the processes of a disowned job) are reaped along the way.
+    */
+    pub fn reap(&mut self) {
+        let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+        loop {
+            let mut status = 0;
+            // safety: status is a valid place for waitpid to write to.
+            let pid = unsafe { libc::waitpid(-1, &mut status, flags) };
+            if pid <= 0 {
+                if pid < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return;
+            }
+
+            let state = process_state(status);
+            for job in &mut self.jobs {
+                if job.update(pid, state) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /**
+    * Tells the user about jobs that have finished or stopped since they last heard, which an interactive shell does
+    * before every prompt. Jobs that are done are dropped from the table once they've been reported. Without job
+    * control nothing is reported, so finished jobs stay around until "jobs" or "wait" get to them.
+    */
+    pub fn notify(&mut self) {
+        self.reap();
+        if !self.job_control() {
+            return;
+        }
+
+        for id in self.ids() {
+            let Some(state) = self.get(id).map(|job| job.state()) else {
+                continue;
+            };
+            if self.get(id).is_some_and(|job| job.reported == state) {
+                continue;
+            }
+
+            if state != JobState::Running {
+                self.print(id, false);
+            }
+            match state {
+                JobState::Done => {
+                    self.remove(id);
+                },
+                _ => {
+                    if let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) {
+                        job.reported = state;
+                    }
+                },
+            }
+        }
+    }
+
+    // jobs: shows every job, with the pid of each one's group leader if "long" is set.
+    pub fn list(&mut self, ids: &[usize], long: bool) {
+        self.reap();
+        for &id in ids {
+            self.print(id, long);
+            let done = match self.jobs.iter_mut().find(|job| job.id == id) {
+                Some(job) => {
+                    job.reported = job.state();
+                    job.reported == JobState::Done
+                },
+                None => false,
+            };
+            // a finished job is only shown once.
+            if done {
+                self.remove(id);
+            }
+        }
+    }
+
+    /**
+    * Resolves a job spec to a job id: "%N" by number, "%+", "%%" or a bare "%" for the current job, "%-" for the one
+    * before it, "%?text" for a command containing text and "%text" for one starting with it. A plain number is the
+    * pid of one of the processes.
+    */
+    pub fn find(&self, spec: &str) -> Result<usize, String> {
+        let not_found = || format!("{}: no such job", spec);
+        let Some(rest) = spec.strip_prefix('%') else {
+            let pid = spec.parse::<libc::pid_t>().map_err(|_| format!("`{}': not a pid or valid job spec", spec))?;
+            return self.jobs
+                .iter()
+                .find(|job| job.processes.iter().any(|process| process.pid == Some(pid)))
+                .map(|job| job.id)
+                .ok_or_else(|| format!("pid {} is not a child of this shell", pid));
+        };
+
+        match rest {
+            "" | "%" | "+" => self.recent.last().copied().ok_or_else(|| "current: no such job".to_string()),
+            "-" => self.recent.iter().rev().nth(1).copied().ok_or_else(|| "previous: no such job".to_string()),
+            _ if rest.bytes().all(|b| b.is_ascii_digit()) => {
+                let id = rest.parse::<usize>().map_err(|_| not_found())?;
+                self.get(id).map(|job| job.id).ok_or_else(not_found)
+            },
+            _ => {
+                let matches: Vec<usize> = match rest.strip_prefix('?') {
+                    Some(text) => self.jobs.iter().filter(|job| job.command.contains(text)).map(|job| job.id).collect(),
+                    None => self.jobs.iter().filter(|job| job.command.starts_with(rest)).map(|job| job.id).collect(),
+                };
+                match matches[..] {
+                    [id] => Ok(id),
+                    [] => Err(not_found()),
+                    _ => Err(format!("{}: ambiguous job spec", spec)),
+                }
+            },
+        }
+    }
+
+    // puts a job in the table, keeping its number if it had one already (a job that was stopped again).
+    fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let idx = self.jobs.iter().position(|other| other.id > id).unwrap_or(self.jobs.len());
+        self.jobs.insert(idx, job);
+        self.recent.push(id);
+        id
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Job> {
+        self.recent.retain(|other| *other != id);
+        let idx = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(idx))
+    }
+
+    // "+" for the current job, "-" for the previous one.
+    fn marker(&self, id: usize) -> char {
+        let mut recent = self.recent.iter().rev();
+        if recent.next() == Some(&id) {
+            '+'
+        } else if recent.next() == Some(&id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    // one line of "jobs", e.g. "[1]+  Running                 sleep 10 &".
+    fn print(&self, id: usize, long: bool) {
+        let Some(job) = self.get(id) else {
+            return;
+        };
+        let command = match job.state() {
+            JobState::Running => format!("{} &", job.command),
+            _ => job.command.clone(),
+        };
+        if long {
+            let pid = job.leader().map(|pid| pid.to_string()).unwrap_or_default();
+            println!("[{}]{} {:>7} {:<24}{}", id, self.marker(id), pid, job.status(), command);
+        } else {
+            println!("[{}]{}  {:<24}{}", id, self.marker(id), job.status(), command);
+        }
+        let _ = io::stdout().flush();
+    }
+
+    fn take_terminal(&self) {
+        if let Some(control) = self.control {
+            // safety: gives the terminal back to our own group, with the settings it had.
+            unsafe {
+                libc::tcsetpgrp(control.terminal, control.shell_pgid);
+                libc::tcsetattr(control.terminal, libc::TCSADRAIN, &control.modes);
+            }
+        }
+    }
+}
+
+// marks the stopped processes of a job as running again after a SIGCONT.
+fn continued(job: &mut Job) {
+    for process in &mut job.processes {
+        if let ProcessState::Stopped(_) = process.state {
+            process.state = ProcessState::Running;
+        }
+    }
+}
+
+/**
+* Waits for every running process of a job in turn. With "untraced" a stopped process counts as no longer running,
+* which is how the shell finds out about ^Z; a job stops as a whole since the signal goes to its entire group.
+*
+* Signals the shell catches while it waits are passed on to a foreground job if another process sent them, the ones
+* from the terminal have reached the job already. Waiting for a background job just stops at the first one, and it's
+* returned.
+*/
+fn wait_job(job: &mut Job, untraced: bool, foreground: bool) -> Result<Option<i32>, io::Error> {
+    let flags = if untraced { libc::WUNTRACED } else { 0 };
+    let mut first_err = None;
+    // anything caught before we started waiting is old news.
+    signals::take();
+
+    for idx in 0..job.processes.len() {
+        let (Some(pid), ProcessState::Running) = (job.processes[idx].pid, job.processes[idx].state) else {
+            continue;
+        };
+
+        let mut status = 0;
+        loop {
+            // safety: status is a valid place for waitpid to write to.
+            if unsafe { libc::waitpid(pid, &mut status, flags) } >= 0 {
+                job.processes[idx].state = process_state(status);
+                break;
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                // keep going so the remaining children still get reaped.
+                job.processes[idx].state = ProcessState::Done(Outcome::Exited(1));
+                first_err.get_or_insert(err);
+                break;
+            }
+
+            match signals::take() {
+                Some((signal, true)) if foreground => forward(job, signal),
+                Some((signal, _)) if !foreground => return Ok(Some(signal)),
+                _ => {},
+            }
+        }
+    }
+
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(None),
+    }
+}
+
+// sends a signal to the whole job, its group if it has one and otherwise each process that's still running.
+fn forward(job: &Job, signal: i32) {
+    // safety: only signals processes of ours.
+    unsafe {
+        if job.pgid != 0 {
+            libc::kill(-job.pgid, signal);
+            return;
+        }
+        for process in &job.processes {
+            if let (Some(pid), ProcessState::Running) = (process.pid, process.state) {
+                libc::kill(pid, signal);
+            }
+        }
+    }
+}
+
+fn process_state(status: i32) -> ProcessState {
+    if libc::WIFSTOPPED(status) {
+        ProcessState::Stopped(libc::WSTOPSIG(status))
+    } else if libc::WIFCONTINUED(status) {
+        ProcessState::Running
+    } else {
+        ProcessState::Done(ExitStatus::from_raw(status).into())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    fn job(command: &str, states: &[ProcessState]) -> Job {
+        let processes = states.iter().map(|state| Process { pid: None, state: *state }).collect();
+        Job::new(0, processes, command.to_string())
+    }
+
+    #[test]
+    fn test_jobs_state() {
+        let done = ProcessState::Done(Outcome::Exited(1));
+        assert_eq!(job("a", &[done, ProcessState::Running]).state(), JobState::Running);
+        assert_eq!(job("a", &[ProcessState::Stopped(20), done]).state(), JobState::Stopped);
+        assert_eq!(job("a", &[done, done]).state(), JobState::Done);
+        assert_eq!(job("a", &[done]).status(), "Exit 1");
+        assert_eq!(job("a", &[ProcessState::Stopped(20)]).outcomes(), vec![Outcome::Stopped(20)]);
+    }
+
+    #[test]
+    fn test_jobs_find() {
+        let mut jobs = Jobs::default();
+        let running = [ProcessState::Running];
+        assert_eq!(jobs.insert(job("sleep 10", &running)), 1);
+        assert_eq!(jobs.insert(job("vim notes", &running)), 2);
+        assert_eq!(jobs.insert(job("sleep 20", &running)), 3);
+        jobs.remove(2);
+        assert_eq!(jobs.find("%"), Ok(3));
+        assert_eq!(jobs.find("%-"), Ok(1));
+        assert_eq!(jobs.find("%1"), Ok(1));
+        assert_eq!(jobs.find("%?20"), Ok(3));
+        assert_eq!(jobs.find("%sleep"), Err("%sleep: ambiguous job spec".to_string()));
+        assert_eq!(jobs.find("%2"), Err("%2: no such job".to_string()));
+        // numbers keep counting up from the highest one in use.
+        assert_eq!(jobs.insert(job("vim", &running)), 4);
+        assert_eq!(jobs.marker(4), '+');
+        assert_eq!(jobs.marker(3), '-');
+    }
+}
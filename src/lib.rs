@@ -1,4 +1,19 @@
 pub mod parser;
+pub mod pattern;
+pub mod redirect;
 pub mod shell;
+pub mod state;
 pub mod token;
-pub mod engine;
\ No newline at end of file
+pub mod arith;
+pub mod brace;
+pub mod builtins;
+pub mod complete;
+pub mod engine;
+pub mod expand;
+pub mod glob;
+pub mod history;
+pub mod jobs;
+pub mod location;
+pub mod signals;
+pub mod traps;
+pub mod variables;
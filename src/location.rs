@@ -0,0 +1,41 @@
+use std::sync::Mutex;
+
+/**
+* Where the command being run was read from, the script's name and the line the command started on. It goes in
+* front of error messages so that they point at the line to fix. Commands typed at the prompt aren't from anywhere
+* in particular, so their errors just start with "rsh".
+*/
+static LOCATION: Mutex<Option<(String, usize)>> = Mutex::new(None);
+
+pub fn set(name: &str, line: usize) {
+    if let Ok(mut location) = LOCATION.lock() {
+        *location = Some((name.to_string(), line));
+    }
+}
+
+pub fn clear() {
+    if let Ok(mut location) = LOCATION.lock() {
+        *location = None;
+    }
+}
+
+// what error messages start with, e.g. "rsh" or "build.rsh:12".
+pub fn prefix() -> String {
+    match LOCATION.lock().as_deref() {
+        Ok(Some((name, line))) => format!("{}:{}", name, line),
+        _ => "rsh".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_location_prefix() {
+        set("build.rsh", 12);
+        assert_eq!(prefix(), "build.rsh:12");
+        clear();
+        assert_eq!(prefix(), "rsh");
+    }
+}
@@ -1,6 +1,76 @@
-use rsh::shell::Rsh;
+use rsh::engine;
+use rsh::shell::{ self, Rsh };
+use std::io::{ self, IsTerminal };
+use std::process;
+use std::thread;
 
+const USAGE: &str = "usage: rsh [script [args...]] | rsh -c command [name [args...]] | rsh -s [args...]";
+
+/**
+* The shell runs on a thread of its own, since the main thread's stack is too small for deeply nested function calls.
+* The main thread blocks every signal while it waits, so that they all reach the shell and interrupt what it's doing.
+*/
 fn main() {
-   let mut rsh = Rsh::new(">>> ".to_string());
-   rsh.run().unwrap();
+    let shell = match thread::Builder::new().stack_size(engine::STACK_SIZE).spawn(run) {
+        Ok(shell) => shell,
+        Err(err) => {
+            eprintln!("rsh: {}", engine::error_message(&err));
+            process::exit(1);
+        },
+    };
+    // safety: set is a valid signal set for sigfillset to fill in.
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigfillset(&mut set);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+    }
+    // the shell exits the process itself, so it only comes back if it panicked.
+    let _ = shell.join();
+    process::exit(101);
+}
+
+fn run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut rsh = Rsh::new(">>> ".to_string());
+
+    let result = match args.first().map(|arg| arg.as_str()) {
+        // the interactive shell, unless stdin is a file or pipe full of commands.
+        None if io::stdin().is_terminal() => rsh.run(),
+        None => rsh.run_script(shell::stdin_lines()),
+        Some("-c") => {
+            let Some(command) = args.get(1) else {
+                eprintln!("rsh: -c: option requires an argument");
+                process::exit(2);
+            };
+            let name = args.get(2).map_or("rsh", |name| name.as_str());
+            rsh.set_args(name, args.get(3..).unwrap_or_default());
+            rsh.run_script(command.lines().map(|line| Ok(line.to_string())))
+        },
+        Some("-s" | "-") => {
+            rsh.set_args("rsh", &args[1..]);
+            rsh.run_script(shell::stdin_lines())
+        },
+        Some(option) if option.starts_with('-') => {
+            eprintln!("rsh: {}: invalid option", option);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+        // a script, whose "#!" line is just a comment to us.
+        Some(path) => {
+            let source = match std::fs::read_to_string(path) {
+                Ok(source) => source,
+                Err(err) => {
+                    eprintln!("rsh: {}: {}", path, engine::error_message(&err));
+                    process::exit(if err.kind() == io::ErrorKind::NotFound { 127 } else { 126 });
+                },
+            };
+            rsh.set_args(path, &args[1..]);
+            rsh.run_script(source.lines().map(|line| Ok(line.to_string())))
+        },
+    };
+
+    if let Err(err) = result {
+        eprintln!("rsh: {}", err);
+    }
+    process::exit(rsh.last_status().code());
 }
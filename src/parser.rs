@@ -1,72 +1,520 @@
 use crate::token::{ Tokenizer, Token };
+use crate::variables::is_valid_name;
+use std::fmt::{ self, Display, Formatter };
 
 // error types for syntax parsing...
 #[derive(Debug)]
-pub enum ParseError {
+pub enum ParseErrorKind {
     UnexpectedToken(String),
     UnexpectedEOF,
     UnexpectedChar(char),
     UnexpectedEOL,
+    // the input ended before the here-document with this delimiter did.
+    UnterminatedHereDoc(String),
 }
 
-// result type for parsing 
-pub type ParseResult = Result<RshNode, ParseError>;
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedToken(token) => write!(f, "syntax error near unexpected token `{}'", token),
+            ParseErrorKind::UnexpectedEOF => write!(f, "syntax error: unexpected end of file"),
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "syntax error near unexpected character `{}'", c),
+            ParseErrorKind::UnexpectedEOL => write!(f, "syntax error: unexpected end of line"),
+            ParseErrorKind::UnterminatedHereDoc(delimiter) => write!(f, "here-document never ended (wanted `{}')", delimiter),
+        }
+    }
+}
+
+// where a syntax error is in the source, as a byte offset and the line and column it's on, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/**
+* A syntax error and where it is. The line it's on is kept as well, so that displaying the error can show it with
+* a caret under the offending token.
+*/
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    text: String,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, source: &str, offset: usize) -> ParseError {
+        let before = &source[..offset];
+        let start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        let end = source[offset..].find('\n').map_or(source.len(), |idx| offset + idx);
+        let span = Span {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[start..].chars().count() + 1,
+        };
+        ParseError { kind, span, text: source[start..end].to_string() }
+    }
+
+    /**
+    * Whether the input ended before the command did, like after an unfinished quote, a trailing "|" or "\\",
+    * an open block or a here-document without its delimiter. More lines could still make it a command, whereas
+    * any other error is there to stay.
+    */
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, ParseErrorKind::UnexpectedEOF | ParseErrorKind::UnterminatedHereDoc(_))
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // tabs are kept so that the caret lines up under them too.
+        let indent: String = self.text
+            .chars()
+            .take(self.span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{}\n{}\n{}^", self.kind, self.text, indent)
+    }
+}
+
+// result type for parsing, the error gets its span once it's made it out of the parser.
+pub type ParseResult = Result<RshNode, ParseErrorKind>;
 
 // redirect mode controls how the redirect will be handled.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RedirectMode {
-    Read,
-    Write,
-    Append, // not sure we need this yet...
+    Read,      // [n]<file
+    Write,     // [n]>file
+    Append,    // [n]>>file
+    ReadWrite, // [n]<>file
+    Duplicate, // [n]>&m and [n]<&m, the target is the fd to copy
+    Close,     // [n]>&- and [n]<&-
+    HereDoc,   // [n]<<word and [n]<<-word, the target is the body of the document
+    HereString, // [n]<<<word
+}
+
+/**
+* A single redirection attached to a command. Redirections are applied left to right, so
+* "> out 2>&1" and "2>&1 > out" mean different things, just like in other shells.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub mode: RedirectMode,
+    pub target: Word,
+}
+
+/**
+* A single piece of a word. Words are kept in pieces until execution time so that expansions like $?
+* can be resolved against the state of the shell when the command actually runs.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    // plain unquoted text.
+    Literal(String),
+    // text that came from quotes or a backslash escape, taken as-is.
+    Quoted(String),
+    // a parameter expansion such as $HOME or ${x:-default}, quoted if it appeared between double quotes.
+    Param {
+        name: String,
+        op: ParamOp,
+        quoted: bool,
+    },
+    // $(...) or `...`, replaced by whatever the commands write to stdout.
+    Command {
+        node: Box<RshNode>,
+        quoted: bool,
+    },
+    // $((...)), the expression is expanded like a double quoted string and then evaluated.
+    Arith {
+        expr: Word,
+        quoted: bool,
+    },
+}
+
+/**
+* What to do with a parameter's value. The ones with a "colon" flag treat an empty value the
+* same as an unset one, e.g. ${x:-d} versus ${x-d}.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamOp {
+    Plain,                                                   // $x, ${x}
+    Length,                                                  // ${#x}
+    Default { word: Word, colon: bool },                     // ${x:-word}
+    Assign { word: Word, colon: bool },                      // ${x:=word}
+    Error { word: Word, colon: bool },                       // ${x:?word}
+    Alternative { word: Word, colon: bool },                 // ${x:+word}
+    RemovePrefix { pattern: Word, longest: bool },           // ${x#pattern}, ${x##pattern}
+    RemoveSuffix { pattern: Word, longest: bool },           // ${x%pattern}, ${x%%pattern}
+    Replace { pattern: Word, replacement: Word, all: bool }, // ${x/pattern/replacement}, ${x//pattern/replacement}
+}
+
+// NAME=value, either on its own or in front of a command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    pub fn new() -> Word {
+        Word::default()
+    }
+
+    pub fn quoted(text: &str) -> Word {
+        Word {
+            parts: vec![WordPart::Quoted(text.to_string())],
+        }
+    }
+
+    // adds a part to the word, merging neighbouring text of the same kind.
+    pub fn push(&mut self, part: WordPart) {
+        match (self.parts.last_mut(), part) {
+            (Some(WordPart::Literal(prev)), WordPart::Literal(text)) => prev.push_str(&text),
+            (Some(WordPart::Quoted(prev)), WordPart::Quoted(text)) => prev.push_str(&text),
+            (_, part) => self.parts.push(part),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    // the word printed without any quotes, for words that were parsed like a here-document, e.g. $((expr)).
+    fn raw(&self) -> String {
+        self.parts.iter().map(|part| match part {
+            WordPart::Literal(text) | WordPart::Quoted(text) => text.clone(),
+            WordPart::Param { name, op, .. } => param_text(name, op, true),
+            WordPart::Command { node, .. } => format!("$({})", node),
+            WordPart::Arith { expr, .. } => format!("$(({}))", expr.raw()),
+        }).collect()
+    }
+}
+
+impl From<&str> for Word {
+    fn from(text: &str) -> Word {
+        Word {
+            parts: vec![WordPart::Literal(text.to_string())],
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RshNode {
+    // the name is empty for a command made up only of assignments or redirects, e.g. "FOO=1 > file"
     Command {
-        name: String,
-        args: Vec<String>,
+        assignments: Vec<Assignment>,
+        name: Word,
+        args: Vec<Word>,
+        redirects: Vec<Redirect>,
     },
     Pipe {
         left: Box<RshNode>,
         right: Box<RshNode>,
     },
-    Redirect {
+    Background {
         command: Box<RshNode>,
-        file: String,
-        mode: RedirectMode,
     },
-    Background {
+    // a ; b - run left, wait for it, then run right.
+    Sequence {
+        left: Box<RshNode>,
+        right: Box<RshNode>,
+    },
+    // a && b - only run right if left succeeded.
+    And {
+        left: Box<RshNode>,
+        right: Box<RshNode>,
+    },
+    // a || b - only run right if left failed.
+    Or {
+        left: Box<RshNode>,
+        right: Box<RshNode>,
+    },
+    // ((expr)) - succeeds if the expression is non-zero.
+    Arith {
+        expr: Word,
+    },
+    // if a; then b; elif c; then d; else e; fi - runs the body of the first condition that succeeds.
+    If {
+        branches: Vec<(RshNode, RshNode)>,
+        otherwise: Option<Box<RshNode>>,
+    },
+    // while a; do b; done - with "until" the body runs for as long as the condition fails instead.
+    While {
+        condition: Box<RshNode>,
+        body: Box<RshNode>,
+        until: bool,
+    },
+    // for name in words; do body; done - "for name; do" loops over "$@".
+    For {
+        name: String,
+        words: Vec<Word>,
+        body: Box<RshNode>,
+    },
+    // for ((init; condition; step)); do body; done - an empty condition is parsed as 1, so it's always true.
+    ArithFor {
+        init: Word,
+        condition: Word,
+        step: Word,
+        body: Box<RshNode>,
+    },
+    // case word in pattern|pattern) body;; esac
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    },
+    // name() { body; } or function name { body; } - defines the function, the body can be any compound command.
+    Function {
+        name: String,
+        body: Box<RshNode>,
+    },
+    // ( list ) - runs in a forked copy of the shell, so nothing it changes is seen outside of it.
+    Subshell {
+        body: Box<RshNode>,
+    },
+    // { list; } - runs in the shell itself, grouped so it can be redirected or piped as a whole.
+    Group {
+        body: Box<RshNode>,
+    },
+    // a compound command with redirections after it, e.g. "{ a; b; } > log", which apply to everything inside.
+    Redirected {
         command: Box<RshNode>,
+        redirects: Vec<Redirect>,
     },
+    // a program with nothing to run in it, only blank lines and comments.
+    Empty,
+}
+
+// how a case item ends, which decides what happens after its body runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseEnd {
+    Break,       // ;; - the case is done
+    Fallthrough, // ;& - the next item's body runs too, whatever its patterns
+    Continue,    // ;;& - the patterns of the next items are tried as well
+}
+
+// one "pattern|pattern) body;;" of a case, the body is None if there's nothing between the ")" and the ";;".
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: Option<RshNode>,
+    pub end: CaseEnd,
 }
 
 impl RshNode {
     pub fn is_background(&self) -> bool {
-        match self {
-            RshNode::Background { .. } => true,
-            _ => false,
-        }
+        matches!(self, RshNode::Background { .. })
     }
 
-    pub fn get_name(&self) -> Option<&str> {
+    pub fn get_name(&self) -> Option<&Word> {
         match self {
             RshNode::Command { name, .. } =>Some(name),
-            RshNode::Redirect { command, .. } => command.get_name(),
             RshNode::Background { command } => command.get_name(),
             _ => None,
         }
     }
 
-    pub fn get_args(&self) -> Option<&[String]> {
+    pub fn get_args(&self) -> Option<&[Word]> {
         match self {
             RshNode::Command { args, .. } => Some(args),
-            RshNode::Redirect { command, .. } => command.get_args(),
             RshNode::Background { command } => command.get_args(),
             _ => None,
         }
     }
 }
 
+/**
+* Trees print back as shell source, e.g. for showing a job's command line. The text is equivalent to what was
+* typed rather than identical to it: quoting is normalized and here-document bodies are left out.
+*/
+impl Display for RshNode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RshNode::Command { assignments, name, args, redirects } => {
+                let mut words = Vec::new();
+                for assignment in assignments {
+                    words.push(format!("{}={}", assignment.name, assignment.value));
+                }
+                if !name.is_empty() {
+                    words.push(name.to_string());
+                }
+                words.extend(args.iter().map(|arg| arg.to_string()));
+                words.extend(redirects.iter().map(|redirect| redirect.to_string()));
+                write!(f, "{}", words.join(" "))
+            },
+            RshNode::Pipe { left, right } => write!(f, "{} | {}", left, right),
+            RshNode::Background { command } => write!(f, "{} &", command),
+            RshNode::Sequence { left, right } if left.is_background() => write!(f, "{} {}", left, right),
+            RshNode::Sequence { left, right } => write!(f, "{}; {}", left, right),
+            RshNode::And { left, right } => write!(f, "{} && {}", left, right),
+            RshNode::Or { left, right } => write!(f, "{} || {}", left, right),
+            RshNode::Arith { expr } => write!(f, "(({}))", expr.raw()),
+            RshNode::If { branches, otherwise } => {
+                for (idx, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if idx == 0 { "if" } else { "elif" };
+                    write!(f, "{} {} then {} ", keyword, terminated(condition), terminated(body))?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else {} ", terminated(otherwise))?;
+                }
+                write!(f, "fi")
+            },
+            RshNode::While { condition, body, until } => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{} {} do {} done", keyword, terminated(condition), terminated(body))
+            },
+            RshNode::For { name, words, body } => {
+                let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+                write!(f, "for {} in {}; do {} done", name, words.join(" "), terminated(body))
+            },
+            RshNode::ArithFor { init, condition, step, body } => {
+                write!(f, "for (({}; {}; {})); do {} done", init.raw(), condition.raw(), step.raw(), terminated(body))
+            },
+            RshNode::Case { word, items } => {
+                write!(f, "case {} in", word)?;
+                for item in items {
+                    let patterns: Vec<String> = item.patterns.iter().map(|pattern| pattern.to_string()).collect();
+                    write!(f, " {})", patterns.join(" | "))?;
+                    if let Some(body) = &item.body {
+                        write!(f, " {}", body)?;
+                        // "a &;;" doesn't parse, the "&" needs room.
+                        if ends_in_background(body) {
+                            write!(f, " ")?;
+                        }
+                    }
+                    let end = match item.end {
+                        CaseEnd::Break => ";;",
+                        CaseEnd::Fallthrough => ";&",
+                        CaseEnd::Continue => ";;&",
+                    };
+                    write!(f, "{}", end)?;
+                }
+                write!(f, " esac")
+            },
+            RshNode::Function { name, body } => write!(f, "{}() {}", name, body),
+            // spaced out, since "((" would start an arithmetic command.
+            RshNode::Subshell { body } => write!(f, "( {} )", body),
+            RshNode::Group { body } => write!(f, "{{ {} }}", terminated(body)),
+            RshNode::Redirected { command, redirects } => {
+                let redirects: Vec<String> = redirects.iter().map(|redirect| redirect.to_string()).collect();
+                write!(f, "{} {}", command, redirects.join(" "))
+            },
+            RshNode::Empty => Ok(()),
+        }
+    }
+}
+
+// a list followed by what ends it inside a compound command, a ";" unless it already ends with "&".
+fn terminated(node: &RshNode) -> String {
+    if ends_in_background(node) {
+        node.to_string()
+    } else {
+        format!("{};", node)
+    }
+}
+
+fn ends_in_background(node: &RshNode) -> bool {
+    match node {
+        RshNode::Sequence { right, .. } => ends_in_background(right),
+        _ => node.is_background(),
+    }
+}
+
+impl Display for Redirect {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (op, default_fd) = match self.mode {
+            RedirectMode::Read => ("<", 0),
+            RedirectMode::Write => (">", 1),
+            RedirectMode::Append => (">>", 1),
+            RedirectMode::ReadWrite => ("<>", 0),
+            RedirectMode::Duplicate if self.fd == 0 => ("<&", 0),
+            RedirectMode::Duplicate => (">&", 1),
+            RedirectMode::Close if self.fd == 0 => ("<&-", 0),
+            RedirectMode::Close => (">&-", 1),
+            // the delimiter isn't kept, only the body.
+            RedirectMode::HereDoc => ("<<EOF", 0),
+            RedirectMode::HereString => ("<<<", 0),
+        };
+
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        match self.mode {
+            RedirectMode::Close | RedirectMode::HereDoc => write!(f, "{}", op),
+            _ => write!(f, "{}{}", op, self.target),
+        }
+    }
+}
+
+// parts that came from double quotes are printed between double quotes again, everything else as it was.
+impl Display for Word {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut in_quotes = false;
+        for (idx, part) in self.parts.iter().enumerate() {
+            let (quoted, text) = match part {
+                WordPart::Literal(text) => (false, text.clone()),
+                WordPart::Quoted(text) => (true, escape_quoted(text)),
+                WordPart::Param { name, op, quoted } => {
+                    // $x followed by more name characters would read as a different name without the braces.
+                    let joined = matches!(self.parts.get(idx + 1), Some(WordPart::Literal(next) | WordPart::Quoted(next))
+                        if next.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'));
+                    (*quoted, param_text(name, op, joined))
+                },
+                WordPart::Command { node, quoted } => (*quoted, format!("$({})", node)),
+                WordPart::Arith { expr, quoted } => (*quoted, format!("$(({}))", expr.raw())),
+            };
+
+            if quoted != in_quotes {
+                write!(f, "\"")?;
+                in_quotes = quoted;
+            }
+            write!(f, "{}", text)?;
+        }
+
+        if in_quotes {
+            write!(f, "\"")?;
+        }
+        Ok(())
+    }
+}
+
+// a parameter expansion as it would be written, "$x" or "${x...}".
+fn param_text(name: &str, op: &ParamOp, joined: bool) -> String {
+    match op {
+        ParamOp::Plain if joined || (name.len() > 1 && name.starts_with(|c: char| c.is_ascii_digit())) => format!("${{{}}}", name),
+        ParamOp::Plain => format!("${}", name),
+        ParamOp::Length => format!("${{#{}}}", name),
+        ParamOp::Default { word, colon } => format!("${{{}{}-{}}}", name, if *colon { ":" } else { "" }, word),
+        ParamOp::Assign { word, colon } => format!("${{{}{}={}}}", name, if *colon { ":" } else { "" }, word),
+        ParamOp::Error { word, colon } => format!("${{{}{}?{}}}", name, if *colon { ":" } else { "" }, word),
+        ParamOp::Alternative { word, colon } => format!("${{{}{}+{}}}", name, if *colon { ":" } else { "" }, word),
+        ParamOp::RemovePrefix { pattern, longest } => format!("${{{}{}{}}}", name, if *longest { "##" } else { "#" }, pattern),
+        ParamOp::RemoveSuffix { pattern, longest } => format!("${{{}{}{}}}", name, if *longest { "%%" } else { "%" }, pattern),
+        ParamOp::Replace { pattern, replacement, all } => {
+            format!("${{{}{}{}/{}}}", name, if *all { "//" } else { "/" }, pattern, replacement)
+        },
+    }
+}
+
+// text made safe to put between double quotes.
+fn escape_quoted(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 pub struct Parser<'src> {
     tokenizer: Tokenizer<'src>,
 }
@@ -78,317 +526,2122 @@ impl<'src> Parser<'src> {
         }
     }
 
-    pub fn parse(&mut self) -> ParseResult {
-        self.parse_command()
+    pub fn parse(&mut self) -> Result<RshNode, ParseError> {
+        self.parse_source().map_err(|kind| {
+            let source = self.tokenizer.source();
+            // the end of the input is just past its last character, not on the blank lines after it.
+            let offset = match kind {
+                ParseErrorKind::UnexpectedEOF => source.trim_end().len(),
+                _ => self.tokenizer.last_start(),
+            };
+            ParseError::new(kind, source, offset)
+        })
     }
 
-    fn parse_command(&mut self) -> ParseResult {
-        let mut command = self.parse_simple_command()?;
+    fn parse_source(&mut self) -> ParseResult {
+        self.skip_newlines();
+        if self.tokenizer.peek_next().is_none() {
+            return Ok(RshNode::Empty);
+        }
+        let list = self.parse_list()?;
+        self.skip_newlines();
 
-        while let Some(token) = self.tokenizer.peek_next() {
-            match token {
-                Token::Pipe => {
+        if let Some(token) = self.tokenizer.next_token() {
+            return Err(ParseErrorKind::UnexpectedToken(token.to_string()));
+        }
+
+        Ok(list)
+    }
+
+    // a list is a series of and/or lists separated by ";", "&" or newlines. A trailing separator is allowed.
+    fn parse_list(&mut self) -> ParseResult {
+        let mut items = Vec::new();
+
+        loop {
+            let mut item = self.parse_and_or()?;
+            self.skip_whitespace();
+
+            match self.tokenizer.peek_next() {
+                Some(Token::Semicolon | Token::Newline) => {
                     self.tokenizer.next_token();
-                    let right = self.parse_command()?;
-                    command = RshNode::Pipe {
-                        left: Box::new(command),
-                        right: Box::new(right),
-                    };
                 },
-                Token::RedirectOutput => {
+                Some(Token::Background) => {
                     self.tokenizer.next_token();
-                    let file = self.parse_argument()?;
-                    command = RshNode::Redirect {
-                        command: Box::new(command),
-                        file,
-                        mode: RedirectMode::Write,
+                    item = RshNode::Background {
+                        command: Box::new(item),
                     };
                 },
-                Token::RedirectInput => {
+                _ => {
+                    items.push(item);
+                    break;
+                },
+            }
+
+            items.push(item);
+            self.skip_newlines();
+
+            if !self.at_command_start() {
+                break;
+            }
+        }
+
+        let mut items = items.into_iter();
+        // the loop above always pushes at least one item.
+        let mut list = items.next().ok_or(ParseErrorKind::UnexpectedEOF)?;
+        for right in items {
+            list = RshNode::Sequence {
+                left: Box::new(list),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(list)
+    }
+
+    // pipelines joined by "&&" or "||", both of which have equal precedence and associate to the left.
+    fn parse_and_or(&mut self) -> ParseResult {
+        let mut node = self.parse_pipeline()?;
+
+        loop {
+            self.skip_whitespace();
+            match self.tokenizer.peek_next() {
+                Some(Token::And) => {
                     self.tokenizer.next_token();
-                    let file = self.parse_argument()?;
-                    command = RshNode::Redirect {
-                        command: Box::new(command),
-                        file,
-                        mode: RedirectMode::Read,
+                    self.skip_newlines();
+                    let right = self.parse_pipeline()?;
+                    node = RshNode::And {
+                        left: Box::new(node),
+                        right: Box::new(right),
                     };
                 },
-                Token::Background => {
+                Some(Token::Or) => {
                     self.tokenizer.next_token();
-                    command = RshNode::Background {
-                        command: Box::new(command),
+                    self.skip_newlines();
+                    let right = self.parse_pipeline()?;
+                    node = RshNode::Or {
+                        left: Box::new(node),
+                        right: Box::new(right),
                     };
                 },
                 _ => break,
             }
         }
 
-        Ok(command)
+        Ok(node)
     }
 
-    fn parse_simple_command(&mut self) -> ParseResult {
-        let name = self.parse_argument()?;
-        let mut args = Vec::new();
+    fn parse_pipeline(&mut self) -> ParseResult {
+        let command = self.parse_command()?;
+        self.skip_whitespace();
 
-        while let Some(token) = self.tokenizer.peek_next() {
-            match token {
-                Token::Text(_) | Token::SingleQuote | Token::DoubleQuote => {
-                    args.push(self.parse_argument()?);
-                },
-                Token::Space => { self.skip_whitespace() }
-                _ => break,
-            }
+        if self.next_is(Token::Pipe) {
+            self.tokenizer.next_token();
+            self.skip_newlines();
+            let right = self.parse_pipeline()?;
+            return Ok(RshNode::Pipe {
+                left: Box::new(command),
+                right: Box::new(right),
+            });
         }
 
-        Ok(RshNode::Command { name, args })
+        Ok(command)
     }
 
-    fn parse_argument(&mut self) -> Result<String, ParseError> {
+    fn parse_command(&mut self) -> ParseResult {
         self.skip_whitespace();
-
-        if self.next_is(Token::SingleQuote) {
-          return self.parse_until_next(Token::SingleQuote);
+        if self.tokenizer.rest().starts_with("((") {
+            self.tokenizer.next_char();
+            self.tokenizer.next_char();
+            let expr = self.parse_arith_text()?;
+            return Ok(RshNode::Arith { expr });
         }
 
-        if self.next_is(Token::DoubleQuote){
-          return self.parse_until_next(Token::DoubleQuote);
+        if let Some(name) = self.function_name() {
+            self.tokenizer.next_token();
+            return self.parse_function(name);
         }
 
-        if let Some(token) = self.tokenizer.next_token() {
-            match token {
-              Token::Text(t) => { 
-                return Ok(t.to_string());
-              },
-              _ => { return Err(ParseError::UnexpectedToken(token.to_string())) }
+        let command = match self.tokenizer.peek_reserved() {
+            Some("if") => self.parse_if()?,
+            Some("while") => self.parse_while(false)?,
+            Some("until") => self.parse_while(true)?,
+            Some("for") => self.parse_for()?,
+            Some("case") => self.parse_case()?,
+            Some("{") => self.parse_group()?,
+            Some("function") => {
+                self.tokenizer.next_token();
+                self.skip_whitespace();
+                let word = self.parse_word()?;
+                return match word.parts.as_slice() {
+                    [WordPart::Literal(name)] if is_function_name(name) => self.parse_function(name.clone()),
+                    _ => Err(ParseErrorKind::UnexpectedToken(word.to_string())),
+                };
+            },
+            Some(word) if self.at_list_end() => {
+                self.tokenizer.next_token();
+                return Err(ParseErrorKind::UnexpectedToken(word.to_string()));
+            },
+            _ if self.next_is(Token::LeftParen) => self.parse_subshell()?,
+            _ => return self.parse_simple_command(),
+        };
+
+        // a compound command can be followed by redirections, which apply to all of it.
+        let mut redirects = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if !self.at_redirect() {
+                break;
             }
+            self.parse_redirect(&mut redirects)?;
         }
+        if redirects.is_empty() {
+            return Ok(command);
+        }
+        Ok(RshNode::Redirected { command: Box::new(command), redirects })
+    }
 
-        Err(ParseError::UnexpectedEOF)
+    // ( list )
+    fn parse_subshell(&mut self) -> ParseResult {
+        self.tokenizer.next_token();
+        let body = self.parse_body()?;
+        self.skip_newlines();
+        match self.tokenizer.next_token() {
+            Some(Token::RightParen) => Ok(RshNode::Subshell { body: Box::new(body) }),
+            Some(token) => Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+            None => Err(ParseErrorKind::UnexpectedEOF),
+        }
     }
 
-    // collects stuff into a string until it hits the next token, "token".
-    fn parse_until_next(&mut self, token: Token) -> Result<String, ParseError> {
+    // { list; }
+    fn parse_group(&mut self) -> ParseResult {
         self.tokenizer.next_token();
+        let body = self.parse_body()?;
+        self.expect_reserved("}")?;
+        Ok(RshNode::Group { body: Box::new(body) })
+    }
 
-        if self.tokenizer.is_empty() {
-          return Err(ParseError::UnexpectedEOF);
+    // the name of a function being defined with "name()", if that's what's next.
+    fn function_name(&mut self) -> Option<String> {
+        let Some(Token::Text(name)) = self.tokenizer.peek_next() else {
+            return None;
+        };
+        let rest = self.tokenizer.rest()[name.len()..].trim_start_matches([' ', '\t']);
+        let parens = rest.strip_prefix('(')?.trim_start_matches([' ', '\t']).starts_with(')');
+        if !parens || !is_function_name(name) || self.tokenizer.peek_reserved().is_some() {
+            return None;
+        }
+        Some(name.to_string())
+    }
+
+    /**
+    * The rest of a function definition once its name has been read: the "()", which is optional after
+    * "function name", and the body, which is usually a group in braces but can be any compound command.
+    */
+    fn parse_function(&mut self, name: String) -> ParseResult {
+        self.skip_whitespace();
+        if self.next_is(Token::LeftParen) {
+            self.tokenizer.next_token();
+            self.skip_whitespace();
+            match self.tokenizer.next_token() {
+                Some(Token::RightParen) => {},
+                Some(token) => return Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+                None => return Err(ParseErrorKind::UnexpectedEOF),
+            }
         }
 
-        let mut res = String::new();
-        while let Some(t) = self.tokenizer.next_token() {
-          if t == token { break; };
-          res.push_str(&t.to_string());
+        self.skip_newlines();
+        let compound = matches!(self.tokenizer.peek_reserved(), Some("{" | "if" | "while" | "until" | "for" | "case"));
+        if !compound && !self.next_is(Token::LeftParen) {
+            return match self.tokenizer.next_token() {
+                Some(token) => Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+                None => Err(ParseErrorKind::UnexpectedEOF),
+            };
         }
 
-        Ok(res)
+        let body = self.parse_command()?;
+        Ok(RshNode::Function { name, body: Box::new(body) })
     }
 
-    fn next_is(&mut self, token: Token) -> bool {
-      if let Some(t) = self.tokenizer.peek_next() {
-        return t == token;
-      }
-      return false;
+    // if list; then list; [elif list; then list;]... [else list;] fi
+    fn parse_if(&mut self) -> ParseResult {
+        self.tokenizer.next_token();
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        loop {
+            let condition = self.parse_body()?;
+            self.expect_reserved("then")?;
+            let body = self.parse_body()?;
+            branches.push((condition, body));
+
+            self.skip_newlines();
+            match self.tokenizer.peek_reserved() {
+                Some("elif") => {
+                    self.tokenizer.next_token();
+                },
+                Some("else") => {
+                    self.tokenizer.next_token();
+                    otherwise = Some(Box::new(self.parse_body()?));
+                    self.expect_reserved("fi")?;
+                    break;
+                },
+                _ => {
+                    self.expect_reserved("fi")?;
+                    break;
+                },
+            }
+        }
+
+        Ok(RshNode::If { branches, otherwise })
     }
 
-    fn skip_whitespace(&mut self) {
-        self.tokenizer.skip_whitespace();
+    // while list; do list; done, and the same for until.
+    fn parse_while(&mut self, until: bool) -> ParseResult {
+        self.tokenizer.next_token();
+        let condition = self.parse_body()?;
+        let body = self.parse_do_group()?;
+        Ok(RshNode::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+            until,
+        })
     }
-}
 
+    // for name [in words...]; do list; done, or for ((init; condition; step)); do list; done
+    fn parse_for(&mut self) -> ParseResult {
+        self.tokenizer.next_token();
+        self.skip_whitespace();
+        if self.tokenizer.rest().starts_with("((") {
+            self.tokenizer.next_char();
+            self.tokenizer.next_char();
+            return self.parse_arith_for();
+        }
 
-#[cfg(test)]
-mod integration {
-    use super::*;
+        let word = self.parse_word()?;
+        let name = match word.parts.as_slice() {
+            [WordPart::Literal(name)] if is_valid_name(name) => name.clone(),
+            _ => return Err(ParseErrorKind::UnexpectedToken(word.to_string())),
+        };
 
-    #[test]
-    fn test_parser_vanilla() {
-        let input = "echo hello";
-        let mut parser = Parser::new(input);
-        let result = parser.parse();
-        assert!(result.is_ok());
-        let unwrapped = result.unwrap();
-        assert_eq!(unwrapped, RshNode::Command {
-            name: "echo".to_string(),
-            args: vec!["hello".to_string()],
-        });
+        self.skip_newlines();
+        let words = if self.tokenizer.peek_reserved() == Some("in") {
+            self.tokenizer.next_token();
+            let mut words = Vec::new();
+            loop {
+                self.skip_whitespace();
+                if !self.at_word_start() {
+                    break;
+                }
+                words.push(self.parse_word()?);
+            }
+            // the words have to be ended, otherwise "do" would just be another one of them.
+            match self.tokenizer.next_token() {
+                Some(Token::Semicolon | Token::Newline) => {},
+                Some(token) => return Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+                None => return Err(ParseErrorKind::UnexpectedEOF),
+            }
+            words
+        } else {
+            if self.next_is(Token::Semicolon) {
+                self.tokenizer.next_token();
+            }
+            vec![Word {
+                parts: vec![WordPart::Param { name: "@".to_string(), op: ParamOp::Plain, quoted: true }],
+            }]
+        };
+
+        let body = self.parse_do_group()?;
+        Ok(RshNode::For { name, words, body: Box::new(body) })
     }
 
-    #[test]
-    fn test_parser_vanilla_double_quote() {
-        let input = "echo \"hello world\"";
-        let mut parser = Parser::new(input);
-        let result = parser.parse();
-        assert!(result.is_ok());
-        let unwrapped = result.unwrap();
-        assert_eq!(unwrapped, RshNode::Command {
-            name: "echo".to_string(),
-            args: vec!["hello world".to_string()],
-        });
+    // called after "for ((" has been consumed.
+    fn parse_arith_for(&mut self) -> ParseResult {
+        let text = self.read_arith_text()?;
+        let exprs = split_arith_for(&text);
+        let [init, condition, step] = exprs.as_slice() else {
+            return Err(ParseErrorKind::UnexpectedToken(format!("(({}))", text)));
+        };
+
+        let init = arith_word(init.trim())?;
+        let condition = arith_word(if condition.trim().is_empty() { "1" } else { condition.trim() })?;
+        let step = arith_word(step.trim())?;
+
+        self.skip_whitespace();
+        if matches!(self.tokenizer.peek_next(), Some(Token::Semicolon | Token::Newline)) {
+            self.tokenizer.next_token();
+        }
+        let body = self.parse_do_group()?;
+        Ok(RshNode::ArithFor { init, condition, step, body: Box::new(body) })
     }
 
-    #[test]
-    fn test_parser_vanilla_single_quote() {
-        let input = "echo 'hello world'";
-        let mut parser = Parser::new(input);
-        let result = parser.parse();
-        assert!(result.is_ok());
-        let unwrapped = result.unwrap();
-        assert_eq!(unwrapped, RshNode::Command {
-            name: "echo".to_string(),
-            args: vec!["hello world".to_string()],
-        });
+    // case word in [(]pattern[|pattern]...) list;; ... esac
+    fn parse_case(&mut self) -> ParseResult {
+        self.tokenizer.next_token();
+        let word = self.parse_word()?;
+        self.expect_reserved("in")?;
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.tokenizer.peek_reserved() == Some("esac") {
+                self.tokenizer.next_token();
+                break;
+            }
+
+            if self.next_is(Token::LeftParen) {
+                self.tokenizer.next_token();
+            }
+            let mut patterns = vec![self.parse_word()?];
+            loop {
+                self.skip_whitespace();
+                match self.tokenizer.next_token() {
+                    Some(Token::Pipe) => patterns.push(self.parse_word()?),
+                    Some(Token::RightParen) => break,
+                    Some(token) => return Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+                    None => return Err(ParseErrorKind::UnexpectedEOF),
+                }
+            }
+
+            self.skip_newlines();
+            let body = if self.at_command_start() { Some(self.parse_list()?) } else { None };
+            self.skip_newlines();
+            let end = match self.tokenizer.peek_next() {
+                Some(Token::CaseBreak) => CaseEnd::Break,
+                Some(Token::CaseFallthrough) => CaseEnd::Fallthrough,
+                Some(Token::CaseContinue) => CaseEnd::Continue,
+                // the last item doesn't need a ";;" before the "esac".
+                _ => {
+                    items.push(CaseItem { patterns, body, end: CaseEnd::Break });
+                    self.expect_reserved("esac")?;
+                    break;
+                },
+            };
+            self.tokenizer.next_token();
+            items.push(CaseItem { patterns, body, end });
+        }
+
+        Ok(RshNode::Case { word, items })
     }
 
-    #[test]
-    fn test_parser_pipe() {
-        let input = "ls -l|grep .rs";
-        let mut parser = Parser::new(input);
-        let result = parser.parse();
-        assert!(result.is_ok());
-        let unwrapped = result.unwrap();
-        assert_eq!(unwrapped, RshNode::Pipe {
-            left: Box::new(RshNode::Command {
-                name: "ls".to_string(),
-                args: vec!["-l".to_string()],
-            }),
-            right: Box::new(RshNode::Command {
-                name: "grep".to_string(),
-                args: vec![".rs".to_string()],
-            }),
-        });
+    // do list; done, the body of every loop.
+    fn parse_do_group(&mut self) -> ParseResult {
+        self.expect_reserved("do")?;
+        let body = self.parse_body()?;
+        self.expect_reserved("done")?;
+        Ok(body)
     }
 
-    #[test]
-    fn test_parser_pipe_multiple() {
-        let input = "ls -l | grep .rs | wc -l";
-        let mut parser = Parser::new(input);
-        let result = parser.parse();
-        if !result.is_ok() {
-          println!("{:?}", result);
-          assert!(false);
+    // the list inside a compound command, which may start on a new line and runs up to a reserved word like "fi".
+    fn parse_body(&mut self) -> ParseResult {
+        self.skip_newlines();
+        self.parse_list()
+    }
+
+    // consumes the reserved word that has to come next, e.g. the "then" after the condition of an if.
+    fn expect_reserved(&mut self, word: &str) -> Result<(), ParseErrorKind> {
+        self.skip_newlines();
+        if self.tokenizer.peek_reserved() == Some(word) {
+            self.tokenizer.next_token();
+            return Ok(());
         }
-        let unwrapped = result.unwrap();
+
+        match self.tokenizer.next_token() {
+            Some(token) => Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+            None => Err(ParseErrorKind::UnexpectedEOF),
+        }
+    }
+
+    // words and redirects can be mixed freely, e.g. "> out echo hi" is the same as "echo hi > out".
+    fn parse_simple_command(&mut self) -> ParseResult {
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.at_redirect() {
+                self.parse_redirect(&mut redirects)?;
+            } else if self.at_word_start() {
+                let word = self.parse_word()?;
+                // assignments are only recognized in front of the command name.
+                if words.is_empty() {
+                    match as_assignment(word) {
+                        Ok(assignment) => assignments.push(assignment),
+                        Err(word) => words.push(word),
+                    }
+                } else {
+                    words.push(word);
+                }
+            } else {
+                break;
+            }
+        }
+
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            // reports whatever is in the way of the command.
+            self.parse_word()?;
+        }
+
+        let mut words = words.into_iter();
+        let name = words.next().unwrap_or_default();
+        let args = words.collect();
+        Ok(RshNode::Command { assignments, name, args, redirects })
+    }
+
+    fn parse_redirect(&mut self, redirects: &mut Vec<Redirect>) -> Result<(), ParseErrorKind> {
+        let mut fd = None;
+        if let Some(Token::IoNumber(n)) = self.tokenizer.peek_next() {
+            self.tokenizer.next_token();
+            fd = Some(n.parse::<i32>().map_err(|_| ParseErrorKind::UnexpectedToken(n.to_string()))?);
+        }
+
+        let op = self.tokenizer.next_token().ok_or(ParseErrorKind::UnexpectedEOF)?;
+        // a redirection missing its target is an error, not something the next line finishes.
+        self.skip_whitespace();
+        if self.tokenizer.is_empty() {
+            return Err(ParseErrorKind::UnexpectedToken("newline".to_string()));
+        }
+        let target = self.parse_word()?;
+
+        match op {
+            Token::HereDoc | Token::HereDocStrip => {
+                let target = self.parse_heredoc(&target, op == Token::HereDocStrip)?;
+                redirects.push(Redirect { fd: fd.unwrap_or(0), mode: RedirectMode::HereDoc, target });
+            },
+            Token::HereString => redirects.push(Redirect { fd: fd.unwrap_or(0), mode: RedirectMode::HereString, target }),
+            Token::RedirectInput => redirects.push(Redirect { fd: fd.unwrap_or(0), mode: RedirectMode::Read, target }),
+            Token::RedirectOutput => redirects.push(Redirect { fd: fd.unwrap_or(1), mode: RedirectMode::Write, target }),
+            Token::Append => redirects.push(Redirect { fd: fd.unwrap_or(1), mode: RedirectMode::Append, target }),
+            Token::ReadWrite => redirects.push(Redirect { fd: fd.unwrap_or(0), mode: RedirectMode::ReadWrite, target }),
+            Token::DupOutput | Token::DupInput => {
+                let default_fd = if op == Token::DupInput { 0 } else { 1 };
+                let mode = if target == Word::from("-") { RedirectMode::Close } else { RedirectMode::Duplicate };
+                redirects.push(Redirect { fd: fd.unwrap_or(default_fd), mode, target });
+            },
+            // &>file and &>>file are shorthand for >file 2>&1 and >>file 2>&1
+            Token::RedirectAll | Token::AppendAll => {
+                if let Some(fd) = fd {
+                    return Err(ParseErrorKind::UnexpectedToken(format!("{}{}", fd, op)));
+                }
+                let mode = if op == Token::AppendAll { RedirectMode::Append } else { RedirectMode::Write };
+                redirects.push(Redirect { fd: 1, mode, target });
+                redirects.push(Redirect { fd: 2, mode: RedirectMode::Duplicate, target: Word::from("1") });
+            },
+            _ => return Err(ParseErrorKind::UnexpectedToken(op.to_string())),
+        }
+
+        Ok(())
+    }
+
+    // a word runs until the next unquoted space or operator, e.g. a"b c"'d' is the single word "ab cd".
+    fn parse_word(&mut self) -> Result<Word, ParseErrorKind> {
+        self.skip_whitespace();
+        let mut word = Word::new();
+
+        while let Some(token) = self.tokenizer.peek_next() {
+            match token {
+                Token::Text(t) => {
+                    self.tokenizer.next_token();
+                    word.push(WordPart::Literal(t.to_string()));
+                },
+                Token::SingleQuote => {
+                    self.tokenizer.next_token();
+                    let text = self.tokenizer.take_while(|c| c != '\'');
+                    if self.tokenizer.next_char().is_none() {
+                        return Err(ParseErrorKind::UnexpectedEOF);
+                    }
+                    word.push(WordPart::Quoted(text.to_string()));
+                },
+                Token::DoubleQuote => {
+                    self.tokenizer.next_token();
+                    self.parse_double_quoted(&mut word)?;
+                },
+                Token::Dollar => {
+                    self.tokenizer.next_token();
+                    word.push(self.parse_dollar(false)?);
+                },
+                Token::Backtick => {
+                    self.tokenizer.next_token();
+                    word.push(self.parse_backquoted(false)?);
+                },
+                Token::Backslash => {
+                    self.tokenizer.next_token();
+                    match self.tokenizer.next_char() {
+                        // a line continuation, the word goes on on the next line.
+                        Some('\n') => {},
+                        Some(c) => word.push(WordPart::Quoted(c.to_string())),
+                        None => return Err(ParseErrorKind::UnexpectedEOF),
+                    }
+                },
+                _ => break,
+            }
+        }
+
+        if word.is_empty() {
+            return match self.tokenizer.next_token() {
+                Some(token) => Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+                None => Err(ParseErrorKind::UnexpectedEOF),
+            };
+        }
+
+        Ok(word)
+    }
+
+    /**
+    * Reads the body of a here-document whose delimiter is "delimiter". If any part of the delimiter was quoted
+    * the body is taken literally, otherwise it gets the same expansions as a double quoted string.
+    */
+    fn parse_heredoc(&mut self, delimiter: &Word, strip_tabs: bool) -> Result<Word, ParseErrorKind> {
+        let mut quoted = false;
+        let mut text = String::new();
+        for part in &delimiter.parts {
+            match part {
+                WordPart::Literal(t) => text.push_str(t),
+                WordPart::Quoted(t) => {
+                    quoted = true;
+                    text.push_str(t);
+                },
+                // "$" only survives in a delimiter as text, e.g. <<$x
+                WordPart::Param { name, .. } => {
+                    text.push('$');
+                    text.push_str(name);
+                },
+                WordPart::Command { .. } | WordPart::Arith { .. } => return Err(ParseErrorKind::UnexpectedToken("$(".to_string())),
+            }
+        }
+
+        let body = self.tokenizer
+            .read_heredoc(&text, strip_tabs)
+            .ok_or(ParseErrorKind::UnterminatedHereDoc(text))?;
+
+        if quoted {
+            return Ok(Word::quoted(&body));
+        }
+
+        let mut word = Word::new();
+        Parser::new(&body).parse_quoted_text(&mut word, true)?;
+        Ok(word)
+    }
+
+    // everything up to the closing quote is taken literally, except for "$" and a few backslash escapes.
+    fn parse_double_quoted(&mut self, word: &mut Word) -> Result<(), ParseErrorKind> {
+        self.parse_quoted_text(word, false)
+    }
+
+    // the body of a here-document follows the double quote rules, except that it runs to the end of the input
+    // and a double quote is just a character.
+    fn parse_quoted_text(&mut self, word: &mut Word, heredoc: bool) -> Result<(), ParseErrorKind> {
+        let mut text = String::new();
+        let start = word.parts.len();
+
+        loop {
+            match self.tokenizer.next_char() {
+                Some('"') if !heredoc => break,
+                Some('\\') => match self.tokenizer.next_char() {
+                    Some(c @ ('$' | '`' | '\\')) => text.push(c),
+                    Some('"') if !heredoc => text.push('"'),
+                    Some('\n') => {},
+                    Some(c) => {
+                        text.push('\\');
+                        text.push(c);
+                    },
+                    None if heredoc => text.push('\\'),
+                    None => return Err(ParseErrorKind::UnexpectedEOF),
+                },
+                Some('$') => {
+                    if !text.is_empty() {
+                        word.push(WordPart::Quoted(std::mem::take(&mut text)));
+                    }
+                    word.push(self.parse_dollar(true)?);
+                },
+                Some('`') => {
+                    if !text.is_empty() {
+                        word.push(WordPart::Quoted(std::mem::take(&mut text)));
+                    }
+                    word.push(self.parse_backquoted(true)?);
+                },
+                Some(c) => text.push(c),
+                None if heredoc => break,
+                None => return Err(ParseErrorKind::UnexpectedEOF),
+            }
+        }
+
+        // "" on its own still produces a word. After an expansion it's left out, or "$@" would always be a field.
+        if !text.is_empty() || word.parts.len() == start {
+            word.push(WordPart::Quoted(text));
+        }
+        Ok(())
+    }
+
+    // called after a "$" has been consumed. A "$" that doesn't start an expansion is just text.
+    fn parse_dollar(&mut self, quoted: bool) -> Result<WordPart, ParseErrorKind> {
+        let name = match self.tokenizer.peek_char() {
+            Some('{') => {
+                self.tokenizer.next_char();
+                return self.parse_braced_param(quoted);
+            },
+            // $(( is always arithmetic, a command substitution that starts with a subshell needs a space: $( (a) ).
+            Some('(') if self.tokenizer.rest().starts_with("((") => {
+                self.tokenizer.next_char();
+                self.tokenizer.next_char();
+                let expr = self.parse_arith_text()?;
+                return Ok(WordPart::Arith { expr, quoted });
+            },
+            Some('(') => {
+                self.tokenizer.next_char();
+                let node = self.parse_substitution()?;
+                return match self.tokenizer.next_token() {
+                    Some(Token::RightParen) => Ok(WordPart::Command { node: Box::new(node), quoted }),
+                    Some(token) => Err(ParseErrorKind::UnexpectedToken(token.to_string())),
+                    None => Err(ParseErrorKind::UnexpectedEOF),
+                };
+            },
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                self.tokenizer.take_while(|c| c.is_ascii_alphanumeric() || c == '_').to_string()
+            },
+            // outside of braces positional parameters are a single digit, $10 is ${1}0.
+            Some(c) if c.is_ascii_digit() || is_special_param(c) => {
+                self.tokenizer.next_char();
+                c.to_string()
+            },
+            _ if quoted => return Ok(WordPart::Quoted("$".to_string())),
+            _ => return Ok(WordPart::Literal("$".to_string())),
+        };
+
+        Ok(WordPart::Param { name, op: ParamOp::Plain, quoted })
+    }
+
+    /**
+    * Called after an opening backquote has been consumed. The text up to the closing backquote is parsed
+    * as a script of its own, after removing the backslashes that escape "$", "`" and "\\" (and "\"" between
+    * double quotes).
+    */
+    fn parse_backquoted(&mut self, quoted: bool) -> Result<WordPart, ParseErrorKind> {
+        let mut source = String::new();
+
+        loop {
+            match self.tokenizer.next_char() {
+                Some('`') => break,
+                Some('\\') => match self.tokenizer.next_char() {
+                    Some(c @ ('$' | '`' | '\\')) => source.push(c),
+                    Some('"') if quoted => source.push('"'),
+                    Some(c) => {
+                        source.push('\\');
+                        source.push(c);
+                    },
+                    None => return Err(ParseErrorKind::UnexpectedEOF),
+                },
+                Some(c) => source.push(c),
+                None => return Err(ParseErrorKind::UnexpectedEOF),
+            }
+        }
+
+        let mut parser = Parser::new(&source);
+        let node = parser.parse_substitution()?;
+        if let Some(token) = parser.tokenizer.next_token() {
+            return Err(ParseErrorKind::UnexpectedToken(token.to_string()));
+        }
+
+        Ok(WordPart::Command { node: Box::new(node), quoted })
+    }
+
+    /**
+    * The expression of $((...)) or ((...)), called after the opening parens. It runs to the first "))" that
+    * isn't closing a paren of its own, and gets the same expansions as the inside of double quotes.
+    */
+    fn parse_arith_text(&mut self) -> Result<Word, ParseErrorKind> {
+        let text = self.read_arith_text()?;
+        arith_word(&text)
+    }
+
+    // the text of $((...)) or ((...)) up to the closing parens, which are consumed but left out.
+    fn read_arith_text(&mut self) -> Result<String, ParseErrorKind> {
+        let mut text = String::new();
+        let mut depth = 0;
+
+        loop {
+            match self.tokenizer.next_char() {
+                Some('(') => {
+                    depth += 1;
+                    text.push('(');
+                },
+                Some(')') if depth > 0 => {
+                    depth -= 1;
+                    text.push(')');
+                },
+                Some(')') => match self.tokenizer.next_char() {
+                    Some(')') => break,
+                    Some(c) => return Err(ParseErrorKind::UnexpectedChar(c)),
+                    None => return Err(ParseErrorKind::UnexpectedEOF),
+                },
+                Some(c) => text.push(c),
+                None => return Err(ParseErrorKind::UnexpectedEOF),
+            }
+        }
+
+        Ok(text)
+    }
+
+    // the commands inside $(...) or `...`, which unlike a line of input are allowed to be empty.
+    fn parse_substitution(&mut self) -> ParseResult {
+        self.skip_newlines();
+        if !self.at_command_start() {
+            return Ok(RshNode::Command {
+                assignments: vec![],
+                name: Word::new(),
+                args: vec![],
+                redirects: vec![],
+            });
+        }
+
+        let list = self.parse_list()?;
+        self.skip_newlines();
+        Ok(list)
+    }
+
+    // called after "${" has been consumed.
+    fn parse_braced_param(&mut self, quoted: bool) -> Result<WordPart, ParseErrorKind> {
+        // ${#x} is the length of x, but ${#} on its own is the parameter named "#".
+        let rest = self.tokenizer.rest();
+        let length = rest.starts_with('#') && !rest.starts_with("#}");
+        if length {
+            self.tokenizer.next_char();
+        }
+
+        let name = match self.tokenizer.peek_char() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                self.tokenizer.take_while(|c| c.is_ascii_alphanumeric() || c == '_').to_string()
+            },
+            Some(c) if c.is_ascii_digit() => self.tokenizer.take_while(|c| c.is_ascii_digit()).to_string(),
+            Some(c) if is_special_param(c) => {
+                self.tokenizer.next_char();
+                c.to_string()
+            },
+            Some(c) => return Err(ParseErrorKind::UnexpectedChar(c)),
+            None => return Err(ParseErrorKind::UnexpectedEOF),
+        };
+
+        let op = if length {
+            ParamOp::Length
+        } else {
+            self.parse_param_op()?
+        };
+
+        match self.tokenizer.next_char() {
+            Some('}') => Ok(WordPart::Param { name, op, quoted }),
+            Some(c) => Err(ParseErrorKind::UnexpectedChar(c)),
+            None => Err(ParseErrorKind::UnexpectedEOF),
+        }
+    }
+
+    // the operator part of ${name<op>word}, leaving the closing brace in place.
+    fn parse_param_op(&mut self) -> Result<ParamOp, ParseErrorKind> {
+        let rest = self.tokenizer.rest();
+        let colon = rest.starts_with(':');
+        let op_char = if colon { rest.chars().nth(1) } else { rest.chars().next() };
+
+        if let Some(c @ ('-' | '=' | '?' | '+')) = op_char {
+            if colon {
+                self.tokenizer.next_char();
+            }
+            self.tokenizer.next_char();
+            let word = self.parse_param_word(&['}'])?;
+            return Ok(match c {
+                '-' => ParamOp::Default { word, colon },
+                '=' => ParamOp::Assign { word, colon },
+                '?' => ParamOp::Error { word, colon },
+                _ => ParamOp::Alternative { word, colon },
+            });
+        }
+
+        match op_char {
+            Some(c @ ('#' | '%')) if !colon => {
+                self.tokenizer.next_char();
+                let longest = self.tokenizer.peek_char() == Some(c);
+                if longest {
+                    self.tokenizer.next_char();
+                }
+                let pattern = self.parse_param_word(&['}'])?;
+                Ok(if c == '#' {
+                    ParamOp::RemovePrefix { pattern, longest }
+                } else {
+                    ParamOp::RemoveSuffix { pattern, longest }
+                })
+            },
+            Some('/') if !colon => {
+                self.tokenizer.next_char();
+                let all = self.tokenizer.peek_char() == Some('/');
+                if all {
+                    self.tokenizer.next_char();
+                }
+                let pattern = self.parse_param_word(&['/', '}'])?;
+                let replacement = if self.tokenizer.peek_char() == Some('/') {
+                    self.tokenizer.next_char();
+                    self.parse_param_word(&['}'])?
+                } else {
+                    Word::new()
+                };
+                Ok(ParamOp::Replace { pattern, replacement, all })
+            },
+            Some('}') if !colon => Ok(ParamOp::Plain),
+            Some(c) => Err(ParseErrorKind::UnexpectedChar(c)),
+            None => Err(ParseErrorKind::UnexpectedEOF),
+        }
+    }
+
+    /**
+    * The word inside ${...}. Spaces and operators are plain text here, the word only ends at one of
+    * the "stops" characters, which is left unconsumed.
+    */
+    fn parse_param_word(&mut self, stops: &[char]) -> Result<Word, ParseErrorKind> {
+        let mut word = Word::new();
+
+        loop {
+            match self.tokenizer.peek_char() {
+                Some(c) if stops.contains(&c) => break,
+                Some('\'') => {
+                    self.tokenizer.next_char();
+                    let text = self.tokenizer.take_while(|c| c != '\'');
+                    if self.tokenizer.next_char().is_none() {
+                        return Err(ParseErrorKind::UnexpectedEOF);
+                    }
+                    word.push(WordPart::Quoted(text.to_string()));
+                },
+                Some('"') => {
+                    self.tokenizer.next_char();
+                    self.parse_double_quoted(&mut word)?;
+                },
+                Some('\\') => {
+                    self.tokenizer.next_char();
+                    match self.tokenizer.next_char() {
+                        Some(c) => word.push(WordPart::Quoted(c.to_string())),
+                        None => return Err(ParseErrorKind::UnexpectedEOF),
+                    }
+                },
+                Some('$') => {
+                    self.tokenizer.next_char();
+                    word.push(self.parse_dollar(false)?);
+                },
+                Some('`') => {
+                    self.tokenizer.next_char();
+                    word.push(self.parse_backquoted(false)?);
+                },
+                Some(c) => {
+                    self.tokenizer.next_char();
+                    word.push(WordPart::Literal(c.to_string()));
+                },
+                None => return Err(ParseErrorKind::UnexpectedEOF),
+            }
+        }
+
+        Ok(word)
+    }
+
+    fn next_is(&mut self, token: Token) -> bool {
+      if let Some(t) = self.tokenizer.peek_next() {
+        return t == token;
+      }
+      false
+    }
+
+    // true if the next token can begin a word.
+    fn at_word_start(&mut self) -> bool {
+        matches!(
+            self.tokenizer.peek_next(),
+            Some(Token::Text(_) | Token::SingleQuote | Token::DoubleQuote | Token::Dollar | Token::Backtick | Token::Backslash)
+        )
+    }
+
+    fn at_redirect(&mut self) -> bool {
+        matches!(
+            self.tokenizer.peek_next(),
+            Some(
+                Token::IoNumber(_)
+                | Token::RedirectInput
+                | Token::RedirectOutput
+                | Token::Append
+                | Token::ReadWrite
+                | Token::DupOutput
+                | Token::DupInput
+                | Token::RedirectAll
+                | Token::AppendAll
+                | Token::HereDoc
+                | Token::HereDocStrip
+                | Token::HereString
+            )
+        )
+    }
+
+    // true at a reserved word that ends the list in front of it, like "then" or "done".
+    fn at_list_end(&mut self) -> bool {
+        matches!(self.tokenizer.peek_reserved(), Some("then" | "elif" | "else" | "fi" | "do" | "done" | "esac" | "}"))
+    }
+
+    // true if the next token can begin a new command.
+    fn at_command_start(&mut self) -> bool {
+        if self.at_list_end() {
+            return false;
+        }
+        self.at_word_start() || self.at_redirect() || self.next_is(Token::LeftParen)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.tokenizer.skip_whitespace();
+    }
+
+    // skips blank lines as well, for the places a command may continue onto the next line.
+    fn skip_newlines(&mut self) {
+        self.skip_whitespace();
+        while self.next_is(Token::Newline) {
+            self.tokenizer.next_token();
+            self.skip_whitespace();
+        }
+    }
+}
+
+// the single character parameters, $? and friends.
+fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*' | '-')
+}
+
+// function names can have a few more characters in them than variable names, e.g. "git-prompt".
+fn is_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
+
+// an arithmetic expression as a word, which gets the same expansions as the inside of double quotes.
+fn arith_word(text: &str) -> Result<Word, ParseErrorKind> {
+    let mut word = Word::new();
+    Parser::new(text).parse_quoted_text(&mut word, true)?;
+    Ok(word)
+}
+
+// the three expressions of "for ((init; condition; step))", split at the semicolons that aren't inside parens.
+fn split_arith_for(text: &str) -> Vec<&str> {
+    let mut exprs = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                exprs.push(&text[start..idx]);
+                start = idx + 1;
+            },
+            _ => {},
+        }
+    }
+    exprs.push(&text[start..]);
+    exprs
+}
+
+// splits NAME=value into an assignment, handing the word back untouched if it isn't one.
+fn as_assignment(mut word: Word) -> Result<Assignment, Word> {
+    let name = match word.parts.first() {
+        Some(WordPart::Literal(text)) => match text.split_once('=') {
+            Some((name, _)) if is_valid_name(name) => name.to_string(),
+            _ => return Err(word),
+        },
+        _ => return Err(word),
+    };
+
+    let rest = match word.parts.remove(0) {
+        WordPart::Literal(text) => text[name.len() + 1..].to_string(),
+        _ => unreachable!(),
+    };
+
+    let mut value = Word::new();
+    if !rest.is_empty() {
+        value.push(WordPart::Literal(rest));
+    }
+    for part in word.parts {
+        value.push(part);
+    }
+
+    Ok(Assignment { name, value })
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+
+    #[test]
+    fn test_parser_vanilla() {
+        let input = "echo hello";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![Word::from("hello")],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_vanilla_double_quote() {
+        let input = "echo \"hello world\"";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![Word::quoted("hello world")],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_vanilla_single_quote() {
+        let input = "echo 'hello world'";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![Word::quoted("hello world")],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_pipe() {
+        let input = "ls -l|grep .rs";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
         assert_eq!(unwrapped, RshNode::Pipe {
             left: Box::new(RshNode::Command {
-                name: "ls".to_string(),
-                args: vec!["-l".to_string()],
+                assignments: vec![],
+                name: Word::from("ls"),
+                args: vec![Word::from("-l")],
+                redirects: vec![],
+            }),
+            right: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("grep"),
+                args: vec![Word::from(".rs")],
+                redirects: vec![],
+            }),
+        });
+    }
+
+    #[test]
+    fn test_parser_pipe_multiple() {
+        let input = "ls -l | grep .rs | wc -l";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        if result.is_err() {
+          panic!("{:?}", result);
+        }
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Pipe {
+            left: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("ls"),
+                args: vec![Word::from("-l")],
+                redirects: vec![],
+            }),
+            right: Box::new(RshNode::Pipe {
+                left: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("grep"),
+                    args: vec![Word::from(".rs")],
+                    redirects: vec![],
+                }),
+                right: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("wc"),
+                    args: vec![Word::from("-l")],
+                    redirects: vec![],
+                }),
+            }),
+        });
+    }
+
+    #[test]
+    fn test_parser_pipe_multiple_background() {
+        let input = "ls -l|grep .rs|wc -l&";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+
+        // (a | b | c) &
+        assert_eq!(unwrapped, RshNode::Background {
+            command: Box::new(RshNode::Pipe {
+                left: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("ls"),
+                    args: vec![Word::from("-l")],
+                    redirects: vec![],
+                }),
+                right: Box::new(RshNode::Pipe {
+                    left: Box::new(RshNode::Command {
+                        assignments: vec![],
+                        name: Word::from("grep"),
+                        args: vec![Word::from(".rs")],
+                        redirects: vec![],
+                    }),
+                    right: Box::new(RshNode::Command {
+                        assignments: vec![],
+                        name: Word::from("wc"),
+                        args: vec![Word::from("-l")],
+                        redirects: vec![],
+                    }),
+                }),
+            }),
+        });
+    }
+
+    #[test]
+    fn test_parser_pipe_multiple_background_deep() {
+        // "&" terminates the pipeline, so it can't be piped into another command.
+        let input = "ls -l|grep .rs|wc -l&|something|something else";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+    }
+
+    #[test]
+    fn test_parser_sequence() {
+        let input = "cd src; ls -l;";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Sequence {
+            left: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("cd"),
+                args: vec![Word::from("src")],
+                redirects: vec![],
+            }),
+            right: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("ls"),
+                args: vec![Word::from("-l")],
+                redirects: vec![],
+            }),
+        });
+    }
+
+    #[test]
+    fn test_parser_and_or() {
+        let input = "make && ./run || echo failed";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+
+        // (a && b) || c
+        assert_eq!(unwrapped, RshNode::Or {
+            left: Box::new(RshNode::And {
+                left: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("make"),
+                    args: vec![],
+                    redirects: vec![],
+                }),
+                right: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("./run"),
+                    args: vec![],
+                    redirects: vec![],
+                }),
+            }),
+            right: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("echo"),
+                args: vec![Word::from("failed")],
+                redirects: vec![],
+            }),
+        });
+    }
+
+    #[test]
+    fn test_parser_and_or_pipe_background() {
+        let input = "sleep 1 & ls | wc -l && echo ok";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Sequence {
+            left: Box::new(RshNode::Background {
+                command: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("sleep"),
+                    args: vec![Word::from("1")],
+                    redirects: vec![],
+                }),
+            }),
+            right: Box::new(RshNode::And {
+                left: Box::new(RshNode::Pipe {
+                    left: Box::new(RshNode::Command {
+                        assignments: vec![],
+                        name: Word::from("ls"),
+                        args: vec![],
+                        redirects: vec![],
+                    }),
+                    right: Box::new(RshNode::Command {
+                        assignments: vec![],
+                        name: Word::from("wc"),
+                        args: vec![Word::from("-l")],
+                        redirects: vec![],
+                    }),
+                }),
+                right: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("echo"),
+                    args: vec![Word::from("ok")],
+                    redirects: vec![],
+                }),
+            }),
+        });
+    }
+
+    #[test]
+    fn test_parser_dangling_and() {
+        let input = "make &&";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+    }
+
+    #[test]
+    fn test_parser_redirec_to_file() {
+        let input = "ls -l > dir.txt";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("ls"),
+            args: vec![Word::from("-l")],
+            redirects: vec![Redirect {
+                fd: 1,
+                mode: RedirectMode::Write,
+                target: Word::from("dir.txt"),
+            }],
+        });
+    }
+
+    #[test]
+    fn test_parser_redirec_from_file() {
+        let input = "cat < dir.txt";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("cat"),
+            args: vec![],
+            redirects: vec![Redirect {
+                fd: 0,
+                mode: RedirectMode::Read,
+                target: Word::from("dir.txt"),
+            }],
+        });
+    }
+
+    #[test]
+    fn test_parser_word_concatenation() {
+        let input = "echo a\"b c\"'d' \\$x \"\"";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![
+                Word {
+                    parts: vec![
+                        WordPart::Literal("a".to_string()),
+                        WordPart::Quoted("b cd".to_string()),
+                    ],
+                },
+                Word {
+                    parts: vec![
+                        WordPart::Quoted("$".to_string()),
+                        WordPart::Literal("x".to_string()),
+                    ],
+                },
+                Word::quoted(""),
+            ],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_exit_status_param() {
+        let input = "echo $? \"code: $?\" '$?'";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![
+                Word {
+                    parts: vec![WordPart::Param { name: "?".to_string(), op: ParamOp::Plain, quoted: false }],
+                },
+                Word {
+                    parts: vec![
+                        WordPart::Quoted("code: ".to_string()),
+                        WordPart::Param { name: "?".to_string(), op: ParamOp::Plain, quoted: true },
+                    ],
+                },
+                Word::quoted("$?"),
+            ],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_unterminated_quote() {
+        let input = "echo \"hello";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+    }
+
+    #[test]
+    fn test_parser_redirect_list() {
+        let input = "2>&1 cmd >> log 2> err 3<>rw 4<&- &>all";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("cmd"),
+            args: vec![],
+            redirects: vec![
+                Redirect { fd: 2, mode: RedirectMode::Duplicate, target: Word::from("1") },
+                Redirect { fd: 1, mode: RedirectMode::Append, target: Word::from("log") },
+                Redirect { fd: 2, mode: RedirectMode::Write, target: Word::from("err") },
+                Redirect { fd: 3, mode: RedirectMode::ReadWrite, target: Word::from("rw") },
+                Redirect { fd: 4, mode: RedirectMode::Close, target: Word::from("-") },
+                Redirect { fd: 1, mode: RedirectMode::Write, target: Word::from("all") },
+                Redirect { fd: 2, mode: RedirectMode::Duplicate, target: Word::from("1") },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_parser_redirect_only() {
+        let input = "> out; cat <&3 | wc -l";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Sequence {
+            left: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::new(),
+                args: vec![],
+                redirects: vec![Redirect { fd: 1, mode: RedirectMode::Write, target: Word::from("out") }],
             }),
             right: Box::new(RshNode::Pipe {
                 left: Box::new(RshNode::Command {
-                    name: "grep".to_string(),
-                    args: vec![".rs".to_string()],
+                    assignments: vec![],
+                    name: Word::from("cat"),
+                    args: vec![],
+                    redirects: vec![Redirect { fd: 0, mode: RedirectMode::Duplicate, target: Word::from("3") }],
                 }),
                 right: Box::new(RshNode::Command {
-                    name: "wc".to_string(),
-                    args: vec!["-l".to_string()],
+                    assignments: vec![],
+                    name: Word::from("wc"),
+                    args: vec![Word::from("-l")],
+                    redirects: vec![],
                 }),
             }),
         });
     }
 
     #[test]
-    fn test_parser_pipe_multiple_background() {
-        let input = "ls -l|grep .rs|wc -l&";
+    fn test_parser_redirect_missing_target() {
+        let input = "echo hi >";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnexpectedToken(token), .. }) if token == "newline"));
+    }
+
+    #[test]
+    fn test_parser_newlines() {
+        let input = "\necho a\n\necho b &&\n  echo c\n";
         let mut parser = Parser::new(input);
         let result = parser.parse();
         assert!(result.is_ok());
         let unwrapped = result.unwrap();
-
-        // a | b | c  
-
-        assert_eq!(unwrapped, RshNode::Pipe {
+        assert_eq!(unwrapped, RshNode::Sequence {
             left: Box::new(RshNode::Command {
-                name: "ls".to_string(),
-                args: vec!["-l".to_string()],
+                assignments: vec![],
+                name: Word::from("echo"),
+                args: vec![Word::from("a")],
+                redirects: vec![],
             }),
-            right: Box::new(RshNode::Pipe {
+            right: Box::new(RshNode::And {
                 left: Box::new(RshNode::Command {
-                    name: "grep".to_string(),
-                    args: vec![".rs".to_string()],
+                    assignments: vec![],
+                    name: Word::from("echo"),
+                    args: vec![Word::from("b")],
+                    redirects: vec![],
                 }),
-                right: Box::new(RshNode::Background {
-                    command: Box::new(RshNode::Command {
-                        name: "wc".to_string(),
-                        args: vec!["-l".to_string()],
-                    }),
+                right: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("echo"),
+                    args: vec![Word::from("c")],
+                    redirects: vec![],
                 }),
-            })
+            }),
         });
     }
 
     #[test]
-    fn test_parser_pipe_multiple_background_deep() {
-        let input = "ls -l|grep .rs|wc -l&|something|something else";
+    fn test_parser_heredoc() {
+        let input = "cat <<EOF | wc -l\nstatus $?\n\\$? \"quoted\"\nEOF\necho done";
         let mut parser = Parser::new(input);
         let result = parser.parse();
         assert!(result.is_ok());
         let unwrapped = result.unwrap();
-
-        // a | b | c
-        assert_eq!(unwrapped, RshNode::Pipe {
-            left: Box::new(RshNode::Command {
-                name: "ls".to_string(),
-                args: vec!["-l".to_string()],
-            }),
-            right: Box::new(RshNode::Pipe {
+        assert_eq!(unwrapped, RshNode::Sequence {
+            left: Box::new(RshNode::Pipe {
                 left: Box::new(RshNode::Command {
-                    name: "grep".to_string(),
-                    args: vec![".rs".to_string()],
+                    assignments: vec![],
+                    name: Word::from("cat"),
+                    args: vec![],
+                    redirects: vec![Redirect {
+                        fd: 0,
+                        mode: RedirectMode::HereDoc,
+                        target: Word {
+                            parts: vec![
+                                WordPart::Quoted("status ".to_string()),
+                                WordPart::Param { name: "?".to_string(), op: ParamOp::Plain, quoted: true },
+                                WordPart::Quoted("\n$? \"quoted\"\n".to_string()),
+                            ],
+                        },
+                    }],
                 }),
-                right: Box::new(RshNode::Pipe {
-                    left: Box::new(RshNode::Background {
-                        command: Box::new(RshNode::Command {
-                            name: "wc".to_string(),
-                            args: vec!["-l".to_string()],
-                        }),
-                    }),
-                    right: Box::new(RshNode::Pipe {
-                        left: Box::new(RshNode::Command {
-                            name: "something".to_string(),
-                            args: vec![],
-                        }),
-                        right: Box::new(RshNode::Command {
-                            name: "something".to_string(),
-                            args: vec!["else".to_string()],
-                        }),
-                    }),
+                right: Box::new(RshNode::Command {
+                    assignments: vec![],
+                    name: Word::from("wc"),
+                    args: vec![Word::from("-l")],
+                    redirects: vec![],
                 }),
             }),
+            right: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("echo"),
+                args: vec![Word::from("done")],
+                redirects: vec![],
+            }),
         });
     }
 
     #[test]
-    fn test_parser_redirec_to_file() {
-        let input = "ls -l > dir.txt";
+    fn test_parser_heredoc_quoted_strip() {
+        let input = "cat <<-'END'\n\t$? stays\n\tEND";
         let mut parser = Parser::new(input);
         let result = parser.parse();
         assert!(result.is_ok());
         let unwrapped = result.unwrap();
-        assert_eq!(unwrapped, RshNode::Redirect {
-            command: Box::new(RshNode::Command {
-                name: "ls".to_string(),
-                args: vec!["-l".to_string()],
-            }),
-            file: "dir.txt".to_string(),
-            mode: RedirectMode::Write,
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("cat"),
+            args: vec![],
+            redirects: vec![Redirect {
+                fd: 0,
+                mode: RedirectMode::HereDoc,
+                target: Word::quoted("$? stays\n"),
+            }],
         });
     }
 
     #[test]
-    fn test_parser_redirec_from_file() {
-        let input = "cat < dir.txt";
+    fn test_parser_heredoc_unterminated() {
+        let input = "cat <<EOF\nhello";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnterminatedHereDoc(d), .. }) if d == "EOF"));
+    }
+
+    #[test]
+    fn test_parser_herestring() {
+        let input = "grep foo <<< \"$?\"";
         let mut parser = Parser::new(input);
         let result = parser.parse();
         assert!(result.is_ok());
         let unwrapped = result.unwrap();
-        assert_eq!(unwrapped, RshNode::Redirect {
-            command: Box::new(RshNode::Command {
-                name: "cat".to_string(),
-                args: vec![],
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("grep"),
+            args: vec![Word::from("foo")],
+            redirects: vec![Redirect {
+                fd: 0,
+                mode: RedirectMode::HereString,
+                target: Word {
+                    parts: vec![
+                        WordPart::Param { name: "?".to_string(), op: ParamOp::Plain, quoted: true },
+                    ],
+                },
+            }],
+        });
+    }
+
+    #[test]
+    fn test_parser_params() {
+        let input = "echo $HOME${x:-a b}${#y}${z/*.rs/x}$1x";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![Word {
+                parts: vec![
+                    WordPart::Param { name: "HOME".to_string(), op: ParamOp::Plain, quoted: false },
+                    WordPart::Param {
+                        name: "x".to_string(),
+                        op: ParamOp::Default { word: Word::from("a b"), colon: true },
+                        quoted: false,
+                    },
+                    WordPart::Param { name: "y".to_string(), op: ParamOp::Length, quoted: false },
+                    WordPart::Param {
+                        name: "z".to_string(),
+                        op: ParamOp::Replace { pattern: Word::from("*.rs"), replacement: Word::from("x"), all: false },
+                        quoted: false,
+                    },
+                    WordPart::Param { name: "1".to_string(), op: ParamOp::Plain, quoted: false },
+                    WordPart::Literal("x".to_string()),
+                ],
+            }],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_param_patterns() {
+        let input = "echo ${f##*/} ${f%.\"rs\"} ${#} ${x?}";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![
+                Word {
+                    parts: vec![WordPart::Param {
+                        name: "f".to_string(),
+                        op: ParamOp::RemovePrefix { pattern: Word::from("*/"), longest: true },
+                        quoted: false,
+                    }],
+                },
+                Word {
+                    parts: vec![WordPart::Param {
+                        name: "f".to_string(),
+                        op: ParamOp::RemoveSuffix {
+                            pattern: Word {
+                                parts: vec![WordPart::Literal(".".to_string()), WordPart::Quoted("rs".to_string())],
+                            },
+                            longest: false,
+                        },
+                        quoted: false,
+                    }],
+                },
+                Word {
+                    parts: vec![WordPart::Param { name: "#".to_string(), op: ParamOp::Plain, quoted: false }],
+                },
+                Word {
+                    parts: vec![WordPart::Param {
+                        name: "x".to_string(),
+                        op: ParamOp::Error { word: Word::new(), colon: false },
+                        quoted: false,
+                    }],
+                },
+            ],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_bad_param() {
+        let input = "echo ${x:y}";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnexpectedChar('y'), .. })));
+
+        let input = "echo ${x";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(matches!(result, Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+    }
+
+    #[test]
+    fn test_parser_assignments() {
+        let input = "A=1 B=\"x y\" cmd C=2";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![
+                Assignment { name: "A".to_string(), value: Word::from("1") },
+                Assignment { name: "B".to_string(), value: Word::quoted("x y") },
+            ],
+            name: Word::from("cmd"),
+            args: vec![Word::from("C=2")],
+            redirects: vec![],
+        });
+
+        let input = "EMPTY= 1x=2";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![Assignment { name: "EMPTY".to_string(), value: Word::new() }],
+            name: Word::from("1x=2"),
+            args: vec![],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_command_substitution() {
+        let input = "cd $(git rev-parse --show-toplevel)/src \"`echo \\`pwd\\``\"";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("cd"),
+            args: vec![
+                Word {
+                    parts: vec![
+                        WordPart::Command {
+                            node: Box::new(RshNode::Command {
+                                assignments: vec![],
+                                name: Word::from("git"),
+                                args: vec![Word::from("rev-parse"), Word::from("--show-toplevel")],
+                                redirects: vec![],
+                            }),
+                            quoted: false,
+                        },
+                        WordPart::Literal("/src".to_string()),
+                    ],
+                },
+                Word {
+                    parts: vec![
+                        WordPart::Command {
+                            node: Box::new(RshNode::Command {
+                                assignments: vec![],
+                                name: Word::from("echo"),
+                                args: vec![Word {
+                                    parts: vec![WordPart::Command {
+                                        node: Box::new(RshNode::Command {
+                                            assignments: vec![],
+                                            name: Word::from("pwd"),
+                                            args: vec![],
+                                            redirects: vec![],
+                                        }),
+                                        quoted: false,
+                                    }],
+                                }],
+                                redirects: vec![],
+                            }),
+                            quoted: true,
+                        },
+                    ],
+                },
+            ],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_command_substitution_list() {
+        let input = "x=$(a; b\n) $()";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![Assignment {
+                name: "x".to_string(),
+                value: Word {
+                    parts: vec![WordPart::Command {
+                        node: Box::new(RshNode::Sequence {
+                            left: Box::new(RshNode::Command {
+                                assignments: vec![],
+                                name: Word::from("a"),
+                                args: vec![],
+                                redirects: vec![],
+                            }),
+                            right: Box::new(RshNode::Command {
+                                assignments: vec![],
+                                name: Word::from("b"),
+                                args: vec![],
+                                redirects: vec![],
+                            }),
+                        }),
+                        quoted: false,
+                    }],
+                },
+            }],
+            name: Word {
+                parts: vec![WordPart::Command {
+                    node: Box::new(RshNode::Command {
+                        assignments: vec![],
+                        name: Word::new(),
+                        args: vec![],
+                        redirects: vec![],
+                    }),
+                    quoted: false,
+                }],
+            },
+            args: vec![],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_bad_command_substitution() {
+        let input = "echo $(ls";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+
+        let input = "echo `ls";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+
+        let input = "echo $(ls;;)";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+
+        let input = "echo )";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+    }
+
+    #[test]
+    fn test_parser_arith() {
+        let input = "echo $(( (x + 1) * $y ))";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::Command {
+            assignments: vec![],
+            name: Word::from("echo"),
+            args: vec![Word {
+                parts: vec![WordPart::Arith {
+                    expr: Word {
+                        parts: vec![
+                            WordPart::Quoted(" (x + 1) * ".to_string()),
+                            WordPart::Param { name: "y".to_string(), op: ParamOp::Plain, quoted: true },
+                            WordPart::Quoted(" ".to_string()),
+                        ],
+                    },
+                    quoted: false,
+                }],
+            }],
+            redirects: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parser_arith_command() {
+        let input = "((i++)) && ((i < 10))";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        let unwrapped = result.unwrap();
+        assert_eq!(unwrapped, RshNode::And {
+            left: Box::new(RshNode::Arith { expr: Word::quoted("i++") }),
+            right: Box::new(RshNode::Arith { expr: Word::quoted("i < 10") }),
+        });
+
+        let input = "((1 + 2)";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+    }
+
+    #[test]
+    fn test_parser_display() {
+        let inputs = [
+            "A=1 ls -l \"$HOME\"/x ${y:-a b} > out 2>&1",
+            "a | b && c || d",
+            "sleep 1 & echo \"${x}y\"; echo $(date) `pwd`",
+            "((i += 1)) && echo $((i * 2)) ${#x} ${x%%.*} ${x//a/b}",
+            "cat <<< 'it''s' 2>&- <> rw",
+            "if a; then b &\nelif c\nthen d; else e; fi",
+            "for x in 1 \"2 3\"; do echo $x; done | sort",
+            "until false; do for ((i = 0; ; i++)); do break; done; done",
+            "case $x in (a|b) echo ab;& *) ;;& esac",
+            "function greet { echo hi \"$@\"; }; greet",
+            "(cd /tmp && make) | tee log; {\n a; b & } 2> err",
+        ];
+        let printed: Vec<String> = inputs.iter().map(|input| Parser::new(input).parse().unwrap().to_string()).collect();
+        assert_eq!(printed, vec![
+            "A=1 ls -l \"$HOME\"/x ${y:-a b} >out 2>&1",
+            "a | b && c || d",
+            "sleep 1 & echo \"${x}y\"; echo $(date) $(pwd)",
+            "((i += 1)) && echo $((i * 2)) ${#x} ${x%%.*} ${x//a/b}",
+            "cat <<<\"its\" 2>&- <>rw",
+            "if a; then b & elif c; then d; else e; fi",
+            "for x in 1 \"2 3\"; do echo $x; done | sort",
+            "until false; do for ((i = 0; 1; i++)); do break; done; done",
+            "case $x in a | b) echo ab;& *);;& esac",
+            "greet() { echo hi \"$@\"; }; greet",
+            "( cd /tmp && make ) | tee log; { a; b & } 2>err",
+        ]);
+
+        // what gets printed parses back to the same tree.
+        for input in inputs {
+            let root = Parser::new(input).parse().unwrap();
+            assert_eq!(Parser::new(&root.to_string()).parse().unwrap(), root);
+        }
+    }
+
+    fn command(name: &str, args: &[&str]) -> RshNode {
+        RshNode::Command {
+            assignments: vec![],
+            name: Word::from(name),
+            args: args.iter().map(|arg| Word::from(*arg)).collect(),
+            redirects: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parser_if() {
+        let input = "if a; then b; elif c\nthen\n d\nelse e; fi";
+        let mut parser = Parser::new(input);
+        let result = parser.parse();
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), RshNode::If {
+            branches: vec![
+                (command("a", &[]), command("b", &[])),
+                (command("c", &[]), command("d", &[])),
+            ],
+            otherwise: Some(Box::new(command("e", &[]))),
+        });
+
+        // reserved words are only special where a command name could go.
+        let input = "echo if then fi";
+        let mut parser = Parser::new(input);
+        assert_eq!(parser.parse().unwrap(), command("echo", &["if", "then", "fi"]));
+    }
+
+    #[test]
+    fn test_parser_loops() {
+        let input = "while a; do b; done && until c; do d; done";
+        let mut parser = Parser::new(input);
+        assert_eq!(parser.parse().unwrap(), RshNode::And {
+            left: Box::new(RshNode::While {
+                condition: Box::new(command("a", &[])),
+                body: Box::new(command("b", &[])),
+                until: false,
+            }),
+            right: Box::new(RshNode::While {
+                condition: Box::new(command("c", &[])),
+                body: Box::new(command("d", &[])),
+                until: true,
+            }),
+        });
+
+        let input = "for x in a b\ndo echo $x; done";
+        let mut parser = Parser::new(input);
+        let mut arg = Word::new();
+        arg.push(WordPart::Param { name: "x".to_string(), op: ParamOp::Plain, quoted: false });
+        assert_eq!(parser.parse().unwrap(), RshNode::For {
+            name: "x".to_string(),
+            words: vec![Word::from("a"), Word::from("b")],
+            body: Box::new(RshNode::Command {
+                assignments: vec![],
+                name: Word::from("echo"),
+                args: vec![arg],
+                redirects: vec![],
             }),
-            file: "dir.txt".to_string(),
-            mode: RedirectMode::Read,
+        });
+
+        // without "in" the loop goes over "$@".
+        let input = "for x; do a; done";
+        let mut parser = Parser::new(input);
+        let all = Word { parts: vec![WordPart::Param { name: "@".to_string(), op: ParamOp::Plain, quoted: true }] };
+        assert_eq!(parser.parse().unwrap(), RshNode::For {
+            name: "x".to_string(),
+            words: vec![all],
+            body: Box::new(command("a", &[])),
+        });
+
+        let input = "for ((i = 0; i < 3; i++)) do a; done";
+        let mut parser = Parser::new(input);
+        assert_eq!(parser.parse().unwrap(), RshNode::ArithFor {
+            init: Word::quoted("i = 0"),
+            condition: Word::quoted("i < 3"),
+            step: Word::quoted("i++"),
+            body: Box::new(command("a", &[])),
         });
     }
-}
 
+    #[test]
+    fn test_parser_case() {
+        let input = "case $x in\n  (a | b) one;;\n  *.txt) ;&\n  *) two;;&\n  c) three\nesac";
+        let mut parser = Parser::new(input);
+        let mut word = Word::new();
+        word.push(WordPart::Param { name: "x".to_string(), op: ParamOp::Plain, quoted: false });
+        assert_eq!(parser.parse().unwrap(), RshNode::Case {
+            word,
+            items: vec![
+                CaseItem { patterns: vec![Word::from("a"), Word::from("b")], body: Some(command("one", &[])), end: CaseEnd::Break },
+                CaseItem { patterns: vec![Word::from("*.txt")], body: None, end: CaseEnd::Fallthrough },
+                CaseItem { patterns: vec![Word::from("*")], body: Some(command("two", &[])), end: CaseEnd::Continue },
+                CaseItem { patterns: vec![Word::from("c")], body: Some(command("three", &[])), end: CaseEnd::Break },
+            ],
+        });
+
+        let input = "case x in esac";
+        let mut parser = Parser::new(input);
+        assert_eq!(parser.parse().unwrap(), RshNode::Case { word: Word::from("x"), items: vec![] });
+    }
+
+    #[test]
+    fn test_parser_empty() {
+        for input in ["", "\n\n", "  \t", "# just a comment", "\n  # one\n# two\n"] {
+            assert_eq!(Parser::new(input).parse().unwrap(), RshNode::Empty, "{:?}", input);
+        }
+        assert_eq!(Parser::new("echo a # done\n\n").parse().unwrap(), command("echo", &["a"]));
+        assert!(matches!(Parser::new("\n)").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+    }
+
+    #[test]
+    fn test_parser_compound_errors() {
+        let unfinished = ["if a; then b", "while a; do b; done; for x in", "case x in x) a;;", "if a; then b; else"];
+        for input in unfinished {
+            assert!(matches!(Parser::new(input).parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })), "{}", input);
+        }
+
+        let bad = ["fi", "if a; fi", "do a; done", "a;; b", "for 1 in a; do b; done", "for ((i; i)); do a; done", "a && then"];
+        for input in bad {
+            assert!(matches!(Parser::new(input).parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parser_function() {
+        let inputs = ["f() { a; b; }", "f ( )\n{\n a; b\n}", "function f { a; b; }", "function f() { a; b; }"];
+        for input in inputs {
+            let mut parser = Parser::new(input);
+            assert_eq!(parser.parse().unwrap(), RshNode::Function {
+                name: "f".to_string(),
+                body: Box::new(RshNode::Group {
+                    body: Box::new(RshNode::Sequence {
+                        left: Box::new(command("a", &[])),
+                        right: Box::new(command("b", &[])),
+                    }),
+                }),
+            }, "{}", input);
+        }
+
+        let input = "git-prompt() if a; then b; fi";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Ok(RshNode::Function { name, body }) if name == "git-prompt" && matches!(*body, RshNode::If { .. })));
+
+        // braces are only special as whole words in command position.
+        let input = "echo { }";
+        let mut parser = Parser::new(input);
+        assert_eq!(parser.parse().unwrap(), command("echo", &["{", "}"]));
+
+        assert!(matches!(Parser::new("f() { a; ").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+        assert!(matches!(Parser::new("f() a").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+        assert!(matches!(Parser::new("a; }").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+    }
+
+    #[test]
+    fn test_parser_groups() {
+        let input = "(a; b) | { c\n}";
+        let mut parser = Parser::new(input);
+        assert_eq!(parser.parse().unwrap(), RshNode::Pipe {
+            left: Box::new(RshNode::Subshell {
+                body: Box::new(RshNode::Sequence {
+                    left: Box::new(command("a", &[])),
+                    right: Box::new(command("b", &[])),
+                }),
+            }),
+            right: Box::new(RshNode::Group { body: Box::new(command("c", &[])) }),
+        });
+
+        let input = "{ a; } > log 2>&1 &";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Ok(RshNode::Background { command })
+            if matches!(command.as_ref(), RshNode::Redirected { command, redirects }
+                if matches!(command.as_ref(), RshNode::Group { .. }) && redirects.len() == 2)));
+
+        let input = "f() (a)";
+        let mut parser = Parser::new(input);
+        assert!(matches!(parser.parse(), Ok(RshNode::Function { body, .. }) if matches!(*body, RshNode::Subshell { .. })));
+
+        assert!(matches!(Parser::new("(a").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedEOF, .. })));
+        assert!(matches!(Parser::new("(a; }").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+        assert!(matches!(Parser::new("{ a; )").parse(), Err(ParseError { kind: ParseErrorKind::UnexpectedToken(_), .. })));
+    }
+
+    #[test]
+    fn test_parser_incomplete() {
+        let inputs = [
+            "echo \"hello", "echo 'x", "a |", "a &&", "echo a\\", "cat <<EOF", "if a; then b", "for x in a; do",
+            "case x in a)", "f() {", "(a", "echo $(a", "echo ${x", "echo `a",
+        ];
+        for input in inputs {
+            assert!(Parser::new(input).parse().is_err_and(|err| err.is_incomplete()), "{}", input);
+        }
+
+        for input in ["a >", "fi", "a | | b", "if a; then b; }"] {
+            assert!(Parser::new(input).parse().is_err_and(|err| !err.is_incomplete()), "{}", input);
+        }
+
+        // the next line finishes the word that "\\" left open.
+        assert_eq!(Parser::new("echo a\\\nb").parse().unwrap(), command("echo", &["ab"]));
+    }
+
+    #[test]
+    fn test_parser_error_display() {
+        let err = Parser::new("if then").parse().unwrap_err();
+        assert_eq!(err.to_string(), "syntax error near unexpected token `then'\nif then\n   ^");
+        let err = Parser::new("a |\n").parse().unwrap_err();
+        assert_eq!(err.to_string(), "syntax error: unexpected end of file\na |\n   ^");
+        let err = Parser::new("\tx=1 )").parse().unwrap_err();
+        assert_eq!(err.to_string(), "syntax error near unexpected token `)'\n\tx=1 )\n\t    ^");
+    }
+
+    #[test]
+    fn test_parser_error_span() {
+        let err = Parser::new("echo a\nfor 1x in y; do z; done").parse().unwrap_err();
+        assert_eq!(err.span, Span { offset: 11, line: 2, column: 5 });
+
+        let err = Parser::new("cat <<EOF\nbody").parse().unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::UnterminatedHereDoc(_)));
+        assert_eq!((err.span.line, err.span.column), (1, 7));
+
+        let err = Parser::new("a; fi").parse().unwrap_err();
+        assert_eq!(err.span, Span { offset: 3, line: 1, column: 4 });
+    }
+}
@@ -0,0 +1,243 @@
+/*
+Shell pattern matching, the "*", "?" and "[...]" wildcards used by parameter expansion, case and globbing.
+A backslash makes the next character match literally.
+*/
+#[derive(Debug, Clone, PartialEq)]
+enum PatternToken {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    // [:alpha:] and friends.
+    Named(String),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Char(expected) => c == *expected,
+            ClassItem::Range(start, end) => *start <= c && c <= *end,
+            ClassItem::Named(name) => match name.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "cntrl" => c.is_control(),
+                "digit" => c.is_ascii_digit(),
+                "graph" => c.is_ascii_graphic(),
+                "lower" => c.is_lowercase(),
+                "print" => c.is_ascii_graphic() || c == ' ',
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<PatternToken>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut idx = 0;
+
+        while idx < chars.len() {
+            match chars[idx] {
+                '*' => {
+                    // runs of stars mean the same as one.
+                    if tokens.last() != Some(&PatternToken::AnyString) {
+                        tokens.push(PatternToken::AnyString);
+                    }
+                },
+                '?' => tokens.push(PatternToken::AnyChar),
+                '[' => match parse_class(&chars, idx + 1) {
+                    Some((class, end)) => {
+                        tokens.push(class);
+                        idx = end;
+                    },
+                    // an unclosed bracket is just a bracket.
+                    None => tokens.push(PatternToken::Char('[')),
+                },
+                '\\' if idx + 1 < chars.len() => {
+                    idx += 1;
+                    tokens.push(PatternToken::Char(chars[idx]));
+                },
+                c => tokens.push(PatternToken::Char(c)),
+            }
+            idx += 1;
+        }
+
+        Pattern { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let (mut t, mut p) = (0, 0);
+        // where to resume if the current attempt fails: the token after the last star, and the text it started at.
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            let matched = match self.tokens.get(p) {
+                Some(PatternToken::AnyString) => {
+                    backtrack = Some((p + 1, t));
+                    p += 1;
+                    continue;
+                },
+                Some(PatternToken::AnyChar) => true,
+                Some(PatternToken::Char(c)) => *c == text[t],
+                Some(PatternToken::Class { negated, items }) => items.iter().any(|item| item.matches(text[t])) != *negated,
+                None => false,
+            };
+
+            if matched {
+                t += 1;
+                p += 1;
+            } else if let Some((star_p, star_t)) = backtrack {
+                // let the last star swallow one more character and try again.
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            } else {
+                return false;
+            }
+        }
+
+        self.tokens[p..].iter().all(|token| *token == PatternToken::AnyString)
+    }
+
+    // true if the pattern has any wildcards, i.e. it could match something other than its literal text.
+    pub fn is_wild(&self) -> bool {
+        self.tokens.iter().any(|token| !matches!(token, PatternToken::Char(_)))
+    }
+
+    // the text the pattern matches if it has no wildcards, with any escapes removed.
+    pub fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|token| match token {
+                PatternToken::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // true if the pattern has to start with the given character, used to keep "*" from matching dotfiles.
+    pub fn starts_with_char(&self, c: char) -> bool {
+        self.tokens.first() == Some(&PatternToken::Char(c))
+    }
+}
+
+// escapes every character that means something in a pattern, so that the text matches only itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// parses a bracket expression starting just after the "[", returning it and the index of the closing "]".
+fn parse_class(chars: &[char], start: usize) -> Option<(PatternToken, usize)> {
+    let mut idx = start;
+    let negated = matches!(chars.get(idx), Some('!') | Some('^'));
+    if negated {
+        idx += 1;
+    }
+
+    let mut items = Vec::new();
+    let first = idx;
+
+    loop {
+        let c = *chars.get(idx)?;
+        // a "]" right at the start is part of the set rather than its end.
+        if c == ']' && idx > first {
+            return Some((PatternToken::Class { negated, items }, idx));
+        }
+
+        if c == '[' && chars.get(idx + 1) == Some(&':') {
+            let rest: String = chars[idx + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                items.push(ClassItem::Named(rest[..end].to_string()));
+                idx += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let c = if c == '\\' && idx + 1 < chars.len() {
+            idx += 1;
+            chars[idx]
+        } else {
+            c
+        };
+
+        if chars.get(idx + 1) == Some(&'-') && chars.get(idx + 2).is_some_and(|end| *end != ']') {
+            items.push(ClassItem::Range(c, chars[idx + 2]));
+            idx += 3;
+        } else {
+            items.push(ClassItem::Char(c));
+            idx += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_pattern_wildcards() {
+        assert!(Pattern::new("*.rs").matches("main.rs"));
+        assert!(!Pattern::new("*.rs").matches("main.rsx"));
+        assert!(Pattern::new("a*b*c").matches("aXXbYYc"));
+        assert!(Pattern::new("a*b*c").matches("abc"));
+        assert!(!Pattern::new("a*b*c").matches("acb"));
+        assert!(Pattern::new("?.txt").matches("a.txt"));
+        assert!(!Pattern::new("?.txt").matches("ab.txt"));
+        assert!(Pattern::new("*").matches(""));
+        assert!(Pattern::new("").matches(""));
+        assert!(!Pattern::new("").matches("a"));
+    }
+
+    #[test]
+    fn test_pattern_classes() {
+        assert!(Pattern::new("[abc]x").matches("bx"));
+        assert!(!Pattern::new("[!abc]x").matches("bx"));
+        assert!(Pattern::new("[^abc]x").matches("dx"));
+        assert!(Pattern::new("file[0-9]").matches("file7"));
+        assert!(!Pattern::new("file[0-9]").matches("filex"));
+        assert!(Pattern::new("[[:upper:]]*").matches("Makefile"));
+        assert!(Pattern::new("[]a]").matches("]"));
+        assert!(Pattern::new("[a-]").matches("-"));
+        // unclosed brackets are literal.
+        assert!(Pattern::new("[ab").matches("[ab"));
+    }
+
+    #[test]
+    fn test_pattern_escapes() {
+        assert!(Pattern::new("\\*").matches("*"));
+        assert!(!Pattern::new("\\*").matches("a"));
+        assert!(Pattern::new(&escape("a*[b]")).matches("a*[b]"));
+        assert!(!Pattern::new(&escape("a*")).is_wild());
+        assert!(Pattern::new("a*").is_wild());
+        assert_eq!(Pattern::new("a\\*b").literal(), Some("a*b".to_string()));
+        assert_eq!(Pattern::new("a*b").literal(), None);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::parser::RedirectMode;
+use std::fs::{ self, OpenOptions };
+use std::io::{ self, Seek, Write };
+use std::os::fd::{ AsRawFd, FromRawFd, OwnedFd, RawFd };
+
+// files opened for a redirect are moved to this fd or above so the fds a user names can't clobber them.
+const FIRST_PRIVATE_FD: RawFd = 10;
+
+// every pipe can hold at least a page, so documents this small can be written up front without blocking.
+const PIPE_MIN_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FdAction {
+    Dup { src: RawFd, dst: RawFd },
+    Close(RawFd),
+}
+
+/**
+* The redirections of a single command, resolved into fd operations that are replayed in order just
+* before the command runs. Files are opened by the shell up front so that errors are reported by us
+* rather than by a half-started child.
+*/
+#[derive(Debug, Default)]
+pub struct RedirectPlan {
+    files: Vec<OwnedFd>,
+    actions: Vec<FdAction>,
+}
+
+impl RedirectPlan {
+    pub fn new() -> RedirectPlan {
+        RedirectPlan::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn actions(&self) -> Vec<FdAction> {
+        self.actions.clone()
+    }
+
+    pub fn open(&mut self, fd: RawFd, path: &str, mode: &RedirectMode) -> Result<(), io::Error> {
+        let mut options = OpenOptions::new();
+        match mode {
+            RedirectMode::Read => options.read(true),
+            RedirectMode::Write => options.write(true).create(true).truncate(true),
+            RedirectMode::Append => options.append(true).create(true),
+            RedirectMode::ReadWrite => options.read(true).write(true).create(true),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file redirect")),
+        };
+
+        let file = park(options.open(path)?.into())?;
+        self.actions.push(FdAction::Dup { src: file.as_raw_fd(), dst: fd });
+        self.files.push(file);
+        Ok(())
+    }
+
+    /**
+    * Makes "body" readable on fd, for here-documents and here-strings. Small bodies go through a pipe that is
+    * filled right away, anything bigger goes through an unlinked temporary file.
+    */
+    pub fn here_document(&mut self, fd: RawFd, body: &str) -> Result<(), io::Error> {
+        let reader: OwnedFd = if body.len() <= PIPE_MIN_CAPACITY {
+            let (reader, mut writer) = io::pipe()?;
+            writer.write_all(body.as_bytes())?;
+            reader.into()
+        } else {
+            let path = std::env::temp_dir().join(format!("rsh-heredoc-{}-{}", std::process::id(), self.files.len()));
+            let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+            fs::remove_file(&path)?;
+            file.write_all(body.as_bytes())?;
+            file.rewind()?;
+            file.into()
+        };
+
+        let reader = park(reader)?;
+        self.actions.push(FdAction::Dup { src: reader.as_raw_fd(), dst: fd });
+        self.files.push(reader);
+        Ok(())
+    }
+
+    // fd becomes a copy of src, which has to be open either in the shell or by an earlier redirect.
+    pub fn duplicate(&mut self, fd: RawFd, src: RawFd) -> Result<(), io::Error> {
+        if !self.will_be_open(src) {
+            return Err(io::Error::from_raw_os_error(libc::EBADF));
+        }
+        self.actions.push(FdAction::Dup { src, dst: fd });
+        Ok(())
+    }
+
+    pub fn close(&mut self, fd: RawFd) {
+        self.actions.push(FdAction::Close(fd));
+    }
+
+    fn will_be_open(&self, fd: RawFd) -> bool {
+        for action in self.actions.iter().rev() {
+            match *action {
+                FdAction::Dup { dst, .. } if dst == fd => return true,
+                FdAction::Close(closed) if closed == fd => return false,
+                _ => {},
+            }
+        }
+        // private fds are close-on-exec, so the command wouldn't see them.
+        fd < FIRST_PRIVATE_FD && unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1
+    }
+}
+
+// moves an fd to FIRST_PRIVATE_FD or above, keeping close-on-exec set.
+fn park(fd: OwnedFd) -> Result<OwnedFd, io::Error> {
+    let raw = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, FIRST_PRIVATE_FD) };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(raw) })
+}
+
+/**
+* Copies of the fds a set of actions is about to change, so that the redirections of a builtin, which runs in
+* the shell itself, can be undone once it's done. Fds that weren't open are closed again on restore.
+*/
+#[derive(Debug)]
+pub struct SavedFds {
+    saved: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+impl SavedFds {
+    pub fn save(actions: &[FdAction]) -> Result<SavedFds, io::Error> {
+        let mut saved: Vec<(RawFd, Option<OwnedFd>)> = Vec::new();
+        for action in actions {
+            let fd = match *action {
+                FdAction::Dup { dst, .. } => dst,
+                FdAction::Close(fd) => fd,
+            };
+            if saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+                continue;
+            }
+
+            let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, FIRST_PRIVATE_FD) };
+            if copy >= 0 {
+                saved.push((fd, Some(unsafe { OwnedFd::from_raw_fd(copy) })));
+            } else if io::Error::last_os_error().raw_os_error() == Some(libc::EBADF) {
+                saved.push((fd, None));
+            } else {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(SavedFds { saved })
+    }
+
+    pub fn restore(self) {
+        for (fd, copy) in self.saved.into_iter().rev() {
+            match copy {
+                Some(copy) => unsafe { libc::dup2(copy.as_raw_fd(), fd) },
+                None => unsafe { libc::close(fd) },
+            };
+        }
+    }
+}
+
+/**
+* Replays the actions on the current process. This usually runs in a freshly forked child, so it only makes
+* async-signal-safe calls. Builtins run it in the shell itself, between SavedFds::save and restore.
+*/
+pub fn apply_actions(actions: &[FdAction]) -> Result<(), io::Error> {
+    for action in actions {
+        match *action {
+            FdAction::Dup { src, dst } if src == dst => {
+                // dup2 would be a no-op, but the fd still needs to survive exec.
+                if unsafe { libc::fcntl(dst, libc::F_SETFD, 0) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            },
+            FdAction::Dup { src, dst } => {
+                if unsafe { libc::dup2(src, dst) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            },
+            FdAction::Close(fd) => {
+                unsafe { libc::close(fd) };
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_plan_duplicate_unknown_fd() {
+        let mut plan = RedirectPlan::new();
+        assert!(plan.duplicate(2, 9).is_err());
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_plan_duplicate_after_close() {
+        let mut plan = RedirectPlan::new();
+        plan.close(1);
+        assert!(plan.duplicate(2, 1).is_err());
+        plan.duplicate(1, 0).unwrap();
+        plan.duplicate(2, 1).unwrap();
+        assert_eq!(plan.actions(), vec![
+            FdAction::Close(1),
+            FdAction::Dup { src: 0, dst: 1 },
+            FdAction::Dup { src: 1, dst: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_plan_here_document() {
+        use std::io::Read;
+
+        for body in ["small\n".to_string(), "x".repeat(PIPE_MIN_CAPACITY * 2)] {
+            let mut plan = RedirectPlan::new();
+            plan.here_document(0, &body).unwrap();
+            let src = match plan.actions()[0] {
+                FdAction::Dup { src, .. } => src,
+                other => panic!("unexpected action {:?}", other),
+            };
+
+            let fd = unsafe { libc::dup(src) };
+            let mut reader = fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+            let mut read = String::new();
+            reader.read_to_string(&mut read).unwrap();
+            assert_eq!(read, body);
+        }
+    }
+
+    #[test]
+    fn test_plan_open_parks_file() {
+        let mut plan = RedirectPlan::new();
+        plan.open(3, "Cargo.toml", &RedirectMode::Read).unwrap();
+        match plan.actions()[0] {
+            FdAction::Dup { src, dst } => {
+                assert!(src >= FIRST_PRIVATE_FD);
+                assert_eq!(dst, 3);
+            },
+            other => panic!("unexpected action {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_saved_fds_restore() {
+        let mut plan = RedirectPlan::new();
+        plan.here_document(0, "x").unwrap();
+        plan.duplicate(40, 0).unwrap();
+        let actions = plan.actions();
+
+        let before = unsafe { libc::fcntl(40, libc::F_GETFD) };
+        assert_eq!(before, -1);
+        let saved = SavedFds::save(&actions).unwrap();
+        // only fd 40 is touched here, fd 0 belongs to the test harness.
+        apply_actions(&actions[1..]).unwrap();
+        assert_ne!(unsafe { libc::fcntl(40, libc::F_GETFD) }, -1);
+        saved.restore();
+        assert_eq!(unsafe { libc::fcntl(40, libc::F_GETFD) }, -1);
+    }
+}
@@ -1,8 +1,16 @@
-use crate::engine::{ Engine, EngineCtx };
-use crate::parser::{ Parser, ParseError };
+use crate::complete::{ self, Completion };
+use crate::engine::{ self, Engine, Outcome };
+use crate::history::{ self, Entry, History };
+use crate::location;
+use crate::parser::{ Parser, ParseError, RshNode };
+use crate::signals;
+use crate::state::ShellState;
+use std::fmt::{ self, Display, Formatter };
 use std::io::{ self };
-use std::thread;
-use rustyline;
+use std::time::{ Duration, Instant };
+use rustyline::{ self, CompletionType, Config, Editor };
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
 
 #[derive(Debug)]
 pub enum RshError {
@@ -11,6 +19,16 @@ pub enum RshError {
     IoError(io::Error),
 }
 
+impl Display for RshError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RshError::ParseError(err) => write!(f, "{}", err),
+            RshError::ReadlineError(err) => write!(f, "{}", err),
+            RshError::IoError(err) => write!(f, "{}", engine::error_message(err)),
+        }
+    }
+}
+
 impl From<ParseError> for RshError {
     fn from(err: ParseError) -> RshError {
         RshError::ParseError(err)
@@ -29,73 +47,315 @@ impl From<io::Error> for RshError {
     }
 }
 
+// the line editor, with tab completion.
+type LineEditor = Editor<Completion, DefaultHistory>;
+
+// shown while the rest of a command is being typed in, unless PS2 is set.
+const CONTINUATION_PROMPT: &str = "> ";
+
 pub struct Rsh {
     prompt: String,
+    state: ShellState,
 }
 
 impl Rsh {
     pub fn new(prompt: String) -> Rsh {
         Rsh {
             prompt,
+            state: ShellState::new(),
         }
     }
 
+    // the outcome of the last command the shell ran.
+    pub fn last_status(&self) -> Outcome {
+        self.state.last_status
+    }
+
+    // $0 and the positional parameters, for a script or a -c string.
+    pub fn set_args(&mut self, name: &str, args: &[String]) {
+        self.state.name = name.to_string();
+        self.state.positional = args.to_vec();
+    }
+
+    /**
+    * Runs the commands of a script, a -c string or stdin, without a prompt or job control. Lines are read until
+    * they make up a whole command, which runs before the lines after it are read, so a script can define a function
+    * and then use it, and a command that reads stdin gets the rest of it. Errors name the line the command started
+    * on, syntax errors the line and column of the mistake. A syntax error stops the script with status 2.
+    */
+    pub fn run_script<I>(&mut self, lines: I) -> Result<(), RshError>
+    where
+        I: Iterator<Item = io::Result<String>>,
+    {
+        let mut lines = lines.enumerate();
+        while !self.state.exit_requested {
+            let Some((idx, line)) = lines.next() else {
+                break;
+            };
+            location::set(&self.state.name, idx + 1);
+
+            let mut source = line?;
+            let parsed = loop {
+                match Parser::new(&source).parse() {
+                    Err(err) if err.is_incomplete() => match lines.next() {
+                        Some((_, line)) => {
+                            source.push('\n');
+                            source.push_str(&line?);
+                        },
+                        None => break Parser::new(&source).parse(),
+                    },
+                    parsed => break parsed,
+                }
+            };
+
+            match parsed {
+                // blank lines and comments have nothing to run.
+                Ok(RshNode::Empty) => {},
+                Ok(root) => {
+                    if let Err(err) = Engine::new(&mut self.state).execute(&root) {
+                        eprintln!("{}: {}", location::prefix(), engine::error_message(&err));
+                    }
+                },
+                Err(err) => {
+                    location::set(&self.state.name, idx + err.span.line);
+                    eprintln!("{}:{}: {}", location::prefix(), err.span.column, err);
+                    self.state.last_status = Outcome::Exited(2);
+                    break;
+                },
+            }
+        }
+        Engine::new(&mut self.state).run_exit_trap();
+        Ok(())
+    }
+
     pub fn run(&mut self) -> Result<(), RshError>{
         let mut should_stop = false;
-        // to-do - handle history and completion
-        let mut rl = rustyline::DefaultEditor::new()?;
+        self.state.interactive = true;
+        let mut rl = self.editor()?;
+        complete::spec::load(&mut self.state);
+        self.state.jobs.enable()?;
+        signals::install();
 
         while !should_stop {
-            let readline = rl.readline(&self.prompt);
+            self.state.jobs.notify();
+            // a ^C only cuts short the command line it was typed during.
+            self.state.interrupted = false;
+            signals::take_interrupt();
+            Engine::new(&mut self.state).run_signal_traps();
+            let prompt = self.prompt.clone();
+            let readline = self.readline(&mut rl, &prompt);
             match readline {
                 Ok(line) => {
-                    // todo - handle builtins like cd, exit, etc.
-                    if line == "exit" {
-                        should_stop = true;
-                    } else {
-                        let mut parser = Parser::new(&line);
-                        let root = parser.parse()?;
-                        let engine = Engine::new(root);
-                        let prog_res = engine.execute();
-                        if let Ok(mut prog) = prog_res {
-                            let _ = self.handle_prog_result(&mut prog);
-                        } else {
-                            // handle error
-                            println!("Error: {:?}", prog_res);
-                        }
+                    let source = match self.read_command(&mut rl, line) {
+                        Ok(source) => source,
+                        // ^C on a continuation line throws away the whole command.
+                        Err(RshError::ReadlineError(ReadlineError::Interrupted)) => {
+                            self.state.last_status = Outcome::Signaled(libc::SIGINT);
+                            continue;
+                        },
+                        // like a failed read at the prompt, this ends the shell, with its EXIT trap.
+                        Err(err) => {
+                            eprintln!("rsh: {}", err);
+                            should_stop = true;
+                            continue;
+                        },
+                    };
+                    // the line with its history expansions is shown before it runs, and is what the history keeps.
+                    let source = match history::expand(&source, self.state.history.entries()) {
+                        Ok(expanded) => {
+                            if expanded != source {
+                                println!("{}", expanded);
+                            }
+                            expanded
+                        },
+                        Err(message) => {
+                            eprintln!("rsh: {}", message);
+                            self.state.last_status = Outcome::Exited(1);
+                            continue;
+                        },
+                    };
+                    // a syntax error only costs the command it's in, the shell carries on.
+                    let started = Instant::now();
+                    match Parser::new(&source).parse() {
+                        Ok(root) => {
+                            if let Err(err) = Engine::new(&mut self.state).execute(&root) {
+                                eprintln!("rsh: {}", engine::error_message(&err));
+                            }
+                        },
+                        Err(err) => {
+                            eprintln!("rsh: {}", err);
+                            self.state.last_status = Outcome::Exited(2);
+                        },
                     }
+                    self.remember(&mut rl, &source, started.elapsed());
+                    should_stop = self.state.exit_requested;
                 }
-                Err(err) => { 
-                    println!("Error: {:?}", err);
+                // ^C on the prompt throws the line away, like it would have killed a command.
+                Err(ReadlineError::Interrupted) => {
+                    self.state.last_status = Outcome::Signaled(libc::SIGINT);
+                },
+                Err(ReadlineError::Eof) => {
+                    should_stop = true;
+                },
+                Err(err) => {
+                    eprintln!("rsh: {}", err);
                     should_stop = true;
                 },
             }
         }
+        Engine::new(&mut self.state).run_exit_trap();
         Ok(())
     }
 
-    fn handle_prog_result(&self, ctx: &mut EngineCtx) -> Result<(), io::Error> {
-        if let Some(mut c) = ctx.take_last_child() {
-            let child_stdout = c.stdout.take();
-            let child_stderr = c.stderr.take();
+    /**
+    * The line editor, with the saved history loaded into it so that the up arrow reaches commands from earlier
+    * sessions. Which commands go in is up to the shell's history, so the editor doesn't filter them itself.
+    */
+    fn editor(&mut self) -> Result<LineEditor, RshError> {
+        match History::open(&self.state.vars) {
+            Ok(opened) => self.state.history = opened,
+            Err(err) => eprintln!("rsh: history: {}", engine::error_message(&err)),
+        }
 
-            if let Some(mut stdout) = child_stdout {
-                thread::spawn(move || {
-                    let _ = io::copy(&mut stdout, &mut io::stdout());
-                });
-            }
+        let size = history::limit(&self.state.vars, "HISTSIZE", history::DEFAULT_SIZE).max(1);
+        let config = Config::builder()
+            .max_history_size(size)?
+            .history_ignore_dups(false)?
+            .history_ignore_space(false)
+            .auto_add_history(false)
+            .completion_type(CompletionType::List)
+            .build();
+        let mut rl = LineEditor::with_config(config)?;
+        rl.set_helper(Some(Completion::default()));
+        for entry in self.state.history.entries() {
+            rl.add_history_entry(entry.command.as_str())?;
+        }
+        Ok(rl)
+    }
+
+    // puts a command that was typed in both the editor's history and the saved one, along with how it went.
+    fn remember(&mut self, rl: &mut LineEditor, source: &str, took: Duration) {
+        if source.trim().is_empty() {
+            return;
+        }
 
-            if let Some(mut stderr) = child_stderr {
-                thread::spawn(move || {
-                    let _ = io::copy(&mut stderr, &mut io::stderr());
-                });
+        let cwd = match self.state.vars.get("PWD") {
+            Some(pwd) => pwd.to_string(),
+            None => std::env::current_dir().map(|dir| dir.to_string_lossy().into_owned()).unwrap_or_default(),
+        };
+        let duration = u64::try_from(took.as_millis()).unwrap_or(u64::MAX);
+        let entry = Entry { duration, ..Entry::new(source, &cwd, self.state.last_status.code()) };
+        let added = self.state.history.add(entry, &self.state.vars).unwrap_or_else(|err| {
+            eprintln!("rsh: history: {}", engine::error_message(&err));
+            true
+        });
+        // after "history -c" and the like the editor starts over from what's left.
+        if self.state.history.edited {
+            self.state.history.edited = false;
+            let _ = rl.clear_history();
+            for entry in self.state.history.entries() {
+                let _ = rl.add_history_entry(entry.command.as_str());
             }
+        } else if added {
+            let _ = rl.add_history_entry(source);
+        }
+    }
+
+    // reads a line, with the shell's state lent to the completer while it's typed so that tab can look names up.
+    fn readline(&mut self, rl: &mut LineEditor, prompt: &str) -> rustyline::Result<String> {
+        if let Some(helper) = rl.helper_mut() {
+            std::mem::swap(&mut self.state, helper.state.get_mut());
+        }
+        let line = rl.readline(prompt);
+        if let Some(helper) = rl.helper_mut() {
+            std::mem::swap(&mut self.state, helper.state.get_mut());
+        }
+        line
+    }
+
+    /**
+    * Keeps reading lines, with the PS2 prompt, for as long as "source" is a command that isn't finished yet: an
+    * unfinished quote, a trailing "|", "&&" or "\\", an open block or a here-document still waiting for its delimiter.
+    * A blank line or a comment is a finished command with nothing in it.
+    */
+    fn read_command(&mut self, rl: &mut LineEditor, mut source: String) -> Result<String, RshError> {
+        while Parser::new(&source).parse().is_err_and(|err| err.is_incomplete()) {
+            let prompt = self.state.vars.get("PS2").unwrap_or(CONTINUATION_PROMPT).to_string();
+            let line = match self.readline(rl, &prompt) {
+                // ^D leaves the command unfinished, which parsing it reports.
+                Err(ReadlineError::Eof) => break,
+                line => line?,
+            };
+            source.push('\n');
+            source.push_str(&line);
+        }
+        Ok(source)
+    }
+}
 
-            // Wait for the child process to finish.
-            c.wait()?;
-            return Ok(());
+/**
+* The lines of the shell's stdin, read a byte at a time so that nothing past the end of a line is taken. Whatever
+* comes after the command being run is left for it to read, like the input of "rsh -s" for "read" or "cat".
+*/
+pub fn stdin_lines() -> impl Iterator<Item = io::Result<String>> {
+    std::iter::from_fn(|| {
+        let mut line = Vec::new();
+        loop {
+            let mut byte = 0u8;
+            // safety: reads at most one byte into a byte we own.
+            let read = unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            match read {
+                0 if line.is_empty() => return None,
+                0 => break,
+                1 if byte == b'\n' => break,
+                1 => line.push(byte),
+                _ => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Some(Err(err));
+                    }
+                },
+            }
         }
+        Some(Ok(String::from_utf8_lossy(&line).into_owned()))
+    })
+}
 
-        return Ok(());
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    fn script(lines: &[&str]) -> Rsh {
+        let mut rsh = Rsh::new(String::new());
+        rsh.run_script(lines.iter().map(|line| Ok(line.to_string()))).unwrap();
+        rsh
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_shell_script_ends_blank() {
+        let rsh = script(&["x=1", "", ""]);
+        assert_eq!(rsh.last_status(), Outcome::Exited(0));
+        assert_eq!(rsh.state.vars.get("x"), Some("1"));
+
+        let rsh = script(&["x=1", "# done", "  # really"]);
+        assert_eq!(rsh.last_status(), Outcome::Exited(0));
+        assert_eq!(rsh.state.vars.get("x"), Some("1"));
+
+        // a blank line doesn't change $?.
+        let rsh = script(&["((0))", ""]);
+        assert_eq!(rsh.last_status(), Outcome::Exited(1));
+    }
+
+    #[test]
+    fn test_shell_script_unset_parameter() {
+        let rsh = script(&["a=1", "b=${x:?is required} c=1", "d=1"]);
+        assert_eq!(rsh.last_status(), Outcome::Exited(127));
+        assert_eq!(rsh.state.vars.get("a"), Some("1"));
+        assert_eq!(rsh.state.vars.get("c"), None);
+        assert_eq!(rsh.state.vars.get("d"), None);
+
+        let rsh = script(&["for i in ${x?}; do a=1; done; b=1"]);
+        assert_eq!(rsh.last_status(), Outcome::Exited(127));
+        assert_eq!(rsh.state.vars.get("b"), None);
+    }
+}
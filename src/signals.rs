@@ -0,0 +1,223 @@
+use crate::engine::Outcome;
+use std::ffi::CStr;
+use std::sync::atomic::{ AtomicBool, AtomicI32, AtomicU64, Ordering };
+
+// caught by an interactive shell, which doesn't die of them but passes them on to the foreground job.
+const FORWARDED: [i32; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM];
+// ignored by an interactive shell, they're meant for stopping jobs and for jobs that touch the terminal from the background.
+const IGNORED: [i32; 3] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+// the names trap knows signals by, without the "SIG" in front.
+const NAMES: [(&str, i32); 31] = [
+    ("HUP", libc::SIGHUP), ("INT", libc::SIGINT), ("QUIT", libc::SIGQUIT), ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP), ("ABRT", libc::SIGABRT), ("BUS", libc::SIGBUS), ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL), ("USR1", libc::SIGUSR1), ("SEGV", libc::SIGSEGV), ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE), ("ALRM", libc::SIGALRM), ("TERM", libc::SIGTERM), ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD), ("CONT", libc::SIGCONT), ("STOP", libc::SIGSTOP), ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN), ("TTOU", libc::SIGTTOU), ("URG", libc::SIGURG), ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ), ("VTALRM", libc::SIGVTALRM), ("PROF", libc::SIGPROF), ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO), ("PWR", libc::SIGPWR), ("SYS", libc::SIGSYS),
+];
+
+// the last signal that was caught, 0 for none, and whether it was sent by another process rather than the terminal.
+static PENDING: AtomicI32 = AtomicI32::new(0);
+static SENT: AtomicBool = AtomicBool::new(false);
+// every signal caught since the traps last ran, one bit per signal number.
+static CAUGHT: AtomicU64 = AtomicU64::new(0);
+// signals the user asked to ignore with trap, which the commands the shell runs ignore as well.
+static USER_IGNORED: AtomicU64 = AtomicU64::new(0);
+// signals the user set a trap on, which are the shell's to handle rather than the foreground job's.
+static USER_TRAPPED: AtomicU64 = AtomicU64::new(0);
+// whether SIGINT was caught, which stops the rest of the command line from running.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// whether install has run, which changes what a signal goes back to when its trap is removed.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn record(signal: i32, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // si_code is positive for signals the kernel raised, like ^C on the terminal, and zero or less for kill and friends.
+    // safety: the kernel hands us a valid siginfo when SA_SIGINFO is set.
+    let sent = !info.is_null() && unsafe { (*info).si_code } <= 0;
+    SENT.store(sent, Ordering::Relaxed);
+    PENDING.store(signal, Ordering::Relaxed);
+    CAUGHT.fetch_or(1 << signal, Ordering::Relaxed);
+    if signal == libc::SIGINT {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+}
+
+/**
+* Sets up the signals for an interactive shell, which shouldn't be taken down by the ^C or ^Z meant for the job it's
+* running. The handler is installed without SA_RESTART, so a blocked waitpid returns and the shell gets to pass the
+* signal on.
+*/
+pub fn install() {
+    INSTALLED.store(true, Ordering::Relaxed);
+    for signal in FORWARDED {
+        catch(signal);
+    }
+    for signal in IGNORED {
+        // safety: SIG_IGN is always a valid disposition.
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+}
+
+// trap action SIG: the shell notes the signal down for the engine to run the action.
+pub fn trap(signal: i32) {
+    USER_TRAPPED.fetch_or(1 << signal, Ordering::Relaxed);
+    catch(signal);
+}
+
+fn catch(signal: i32) {
+    USER_IGNORED.fetch_and(!(1 << signal), Ordering::Relaxed);
+    // safety: the action is fully initialized before use, and the handler only touches atomics.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = record as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+// trap '' SIG: the shell and everything it runs ignore the signal.
+pub fn ignore(signal: i32) {
+    USER_TRAPPED.fetch_and(!(1 << signal), Ordering::Relaxed);
+    USER_IGNORED.fetch_or(1 << signal, Ordering::Relaxed);
+    // safety: SIG_IGN is always a valid disposition.
+    unsafe { libc::signal(signal, libc::SIG_IGN) };
+}
+
+// trap - SIG: the signal goes back to what the shell does with it when there's no trap.
+pub fn restore(signal: i32) {
+    USER_TRAPPED.fetch_and(!(1 << signal), Ordering::Relaxed);
+    USER_IGNORED.fetch_and(!(1 << signal), Ordering::Relaxed);
+    let installed = INSTALLED.load(Ordering::Relaxed);
+    if installed && FORWARDED.contains(&signal) {
+        catch(signal);
+        return;
+    }
+    let disposition = if installed && IGNORED.contains(&signal) { libc::SIG_IGN } else { libc::SIG_DFL };
+    // safety: both are always valid dispositions.
+    unsafe { libc::signal(signal, disposition) };
+}
+
+/**
+* Puts every signal the shell changed back to its default. Ignored signals survive exec, so every child calls this
+* before it becomes a command, otherwise nothing the shell ran could be interrupted with ^C or stopped with ^Z.
+* Signals ignored with trap stay ignored. Only makes async-signal-safe calls, since it runs between fork and exec.
+*/
+pub fn reset() {
+    let user_ignored = USER_IGNORED.load(Ordering::Relaxed);
+    for signal in FORWARDED.into_iter().chain(IGNORED) {
+        if user_ignored & (1 << signal) == 0 {
+            // safety: SIG_DFL is always a valid disposition.
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+}
+
+// every signal caught since the last call, lowest number first.
+pub fn take_caught() -> Vec<i32> {
+    let caught = CAUGHT.swap(0, Ordering::Relaxed);
+    (1..64).filter(|signal| caught & (1 << signal) != 0).collect()
+}
+
+// whether SIGINT was caught since the last call.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+// whether the user set a trap on a signal.
+pub fn is_trapped(signal: i32) -> bool {
+    USER_TRAPPED.load(Ordering::Relaxed) & (1 << signal) != 0
+}
+
+// a signal by name, with or without "SIG" and in any case, or by number.
+pub fn number(name: &str) -> Option<i32> {
+    if let Ok(signal) = name.parse::<i32>() {
+        return NAMES.iter().find(|(_, number)| *number == signal).map(|(_, number)| *number);
+    }
+    let upper = name.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    NAMES.iter().find(|(other, _)| *other == name).map(|(_, number)| *number)
+}
+
+// the short name of a signal, e.g. "INT".
+pub fn abbrev(signal: i32) -> Option<&'static str> {
+    NAMES.iter().find(|(_, number)| *number == signal).map(|(name, _)| *name)
+}
+
+// every signal there's a name for, in order.
+pub fn all() -> impl Iterator<Item = (&'static str, i32)> {
+    NAMES.iter().copied()
+}
+
+/**
+* The signal caught since the last call, if any, and whether to pass it on to the foreground job: only the ones the
+* shell ignores for its own sake, when another process sent them and there's no trap for them.
+*/
+pub fn take() -> Option<(i32, bool)> {
+    match PENDING.swap(0, Ordering::Relaxed) {
+        0 => None,
+        signal => {
+            let forward = SENT.load(Ordering::Relaxed) && FORWARDED.contains(&signal) && !is_trapped(signal);
+            Some((signal, forward))
+        },
+    }
+}
+
+// how signals are described to the user, e.g. "Killed" or "Stopped".
+pub fn name(signal: i32) -> String {
+    // safety: strsignal returns a pointer to a string that's valid until the next call, and we copy it right away.
+    let name = unsafe { libc::strsignal(signal) };
+    if name.is_null() {
+        return format!("Signal {}", signal);
+    }
+    unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+}
+
+/**
+* What to tell the user about a foreground command that didn't exit on its own. A broken pipe is left out, that's
+* how the early stages of "cmd | head" normally end.
+*/
+pub fn describe(outcome: Outcome) -> Option<String> {
+    match outcome {
+        Outcome::Signaled(libc::SIGINT) => Some("Interrupted".to_string()),
+        Outcome::Signaled(libc::SIGPIPE) => None,
+        Outcome::Signaled(signal) => Some(format!("Killed by signal {} ({})", signal, name(signal))),
+        Outcome::Exited(_) | Outcome::Stopped(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_signals_describe() {
+        assert_eq!(describe(Outcome::Signaled(libc::SIGINT)), Some("Interrupted".to_string()));
+        assert_eq!(describe(Outcome::Signaled(libc::SIGKILL)), Some("Killed by signal 9 (Killed)".to_string()));
+        assert_eq!(describe(Outcome::Signaled(libc::SIGPIPE)), None);
+        assert_eq!(describe(Outcome::Exited(130)), None);
+    }
+
+    #[test]
+    fn test_signals_take() {
+        assert_eq!(take(), None);
+        record(libc::SIGTERM, std::ptr::null_mut(), std::ptr::null_mut());
+        assert_eq!(take(), Some((libc::SIGTERM, false)));
+        assert_eq!(take(), None);
+        assert_eq!(take_caught(), vec![libc::SIGTERM]);
+        assert_eq!(take_caught(), vec![]);
+    }
+
+    #[test]
+    fn test_signals_names() {
+        assert_eq!(number("INT"), Some(libc::SIGINT));
+        assert_eq!(number("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(number("9"), Some(libc::SIGKILL));
+        assert_eq!(number("NOPE"), None);
+        assert_eq!(number("0"), None);
+        assert_eq!(abbrev(libc::SIGUSR1), Some("USR1"));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::complete::Spec;
+use crate::engine::Outcome;
+use crate::history::History;
+use crate::jobs::Jobs;
+use crate::parser::RshNode;
+use crate::signals;
+use crate::traps::Traps;
+use crate::variables::Variables;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/**
+* Everything the shell needs to remember between command lines. The engine reads and updates this
+* while it executes a tree, and the shell keeps it alive for the whole session.
+*/
+#[derive(Debug, Default)]
+pub struct ShellState {
+    // the outcome of the most recently completed command, exposed as $?.
+    pub last_status: Outcome,
+    // the outcome of each stage of the most recent pipeline, like bash's PIPESTATUS.
+    pub pipestatus: Vec<Outcome>,
+    pub options: ShellOptions,
+    pub vars: Variables,
+    // $$, the pid of the shell, which the copies it forks for subshells keep.
+    pub pid: u32,
+    // $0, the name of the running script, or of the shell itself.
+    pub name: String,
+    // $1 and on, the arguments of the running function, or of the script.
+    pub positional: Vec<String>,
+    // the bodies of the functions that have been defined, by name.
+    pub functions: HashMap<String, Rc<RshNode>>,
+    // how many function calls deep the engine is.
+    pub call_depth: u32,
+    pub jobs: Jobs,
+    pub traps: Traps,
+    // the commands typed at the prompt, which scripts don't have.
+    pub history: History,
+    // how the arguments of commands are completed at the prompt, by command name, or "name subcommand".
+    pub completions: HashMap<String, Spec>,
+    // set by the exit builtin, the shell stops once the current command line is done with.
+    pub exit_requested: bool,
+    // how many loops the engine is running inside of, which is how far break and continue can reach.
+    pub loop_depth: u32,
+    // set by break, continue and return, the engine skips what's left until a loop or function call picks it up.
+    pub jump: Option<Jump>,
+    // whether commands are typed at a prompt, rather than read from a script, -c or a pipe.
+    pub interactive: bool,
+    // set when ^C ends a foreground job, the rest of the command line is skipped and the shell goes back to the prompt.
+    pub interrupted: bool,
+}
+
+/**
+* Leaving the innermost n loops, going on with the next round of the n-th one, or leaving the running function.
+* The status of a return is the one the return builtin left in $?.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    Break(u32),
+    Continue(u32),
+    Return,
+}
+
+// toggles that change how the shell behaves.
+#[derive(Debug, Default)]
+pub struct ShellOptions {
+    // a pipeline fails if any stage fails, not just the last one.
+    pub pipefail: bool,
+    // a glob that matches nothing expands to nothing, instead of being left as it is.
+    pub nullglob: bool,
+    // a glob that matches nothing is an error, and the command doesn't run.
+    pub failglob: bool,
+    // globs match names that start with a dot without the pattern having to spell out the dot.
+    pub dotglob: bool,
+}
+
+impl ShellState {
+    pub fn new() -> ShellState {
+        ShellState {
+            vars: Variables::from_env(),
+            pid: std::process::id(),
+            name: "rsh".to_string(),
+            ..ShellState::default()
+        }
+    }
+
+    /**
+    * Notes a ^C after a foreground job is done with: the job died of SIGINT, or the shell caught one while it waited.
+    * Like bash, a trap on INT takes the place of giving up on the command line.
+    */
+    pub fn note_interrupt(&mut self, outcomes: &[Outcome]) {
+        let caught = signals::take_interrupt();
+        if (caught || outcomes.contains(&Outcome::Signaled(libc::SIGINT))) && !signals::is_trapped(libc::SIGINT) {
+            self.interrupted = true;
+        }
+    }
+}
@@ -8,9 +8,33 @@ pub enum Token<'src> {
     Pipe,
     RedirectOutput,
     RedirectInput,
+    Append,
+    ReadWrite,
+    DupOutput,
+    DupInput,
+    RedirectAll,
+    AppendAll,
+    HereDoc,
+    HereDocStrip,
+    HereString,
+    // the fd number in front of a redirect, e.g. the 2 in 2>err.txt
+    IoNumber(&'src str),
     Background,
+    Semicolon,
+    // the ends of a case item: ";;" stops, ";&" runs the next item's commands too, ";;&" tries the next patterns.
+    CaseBreak,
+    CaseFallthrough,
+    CaseContinue,
+    And,
+    Or,
+    LeftParen,
+    RightParen,
     SingleQuote,
     DoubleQuote,
+    Dollar,
+    Backtick,
+    Backslash,
+    Newline,
     Space
 }
 
@@ -21,68 +45,243 @@ impl Display for Token<'_> {
             Token::Pipe => write!(f, "|"),
             Token::RedirectOutput => write!(f, ">"),
             Token::RedirectInput => write!(f, "<"),
+            Token::Append => write!(f, ">>"),
+            Token::ReadWrite => write!(f, "<>"),
+            Token::DupOutput => write!(f, ">&"),
+            Token::DupInput => write!(f, "<&"),
+            Token::RedirectAll => write!(f, "&>"),
+            Token::AppendAll => write!(f, "&>>"),
+            Token::HereDoc => write!(f, "<<"),
+            Token::HereDocStrip => write!(f, "<<-"),
+            Token::HereString => write!(f, "<<<"),
+            Token::IoNumber(n) => write!(f, "{}", n),
             Token::Background => write!(f, "&"),
+            Token::Semicolon => write!(f, ";"),
+            Token::CaseBreak => write!(f, ";;"),
+            Token::CaseFallthrough => write!(f, ";&"),
+            Token::CaseContinue => write!(f, ";;&"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
             Token::SingleQuote => write!(f, "'"),
             Token::DoubleQuote => write!(f, "\""),
+            Token::Dollar => write!(f, "$"),
+            Token::Backtick => write!(f, "`"),
+            Token::Backslash => write!(f, "\\"),
+            Token::Newline => write!(f, "\\n"),
             Token::Space => write!(f, " "),
         }
     }
 }
 
 impl<'src> Token<'src> {
-    pub fn new(token: &'src str) -> Token {
+    pub fn new(token: &'src str) -> Token<'src> {
         match token {
             "|" => Token::Pipe,
+            "||" => Token::Or,
+            "&&" => Token::And,
+            ";" => Token::Semicolon,
+            ";;" => Token::CaseBreak,
+            ";&" => Token::CaseFallthrough,
+            ";;&" => Token::CaseContinue,
             ">" => Token::RedirectOutput,
             "<" => Token::RedirectInput,
+            ">>" => Token::Append,
+            "<>" => Token::ReadWrite,
+            ">&" => Token::DupOutput,
+            "<&" => Token::DupInput,
+            "&>" => Token::RedirectAll,
+            "&>>" => Token::AppendAll,
+            "<<" => Token::HereDoc,
+            "<<-" => Token::HereDocStrip,
+            "<<<" => Token::HereString,
             "&" => Token::Background,
+            "(" => Token::LeftParen,
+            ")" => Token::RightParen,
             "'" => Token::SingleQuote,
             "\"" => Token::DoubleQuote,
-            " " => Token::Space,
+            "$" => Token::Dollar,
+            "`" => Token::Backtick,
+            "\\" => Token::Backslash,
+            "\n" => Token::Newline,
+            " " | "\t" => Token::Space,
             _ => Token::Text(token),
         }
     }
 }
 
+// operators longer than one character, longest first so that e.g. "&>>" wins over "&>".
+const OPERATORS: [&str; 14] = ["&>>", "<<<", "<<-", ";;&", "&&", "||", ">>", "<<", "<>", ">&", "<&", "&>", ";;", ";&"];
+
+// words that begin or end a compound command, but only where a command name could go.
+const RESERVED: [&str; 16] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "for", "in", "do", "done", "case", "esac", "function", "{", "}",
+];
+
 /*
 The tokenizer that takes a string and then will break it into tokens.
 */
 #[derive(Debug)]
 pub struct Tokenizer<'src> {
+    // all of the input, of which "input" is what's left.
+    source: &'src str,
     input: &'src str,
+    // where to pick up after the next newline when here-document bodies have been read past it.
+    heredoc_rest: Option<&'src str>,
+    // the offset the most recently consumed token or character started at, for pointing out errors.
+    last_start: usize,
 }
 
 impl<'src> Tokenizer<'src> {
     pub fn new(input: &'src str) -> Tokenizer<'src> {
         Tokenizer {
-            input: input,
+            source: input,
+            input,
+            heredoc_rest: None,
+            last_start: 0,
         }
     }
 
+    pub fn source(&self) -> &'src str {
+        self.source
+    }
+
+    pub fn last_start(&self) -> usize {
+        self.last_start
+    }
+
+    // how far into the source the tokenizer is.
+    fn offset(&self) -> usize {
+        self.source.len() - self.input.len()
+    }
+
+    /**
+    * Skips spaces, tabs and escaped newlines, but not plain newlines since those end a command. A "#" where the
+    * next word would start begins a comment, which is skipped up to the end of its line as well.
+    */
     pub fn skip_whitespace(&mut self) {
-        self.input = self.input.trim_start();
+        loop {
+            let trimmed = self.input.trim_start_matches([' ', '\t']);
+            match trimmed.strip_prefix("\\\n") {
+                Some(rest) => self.input = rest,
+                None => {
+                    self.input = trimmed;
+                    break;
+                },
+            }
+        }
+
+        if self.input.starts_with('#') {
+            let end = self.input.find('\n').unwrap_or(self.input.len());
+            self.input = &self.input[end..];
+        }
+    }
+
+    /**
+    * Reads the body of a here-document. The body starts on the line after the one being tokenized, or after
+    * the previous here-document body if there are several on one line. Returns None if the input ends before
+    * the delimiter does.
+    */
+    pub fn read_heredoc(&mut self, delimiter: &str, strip_tabs: bool) -> Option<String> {
+        let mut rest = match self.heredoc_rest {
+            Some(rest) => rest,
+            None => {
+                let idx = self.input.find('\n')?;
+                &self.input[idx + 1..]
+            },
+        };
+
+        let mut body = String::new();
+        while !rest.is_empty() {
+            let (line, next) = match rest.find('\n') {
+                Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+                None => (rest, ""),
+            };
+            let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+
+            if line == delimiter {
+                self.heredoc_rest = Some(next);
+                return Some(body);
+            }
+
+            body.push_str(line);
+            body.push('\n');
+            rest = next;
+        }
+
+        None
     }
 
     pub fn is_empty(&self) -> bool {
       self.input.is_empty()
     }
     
-    pub fn peek_next(&mut self) -> Option<Token> {
+    pub fn peek_next(&mut self) -> Option<Token<'src>> {
       self.parse_next_token(false)
     }
     
-    pub fn next_token(&mut self) -> Option<Token> {
+    pub fn next_token(&mut self) -> Option<Token<'src>> {
       self.parse_next_token(true)
     }
 
+    /**
+    * Raw character access for contexts where the normal token rules don't apply, like the inside
+    * of quotes or the name after a "$".
+    */
+    pub fn peek_char(&self) -> Option<char> {
+      self.input.chars().next()
+    }
+
+    /**
+    * The reserved word the input starts with, if any. Only a whole unquoted word counts, so "done" is
+    * reserved but "done.txt", "\done" and "do"ne" are not. Whether it's treated as one is up to the parser.
+    */
+    pub fn peek_reserved(&mut self) -> Option<&'src str> {
+      match self.peek_next() {
+        Some(Token::Text(text)) if RESERVED.contains(&text) => {
+          // a quote or expansion straight after the text makes it part of a longer word.
+          match self.input[text.len()..].chars().next() {
+            Some('"' | '\'' | '$' | '`' | '\\') => None,
+            _ => Some(text),
+          }
+        },
+        _ => None,
+      }
+    }
+
+    // everything that hasn't been consumed yet, for lookahead that single characters can't do.
+    pub fn rest(&self) -> &'src str {
+      self.input
+    }
+
+    pub fn next_char(&mut self) -> Option<char> {
+      let c = self.input.chars().next()?;
+      self.last_start = self.offset();
+      self.input = &self.input[c.len_utf8()..];
+      Some(c)
+    }
+
+    // consumes characters while "pred" holds and returns them as a slice of the input.
+    pub fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> &'src str {
+      let end = self.input
+        .char_indices()
+        .find(|(_, c)| !pred(*c))
+        .map(|(idx, _)| idx)
+        .unwrap_or(self.input.len());
+      let (taken, rest) = self.input.split_at(end);
+      if !taken.is_empty() {
+        self.last_start = self.offset();
+      }
+      self.input = rest;
+      taken
+    }
+
     fn is_special_token(&self, c: char) -> bool {
-        match c {
-            '|' | '>' | '<' | '&' | '"' | '\'' | ' '  => true,
-            _ => false,
-        }
+        matches!(c, '|' | '>' | '<' | '&' | ';' | '(' | ')' | '"' | '\'' | '$' | '`' | '\\' | '\n' | ' ' | '\t')
     }
 
-    fn parse_next_token(&mut self, advance_stream: bool) -> Option<Token> {
+    fn parse_next_token(&mut self, advance_stream: bool) -> Option<Token<'src>> {
         if self.input.is_empty() {
             return None;
         }
@@ -98,13 +297,24 @@ impl<'src> Tokenizer<'src> {
         None
     }
 
-    fn special_token(&mut self, advance_stream: bool) -> Option<Token> {
+    fn special_token(&mut self, advance_stream: bool) -> Option<Token<'src>> {
         if let Some(c) = self.input.chars().next() {
-          let end = c.len_utf8();
+          let end = OPERATORS
+            .iter()
+            .find(|op| self.input.starts_with(*op))
+            .map(|op| op.len())
+            .unwrap_or(c.len_utf8());
           let toke = Token::new(&self.input[0..end]);
 
           if advance_stream {
+            self.last_start = self.offset();
             self.input = &self.input[end..];
+            // the lines after this one were here-document bodies, which have already been read.
+            if toke == Token::Newline {
+              if let Some(rest) = self.heredoc_rest.take() {
+                self.input = rest;
+              }
+            }
           }
 
           return Some(toke);
@@ -113,7 +323,7 @@ impl<'src> Tokenizer<'src> {
         None
     }
 
-    fn text_token(&mut self, advance_stream: bool) -> Option<Token> {
+    fn text_token(&mut self, advance_stream: bool) -> Option<Token<'src>> {
       let mut end = 0;
       for (idx, c) in self.input.char_indices() {
         if self.is_special_token(c) { 
@@ -125,8 +335,16 @@ impl<'src> Tokenizer<'src> {
       }
 
       if end > 0 {
-        let toke = Token::new(&self.input[0..end]);
+        let text = &self.input[0..end];
+        let rest = &self.input[end..];
+        // a run of digits directly in front of "<" or ">" names the fd being redirected.
+        let toke = if text.bytes().all(|b| b.is_ascii_digit()) && (rest.starts_with('<') || rest.starts_with('>')) {
+          Token::IoNumber(text)
+        } else {
+          Token::new(text)
+        };
         if advance_stream {
+          self.last_start = self.offset();
           self.input = &self.input[end..];
         };
         return Some(toke);
@@ -260,4 +478,185 @@ mod unit {
         assert_eq!(tokenizer.next_token(), Some(Token::Text(".txt")));
         assert_eq!(tokenizer.next_token(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tokenizer_lists() {
+        let input = "make && ./run||echo failed;ls & pwd";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("make")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::And));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("./run")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Or));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("echo")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("failed")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Semicolon));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("ls")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Background));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("pwd")));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_dollar() {
+        let input = "echo $?x \\$";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("echo")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Dollar));
+        assert_eq!(tokenizer.next_char(), Some('?'));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("x")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Backslash));
+        assert_eq!(tokenizer.next_token(), Some(Token::Dollar));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_take_while() {
+        let input = "it's quoted' rest";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.take_while(|c| c != '\''), "it");
+        assert_eq!(tokenizer.next_token(), Some(Token::SingleQuote));
+        assert_eq!(tokenizer.take_while(|c| c != '\''), "s quoted");
+        assert_eq!(tokenizer.next_token(), Some(Token::SingleQuote));
+        assert_eq!(tokenizer.take_while(|c| c != 'x'), " rest");
+        assert!(tokenizer.is_empty());
+    }
+
+    #[test]
+    fn test_tokenizer_redirects() {
+        let input = "cmd 2>>err 3<>f 2>&1 4<&- &>all 5 >x";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("cmd")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::IoNumber("2")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Append));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("err")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::IoNumber("3")));
+        assert_eq!(tokenizer.next_token(), Some(Token::ReadWrite));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("f")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::IoNumber("2")));
+        assert_eq!(tokenizer.next_token(), Some(Token::DupOutput));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("1")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::IoNumber("4")));
+        assert_eq!(tokenizer.next_token(), Some(Token::DupInput));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("-")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::RedirectAll));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("all")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        // not touching the operator, so just an argument.
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("5")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::RedirectOutput));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("x")));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_whitespace() {
+        let input = "a\tb \\\n c\nd";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("a")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("b")));
+        tokenizer.skip_whitespace();
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("c")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Newline));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("d")));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_comments() {
+        let input = "#!/bin/rsh\na#b # c\n";
+        let mut tokenizer = Tokenizer::new(input);
+        tokenizer.skip_whitespace();
+        assert_eq!(tokenizer.next_token(), Some(Token::Newline));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("a#b")));
+        tokenizer.skip_whitespace();
+        assert_eq!(tokenizer.next_token(), Some(Token::Newline));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_heredoc() {
+        let input = "cat <<A <<-B; echo\none\nA\n\ttwo\n\tB\nls";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("cat")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::HereDoc));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("A")));
+        assert_eq!(tokenizer.read_heredoc("A", false), Some("one\n".to_string()));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::HereDocStrip));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("B")));
+        assert_eq!(tokenizer.read_heredoc("B", true), Some("two\n".to_string()));
+        assert_eq!(tokenizer.next_token(), Some(Token::Semicolon));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("echo")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Newline));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("ls")));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_heredoc_unterminated() {
+        let input = "cat <<EOF\nhello";
+        let mut tokenizer = Tokenizer::new(input);
+        tokenizer.next_token();
+        tokenizer.next_token();
+        tokenizer.next_token();
+        tokenizer.next_token();
+        assert_eq!(tokenizer.read_heredoc("EOF", false), None);
+    }
+
+    #[test]
+    fn test_tokenizer_substitution() {
+        let input = "echo $(pwd)`date`";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("echo")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Space));
+        assert_eq!(tokenizer.next_token(), Some(Token::Dollar));
+        assert_eq!(tokenizer.next_token(), Some(Token::LeftParen));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("pwd")));
+        assert_eq!(tokenizer.next_token(), Some(Token::RightParen));
+        assert_eq!(tokenizer.next_token(), Some(Token::Backtick));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("date")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Backtick));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_case_operators() {
+        let input = "a;;b;&c;;&d;e";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("a")));
+        assert_eq!(tokenizer.next_token(), Some(Token::CaseBreak));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("b")));
+        assert_eq!(tokenizer.next_token(), Some(Token::CaseFallthrough));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("c")));
+        assert_eq!(tokenizer.next_token(), Some(Token::CaseContinue));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("d")));
+        assert_eq!(tokenizer.next_token(), Some(Token::Semicolon));
+        assert_eq!(tokenizer.next_token(), Some(Token::Text("e")));
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_reserved() {
+        assert_eq!(Tokenizer::new("done; x").peek_reserved(), Some("done"));
+        assert_eq!(Tokenizer::new("if").peek_reserved(), Some("if"));
+        assert_eq!(Tokenizer::new("done.txt").peek_reserved(), None);
+        assert_eq!(Tokenizer::new("do\"ne\"").peek_reserved(), None);
+        assert_eq!(Tokenizer::new("echo").peek_reserved(), None);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::signals;
+use std::collections::BTreeMap;
+use std::fmt::{ self, Display, Formatter };
+
+/**
+* What a trap can be set on: a signal, or one of the pseudo-signals for points in the shell's own life. EXIT runs
+* when the shell exits, ERR after a command fails, DEBUG before every command and RETURN when a function returns.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    Exit,
+    Signal(i32),
+    Debug,
+    Err,
+    Return,
+}
+
+impl Trap {
+    // a trap by the name trap takes, "EXIT" (or 0), a signal name or number, or one of the other pseudo-signals.
+    pub fn parse(name: &str) -> Option<Trap> {
+        match name.to_ascii_uppercase().as_str() {
+            "EXIT" | "0" => Some(Trap::Exit),
+            "DEBUG" => Some(Trap::Debug),
+            "ERR" => Some(Trap::Err),
+            "RETURN" => Some(Trap::Return),
+            _ => signals::number(name).map(Trap::Signal),
+        }
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Trap::Exit => write!(f, "EXIT"),
+            Trap::Signal(signal) => match signals::abbrev(*signal) {
+                Some(name) => write!(f, "SIG{}", name),
+                None => write!(f, "{}", signal),
+            },
+            Trap::Debug => write!(f, "DEBUG"),
+            Trap::Err => write!(f, "ERR"),
+            Trap::Return => write!(f, "RETURN"),
+        }
+    }
+}
+
+/**
+* The commands set with trap. An empty command means the signal is ignored. Setting and removing signal traps also
+* changes what the shell does when the signal arrives, the commands themselves are run by the engine.
+*/
+#[derive(Debug, Default)]
+pub struct Traps {
+    actions: BTreeMap<Trap, String>,
+    // set while a trap's command runs, so it doesn't set off ERR or DEBUG traps of its own.
+    pub running: bool,
+}
+
+impl Traps {
+    pub fn get(&self, trap: Trap) -> Option<&str> {
+        self.actions.get(&trap).map(|action| action.as_str())
+    }
+
+    pub fn set(&mut self, trap: Trap, action: &str) {
+        if let Trap::Signal(signal) = trap {
+            if action.is_empty() {
+                signals::ignore(signal);
+            } else {
+                signals::trap(signal);
+            }
+        }
+        self.actions.insert(trap, action.to_string());
+    }
+
+    pub fn remove(&mut self, trap: Trap) {
+        if let Trap::Signal(signal) = trap {
+            signals::restore(signal);
+        }
+        self.actions.remove(&trap);
+    }
+
+    // every trap that's set, in the order trap lists them.
+    pub fn iter(&self) -> impl Iterator<Item = (Trap, &str)> {
+        self.actions.iter().map(|(trap, action)| (*trap, action.as_str()))
+    }
+
+    /**
+    * For a forked copy of the shell, which doesn't run the traps of the shell it came from. Ignored signals stay
+    * ignored, like they do for every other command the shell runs.
+    */
+    pub fn reset(&mut self) {
+        let set: Vec<Trap> = self.iter().filter(|(_, action)| !action.is_empty()).map(|(trap, _)| trap).collect();
+        for trap in set {
+            self.remove(trap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_traps_parse() {
+        assert_eq!(Trap::parse("exit"), Some(Trap::Exit));
+        assert_eq!(Trap::parse("0"), Some(Trap::Exit));
+        assert_eq!(Trap::parse("SIGINT"), Some(Trap::Signal(libc::SIGINT)));
+        assert_eq!(Trap::parse("15"), Some(Trap::Signal(libc::SIGTERM)));
+        assert_eq!(Trap::parse("ERR"), Some(Trap::Err));
+        assert_eq!(Trap::parse("BOGUS"), None);
+        assert_eq!(Trap::Signal(libc::SIGHUP).to_string(), "SIGHUP");
+    }
+
+    #[test]
+    fn test_traps_reset() {
+        let mut traps = Traps::default();
+        traps.set(Trap::Exit, "echo bye");
+        traps.set(Trap::Debug, "");
+        traps.reset();
+        assert_eq!(traps.iter().collect::<Vec<_>>(), vec![(Trap::Debug, "")]);
+    }
+}
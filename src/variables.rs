@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub value: String,
+    // exported variables are passed on to the environment of every command the shell runs.
+    pub exported: bool,
+}
+
+/**
+* The shell's variables. This is separate from the process environment: the environment is imported once
+* at startup, and from then on commands get an environment built from whichever variables are exported.
+*/
+#[derive(Debug, Default)]
+pub struct Variables {
+    vars: HashMap<String, Variable>,
+    /**
+    * One scope per function call running, holding what each of its local variables was before it was made
+    * local. Scoping is dynamic like in other shells, so a function sees the locals of the ones that called it.
+    */
+    scopes: Vec<HashMap<String, Option<Variable>>>,
+}
+
+impl Variables {
+    pub fn new() -> Variables {
+        Variables::default()
+    }
+
+    // a store seeded with the environment rsh was started with, all marked as exported.
+    pub fn from_env() -> Variables {
+        let mut vars = Variables::new();
+        for (name, value) in std::env::vars() {
+            vars.vars.insert(name, Variable { value, exported: true });
+        }
+        vars
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    // sets the value, keeping the variable exported if it already was.
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value.to_string(),
+            None => {
+                self.vars.insert(name.to_string(), Variable { value: value.to_string(), exported: false });
+            },
+        }
+    }
+
+    // marks a variable as exported, creating it empty if needed like "export NAME" does.
+    pub fn export(&mut self, name: &str) {
+        self.vars
+            .entry(name.to_string())
+            .or_insert_with(|| Variable { value: String::new(), exported: false })
+            .exported = true;
+    }
+
+    // puts back a variable exactly as it was, e.g. one saved with get_var before a temporary assignment.
+    pub fn insert(&mut self, name: &str, var: Variable) {
+        self.vars.insert(name.to_string(), var);
+    }
+
+    pub fn unset(&mut self, name: &str) -> Option<Variable> {
+        self.vars.remove(name)
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    // ends the innermost scope, putting every variable made local in it back the way it was.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, var) in scope {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /**
+    * Makes a variable local to the innermost scope, where it starts out unset. Returns false if there's no scope,
+    * i.e. no function is running.
+    */
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let Some(scope) = self.scopes.last_mut() else {
+            return false;
+        };
+        // a variable that's already local to this scope keeps its value.
+        if !scope.contains_key(name) {
+            scope.insert(name.to_string(), self.vars.remove(name));
+        }
+        true
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.vars.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    // the environment handed to child processes.
+    pub fn exported(&self) -> Vec<(&str, &str)> {
+        let mut env: Vec<(&str, &str)> = self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.as_str(), var.value.as_str()))
+            .collect();
+        env.sort();
+        env
+    }
+}
+
+// a valid variable name is a letter or underscore followed by letters, digits or underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_variables_set_keeps_export() {
+        let mut vars = Variables::new();
+        vars.set("A", "1");
+        vars.export("B");
+        vars.set("B", "2");
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.exported(), vec![("B", "2")]);
+
+        vars.export("A");
+        assert_eq!(vars.exported(), vec![("A", "1"), ("B", "2")]);
+
+        vars.unset("A");
+        assert_eq!(vars.get("A"), None);
+        assert_eq!(vars.exported(), vec![("B", "2")]);
+    }
+
+    #[test]
+    fn test_variables_scopes() {
+        let mut vars = Variables::new();
+        vars.set("a", "global");
+        assert!(!vars.make_local("a"));
+
+        vars.push_scope();
+        assert!(vars.make_local("a"));
+        assert_eq!(vars.get("a"), None);
+        vars.set("a", "outer");
+        vars.push_scope();
+        vars.make_local("a");
+        vars.make_local("b");
+        vars.set("a", "inner");
+        vars.set("b", "inner");
+        vars.make_local("a");
+        assert_eq!(vars.get("a"), Some("inner"));
+        vars.pop_scope();
+        assert_eq!(vars.get("a"), Some("outer"));
+        assert_eq!(vars.get("b"), None);
+        vars.pop_scope();
+        assert_eq!(vars.get("a"), Some("global"));
+        vars.pop_scope();
+        assert_eq!(vars.get("a"), Some("global"));
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("PATH"));
+        assert!(is_valid_name("_x1"));
+        assert!(!is_valid_name("1x"));
+        assert!(!is_valid_name("a-b"));
+        assert!(!is_valid_name(""));
+    }
+}